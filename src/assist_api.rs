@@ -0,0 +1,348 @@
+//! `POST /api/assist` on localhost, so editors and other tools can use the
+//! models and templates configured in PlugOvr.
+//!
+//! Requests need the header `Authorization: Bearer <token>` with the
+//! `api_token` of the settings, which is created on the first start. With
+//! `"stream": true` (the default) the answer is sent as server-sent events:
+//! `token` events carry the new text, a final `done` event the whole answer
+//! and an `error` event replaces it if the request fails.
+
+use crate::llm::LLMSelector;
+use crate::llm::backend::{FinishReason, LlmRequest};
+use crate::llm::error::LlmError;
+use crate::settings;
+use crate::templates::{self, Variables};
+use crate::ui::template_editor::TemplateMap;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::post,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rand::{Rng, distributions::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const DEFAULT_API_PORT: u16 = 3001;
+/// How often the answer is checked for new text while streaming.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone)]
+struct AssistState {
+    llm_selector: Arc<Mutex<LLMSelector>>,
+    prompt_templates: TemplateMap,
+    token: String,
+    /// Requests are answered one after the other, like in the assistance window.
+    busy: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Deserialize)]
+struct AssistRequest {
+    /// The text to work on, like the selection in the GUI.
+    #[serde(default)]
+    text: String,
+    /// Template name, e.g. `@correct` or `@translate(german)`.
+    template: Option<String>,
+    /// Used when no template is given.
+    instruction: Option<String>,
+    /// Overrides the model of the template, see `plugovr models`.
+    model: Option<String>,
+    /// Base64 encoded PNG or JPEG images, with or without `data:` prefix.
+    #[serde(default)]
+    images: Vec<String>,
+    #[serde(default = "default_stream")]
+    stream: bool,
+}
+
+fn default_stream() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+struct AssistAnswer {
+    answer: String,
+    finish_reason: &'static str,
+    /// Notes of the knowledge folder that were added to the prompt.
+    sources: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ApiError {
+    kind: &'static str,
+    message: String,
+}
+
+fn api_error(status: StatusCode, kind: &'static str, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(ApiError {
+            kind,
+            message: message.into(),
+        }),
+    )
+        .into_response()
+}
+
+fn llm_error_response(error: &LlmError) -> Response {
+    let status = match error {
+        LlmError::ContextTooLong(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        LlmError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        LlmError::NotAllowed(_) => StatusCode::FORBIDDEN,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    api_error(status, error.kind(), error.to_string())
+}
+
+fn finish_reason_name(finish_reason: Option<FinishReason>) -> &'static str {
+    match finish_reason {
+        Some(FinishReason::MaxTokens) => "max_tokens",
+        Some(FinishReason::Cancelled) => "cancelled",
+        Some(FinishReason::Stop) | None => "stop",
+    }
+}
+
+/// Loads the token clients have to send, a new one is created on first use.
+fn load_or_create_api_token() -> std::io::Result<String> {
+    if let Some(token) = settings::load().api_token {
+        return Ok(token);
+    }
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    settings::update(|settings| settings.api_token = Some(token.clone()))?;
+    Ok(token)
+}
+
+pub async fn start_server(
+    llm_selector: Arc<Mutex<LLMSelector>>,
+    prompt_templates: TemplateMap,
+    port: u16,
+) {
+    let token = match load_or_create_api_token() {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Failed to create the API token, the API is disabled: {}", e);
+            return;
+        }
+    };
+    let state = AssistState {
+        llm_selector,
+        prompt_templates,
+        token,
+        busy: Arc::new(tokio::sync::Mutex::new(())),
+    };
+    let app = Router::new()
+        .route("/api/assist", post(assist_handler))
+        .with_state(state);
+
+    // Only reachable from this machine.
+    let listener = match tokio::net::TcpListener::bind(("127.0.0.1", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to start the assistant API on port {}: {}", port, e);
+            return;
+        }
+    };
+    println!(
+        "Assistant API on http://127.0.0.1:{}/api/assist, api_token in {}",
+        port,
+        settings::config_path("settings.json").display()
+    );
+    if let Err(e) = axum::serve(listener, app).await {
+        eprintln!("Assistant API stopped: {}", e);
+    }
+}
+
+fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|bearer| constant_time_eq(bearer.trim().as_bytes(), token.as_bytes()))
+}
+
+/// Compares without stopping at the first difference, so the response time
+/// doesn't tell how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Resolves the template like the assistance window does: its instruction,
+/// its model and its generation options.
+fn build_request(
+    state: &AssistState,
+    body: &AssistRequest,
+) -> Result<(LlmRequest, Option<crate::llm::LLMType>), String> {
+    let llm_selector = state.llm_selector.lock().unwrap();
+    let variables = Variables {
+        selection: &body.text,
+        active_app: None,
+    };
+    let (instruction, mut llm_type, options, template_key) = match &body.template {
+        Some(template) => {
+            let prompt_templates = state.prompt_templates.lock().unwrap();
+            let invocation = templates::find_template(template, &prompt_templates)
+                .unwrap_or_else(|| Err(format!("Unknown template {}", template)))?;
+            let prompt_template = &prompt_templates[&invocation.key];
+            (
+                templates::expand(
+                    &invocation.call,
+                    &invocation,
+                    &prompt_template.instruction,
+                    &variables,
+                ),
+                prompt_template.llm_type.clone(),
+                prompt_template.options.clone(),
+                Some(invocation.key),
+            )
+        }
+        None => (
+            body.instruction
+                .as_deref()
+                .filter(|instruction| !instruction.trim().is_empty())
+                .map(|instruction| templates::fill_variables(instruction, &variables))
+                .ok_or("Either template or instruction is required")?,
+            None,
+            Default::default(),
+            None,
+        ),
+    };
+    if let Some(model) = &body.model {
+        llm_type = Some(
+            llm_selector
+                .find_llm_type(model)
+                .ok_or_else(|| format!("Unknown model {}", model))?,
+        );
+    }
+    let mut screenshots = vec![];
+    for image in &body.images {
+        let data = image.rsplit(',').next().unwrap_or(image);
+        let bytes = STANDARD
+            .decode(data.trim())
+            .map_err(|e| format!("Invalid base64 image: {}", e))?;
+        let image = image_24::load_from_memory(&bytes)
+            .map_err(|e| format!("Invalid image: {}", e))?
+            .to_rgba8();
+        screenshots.push((image, egui::Pos2::ZERO));
+    }
+    let request = LlmRequest {
+        context: body.text.clone(),
+        instruction,
+        screenshots,
+        history: vec![],
+        options,
+        answer_prefix: String::new(),
+        bypass_cache: false,
+        template: template_key,
+        knowledge: vec![],
+    };
+    Ok((request, llm_type))
+}
+
+async fn assist_handler(
+    State(state): State<AssistState>,
+    headers: HeaderMap,
+    Json(body): Json<AssistRequest>,
+) -> Response {
+    if !authorized(&headers, &state.token) {
+        return api_error(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Missing or wrong bearer token",
+        );
+    }
+    let (request, llm_type) = match build_request(&state, &body) {
+        Ok(request) => request,
+        Err(e) => return api_error(StatusCode::BAD_REQUEST, "bad_request", e),
+    };
+    let busy = state.busy.clone().lock_owned().await;
+    let ai_answer = Arc::new(Mutex::new(String::new()));
+    let finish_reason = Arc::new(Mutex::new(None));
+    let llm_error = Arc::new(Mutex::new(None));
+    let generation = match state.llm_selector.lock().unwrap().process_input(
+        request,
+        ai_answer.clone(),
+        finish_reason.clone(),
+        llm_error.clone(),
+        Arc::new(Mutex::new(false)),
+        llm_type,
+    ) {
+        Ok(generation) => generation,
+        Err(e) => return llm_error_response(&e),
+    };
+    let sources = generation.knowledge_sources();
+
+    if !body.stream {
+        let _ = generation.finished().await;
+        if let Some(error) = llm_error.lock().unwrap().take() {
+            return llm_error_response(&error);
+        }
+        let answer = AssistAnswer {
+            answer: ai_answer.lock().unwrap().clone(),
+            finish_reason: finish_reason_name(*finish_reason.lock().unwrap()),
+            sources,
+        };
+        return Json(answer).into_response();
+    }
+
+    let (tx, rx) = futures::channel::mpsc::unbounded::<Result<Event, Infallible>>();
+    tokio::spawn(async move {
+        let _busy = busy;
+        let mut sent = String::new();
+        loop {
+            let finished = generation.is_finished();
+            let answer = ai_answer.lock().unwrap().clone();
+            // Validated JSON answers are rewritten at the end, `done` has them.
+            if answer.len() > sent.len() && answer.starts_with(sent.as_str()) {
+                let delta = serde_json::to_string(&answer[sent.len()..]).unwrap_or_default();
+                if tx
+                    .unbounded_send(Ok(Event::default().event("token").data(delta)))
+                    .is_err()
+                {
+                    // The client went away.
+                    generation.cancel();
+                    return;
+                }
+                sent = answer.clone();
+            }
+            if finished {
+                let event = match llm_error.lock().unwrap().take() {
+                    Some(error) => Event::default().event("error").data(
+                        serde_json::to_string(&ApiError {
+                            kind: error.kind(),
+                            message: error.to_string(),
+                        })
+                        .unwrap_or_default(),
+                    ),
+                    None => Event::default().event("done").data(
+                        serde_json::to_string(&AssistAnswer {
+                            answer,
+                            finish_reason: finish_reason_name(*finish_reason.lock().unwrap()),
+                            sources,
+                        })
+                        .unwrap_or_default(),
+                    ),
+                };
+                let _ = tx.unbounded_send(Ok(event));
+                return;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+    Sse::new(rx)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
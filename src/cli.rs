@@ -0,0 +1,231 @@
+//! Headless commands that run templates without starting the overlay, so
+//! scripts and editors use the same prompts and models as the GUI.
+//!
+//! The Windows build uses the windows subsystem and has no console of its own,
+//! redirect the output there, e.g. `plugovr ask --template @correct < in.txt > out.txt`.
+
+use crate::llm::LLMSelector;
+use crate::llm::backend::{FinishReason, LlmRequest};
+use crate::templates::{Invocation, Templates, Variables, expand};
+use crate::ui::template_editor::load_active_templates;
+use clap::Subcommand;
+use plugovr_types::UserInfo;
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a template on the text from stdin and print the answer
+    Ask {
+        /// Template name, e.g. @correct
+        #[arg(long)]
+        template: String,
+        /// Model to use instead of the one of the template or the GUI
+        #[arg(long)]
+        model: Option<String>,
+    },
+    /// Run a template on every file of a directory
+    Batch {
+        /// Template name, e.g. @summarize
+        #[arg(long)]
+        template: String,
+        /// Model to use instead of the one of the template or the GUI
+        #[arg(long)]
+        model: Option<String>,
+        /// Directory with the text files
+        input: PathBuf,
+        /// Directory for the answers, one file per input file
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// List the models that can be passed to --model
+    Models,
+    /// Serve the assistant API on localhost without the overlay
+    Serve {
+        #[arg(long, default_value_t = crate::assist_api::DEFAULT_API_PORT)]
+        port: u16,
+    },
+}
+
+pub async fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    let mut llm_selector = LLMSelector::new(load_user_info());
+    llm_selector.refresh_models().await;
+    let templates = load_active_templates();
+
+    match command {
+        Command::Ask { template, model } => {
+            select_model(&mut llm_selector, &templates, &template, model.as_deref()).await?;
+            let mut context = String::new();
+            std::io::stdin().read_to_string(&mut context)?;
+            let answer = ask(&llm_selector, &templates, &template, context).await?;
+            println!("{}", answer);
+        }
+        Command::Batch {
+            template,
+            model,
+            input,
+            out,
+        } => {
+            select_model(&mut llm_selector, &templates, &template, model.as_deref()).await?;
+            batch(&llm_selector, &templates, &template, &input, &out).await?;
+        }
+        Command::Models => {
+            for llm_type in llm_selector.available_llm_types() {
+                println!("{}\t{}", llm_type, llm_type.description());
+            }
+        }
+        Command::Serve { port } => {
+            llm_selector.load_model().await;
+            crate::assist_api::start_server(
+                Arc::new(Mutex::new(llm_selector)),
+                Arc::new(Mutex::new(templates)),
+                port,
+            )
+            .await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "cs")]
+fn load_user_info() -> Arc<Mutex<Option<UserInfo>>> {
+    let user_info = plugovr_cs::user_management::get_user()
+        .map_err(|e| eprintln!("Not logged in, cloud models are not available: {}", e))
+        .ok();
+    Arc::new(Mutex::new(user_info))
+}
+
+#[cfg(not(feature = "cs"))]
+fn load_user_info() -> Arc<Mutex<Option<UserInfo>>> {
+    Arc::new(Mutex::new(None))
+}
+
+/// The template `--template` calls, with its arguments, e.g. `@translate(german)`.
+fn find_template(templates: &Templates, template: &str) -> Result<Invocation, String> {
+    crate::templates::find_template(template, templates)
+        .unwrap_or_else(|| Err(format!("Unknown template {}", template)))
+}
+
+/// `--model` wins over the model of the template, which wins over the model
+/// selected in the GUI. Local models are loaded here, before the first request.
+async fn select_model(
+    llm_selector: &mut LLMSelector,
+    templates: &Templates,
+    template: &str,
+    model: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let invocation = find_template(templates, template)?;
+    let llm_from_template = &templates[&invocation.key].llm_type;
+    let llm_type = match model {
+        Some(model) => llm_selector.find_llm_type(model).ok_or_else(|| {
+            format!(
+                "Unknown model {}, `plugovr models` lists the available ones",
+                model
+            )
+        })?,
+        None => llm_from_template
+            .clone()
+            .unwrap_or_else(|| llm_selector.get_llm_type()),
+    };
+    llm_selector.set_llm_type(llm_type);
+    llm_selector.load_model().await;
+    Ok(())
+}
+
+async fn ask(
+    llm_selector: &LLMSelector,
+    templates: &Templates,
+    template: &str,
+    context: String,
+) -> Result<String, Box<dyn Error>> {
+    let invocation = find_template(templates, template)?;
+    let variables = Variables {
+        selection: &context,
+        active_app: None,
+    };
+    let prompt_template = &templates[&invocation.key];
+    let instruction = expand(
+        &invocation.call,
+        &invocation,
+        &prompt_template.instruction,
+        &variables,
+    );
+    let request = LlmRequest {
+        context,
+        instruction,
+        screenshots: vec![],
+        history: vec![],
+        options: prompt_template.options.clone(),
+        answer_prefix: String::new(),
+        bypass_cache: false,
+        template: Some(invocation.key),
+        knowledge: vec![],
+    };
+    let ai_answer = Arc::new(Mutex::new(String::new()));
+    let finish_reason = Arc::new(Mutex::new(None));
+    let llm_error = Arc::new(Mutex::new(None));
+    let spinner = Arc::new(Mutex::new(false));
+    let generation = llm_selector.process_input(
+        request,
+        ai_answer.clone(),
+        finish_reason.clone(),
+        llm_error.clone(),
+        spinner,
+        None,
+    )?;
+    let sources = generation.knowledge_sources();
+    generation.finished().await?;
+    if let Some(error) = llm_error.lock().unwrap().take() {
+        return Err(Box::new(error));
+    }
+    if *finish_reason.lock().unwrap() == Some(FinishReason::MaxTokens) {
+        eprintln!("Warning: Max tokens reached. The answer may be incomplete.");
+    }
+    if !sources.is_empty() {
+        eprintln!("Sources: {}", sources.join(", "));
+    }
+    let answer = ai_answer.lock().unwrap().clone();
+    Ok(answer)
+}
+
+/// Answers every text file of `input` and writes the answer under the same
+/// name to `out`. Files that fail are reported and skipped.
+async fn batch(
+    llm_selector: &LLMSelector,
+    templates: &Templates,
+    template: &str,
+    input: &Path,
+    out: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut files = std::fs::read_dir(input)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    files.sort();
+    std::fs::create_dir_all(out)?;
+    let mut failed = 0;
+    for (index, path) in files.iter().enumerate() {
+        let Some(file_name) = path.file_name() else {
+            continue;
+        };
+        eprintln!("[{}/{}] {}", index + 1, files.len(), path.display());
+        let result = match std::fs::read_to_string(path) {
+            Ok(context) => ask(llm_selector, templates, template, context).await,
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(answer) => std::fs::write(out.join(file_name), answer)?,
+            Err(e) => {
+                eprintln!("Failed to process {}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} files failed", failed, files.len()).into());
+    }
+    Ok(())
+}
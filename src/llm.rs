@@ -1,146 +1,65 @@
 // Add these imports at the top of the file
+pub mod backend;
+mod cache;
+mod catalog;
+pub mod chunking;
+mod cloud;
+pub mod downloads;
+pub mod error;
+mod fallback;
+pub mod knowledge;
+mod local;
+pub mod metrics;
+#[cfg(test)]
+mod mock;
+mod ollama;
+mod openai_compatible;
+pub mod options;
+pub mod prompt;
+pub mod structured;
+
+use crate::llm::backend::{DEFAULT_CONTEXT_WINDOW, FinishReason, LlmBackend, LlmRequest, generate};
+pub use crate::llm::cache::CacheSettings;
+use crate::llm::cache::ResponseCache;
+pub use crate::llm::catalog::{CustomModel, LocalModel, Quantization};
+use crate::llm::chunking::{ChunkProgress, ChunkedBackend};
+use crate::llm::cloud::CloudBackend;
+use crate::llm::downloads::{DownloadManager, kalosm_cache_dir};
+use crate::llm::error::LlmError;
+pub use crate::llm::fallback::DEFAULT_FALLBACK_TIMEOUT_SECS;
+use crate::llm::fallback::FallbackBackend;
+use crate::llm::knowledge::{self, KnowledgeBase, Snippet};
+use crate::llm::local::LocalBackend;
+use crate::llm::metrics::{MeteredBackend, save_metrics};
+pub use crate::llm::ollama::{DEFAULT_OLLAMA_URL, OllamaModelInfo};
+use crate::llm::ollama::{OllamaBackend, list_ollama_models, ollama_from_url, pull_ollama_model};
+use crate::llm::openai_compatible::OpenAiCompatibleBackend;
+pub use crate::llm::openai_compatible::json_schema_response_format;
+use crate::llm::options::GenerationConfig;
+use crate::settings::{self, Profile};
 use kalosm::language::*;
-#[cfg(feature = "cs")]
-use plugovr_cs::cloud_llm::call_aws_lambda;
-use plugovr_types::{Screenshots, UserInfo};
-use std::error::Error;
-use std::io::Write;
+use plugovr_types::UserInfo;
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
 
-#[cfg(feature = "cs")]
-use plugovr_cs::user_management::get_user;
-
-use egui::{Context, Window};
-
-use image_24::{ImageBuffer, Rgba};
-use ollama_rs::{
-    Ollama,
-    generation::chat::{ChatMessage, MessageRole, request::ChatMessageRequest},
-    generation::images::Image,
-    generation::options::GenerationOptions,
-};
+use ollama_rs::Ollama;
 use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::fs::File;
-use std::io::Read;
 use strum::EnumIter;
 
-async fn call_ollama(
-    ollama: Arc<Mutex<Option<Ollama>>>,
-    model: String,
-    input: String,
-    _context: String,
-    _instruction: String,
-    ai_answer: Arc<Mutex<String>>,
-    screenshots: &Screenshots,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-    use base64::{Engine as _, engine::general_purpose};
-
-    let screenshot_base64 = screenshots
-        .iter()
-        .map(|img| {
-            let mut buf = vec![];
-            img.0
-                .write_to(
-                    &mut std::io::Cursor::new(&mut buf),
-                    image_24::ImageOutputFormat::Png,
-                )
-                .unwrap();
-            general_purpose::STANDARD.encode(&buf)
-        })
-        .collect::<Vec<String>>();
-    let images = screenshot_base64
-        .iter()
-        .map(|base64| Image::from_base64(base64))
-        .collect::<Vec<Image>>();
-
-    let options = GenerationOptions::default()
-        .temperature(0.2)
-        .repeat_penalty(1.1)
-        .top_k(40)
-        .top_p(0.5)
-        .num_predict(500);
-
-    // let mut stream = ollama
-    //     .as_ref()
-    //     .lock()
-    //     .unwrap()
-    //     .as_ref()
-    //     .unwrap()
-    //     .generate_stream(
-    //         GenerationRequest::new(model, input)
-    //             .options(options)
-    //             .images(images),
-    //     )
-    //     .await
-    //     .unwrap();
-    let message = ChatMessage::new(MessageRole::User, input.to_string());
-    let messages = vec![message.clone().with_images(images)];
-    let ollama_instance = {
-        let guard = ollama.lock().unwrap();
-        guard.as_ref().unwrap().clone()
-    };
-
-    let stream = ollama_instance
-        .send_chat_messages_stream(ChatMessageRequest::new(model, messages).options(options))
-        .await;
-    let mut response = String::new();
-    match stream {
-        Err(e) => {
-            *ai_answer.lock().unwrap() = format!("Error: {}", e);
-            response = format!("Error: {}", e);
-            Ok(response)
-        }
-        Ok(mut stream) => {
-            while let Some(Ok(res)) = stream.next().await {
-                let assistant_message = res.message;
-                response += assistant_message.content.as_str();
-                *ai_answer.lock().unwrap() = response.clone();
-            }
-            Ok(response)
-        }
-    }
-}
-async fn call_local_llm(
-    _input: String,
-    context: String,
-    instruction: String,
-    ai_answer: Arc<Mutex<String>>,
-    model: Arc<Mutex<Option<Llama>>>,
-) -> Result<String, Box<dyn Error + Send + Sync>> {
-    let model = model.try_lock();
-    if model.is_err() {
-        eprintln!("Model is not locked");
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Model is not locked",
-        )));
-    }
-
-    let prompt = format!("Context: {} Instruction: {}", context, instruction);
-
-    let model_instance = {
-        let mut guard = model.unwrap();
-        guard.as_mut().unwrap().clone()
-    };
-    let mut stream = model_instance(&prompt);
-
-    let mut response = String::new();
-
-    while let Some(token) = stream.next().await {
-        response.push_str(&token);
-
-        // Update ai_answer with the current response
-        *ai_answer.lock().unwrap() = response.clone();
-    }
-    Ok(response)
-}
-
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum LLMType {
     Cloud(CloudModel),
     Local(LocalModel),
     Ollama(String),
+    /// The API key is kept in the settings by base URL, not in the model, so
+    /// templates, profiles and packs never contain it.
+    OpenAiCompatible {
+        base_url: String,
+        model: String,
+    },
+    CustomGguf(CustomModel),
 }
 use strum::IntoEnumIterator;
 
@@ -158,377 +77,854 @@ impl CloudModel {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, EnumIter, Serialize, Deserialize)]
-pub enum LocalModel {
-    Llama32S1bChat,
-    Llama32S3bChat,
-}
-impl LocalModel {
-    pub fn description(&self) -> String {
-        match self {
-            LocalModel::Llama32S1bChat => "Llama 3.2 1B Chat".to_string(),
-            LocalModel::Llama32S3bChat => "Llama 3.2 3B Chat".to_string(),
-        }
-    }
-}
 impl LLMType {
     pub fn description(&self) -> String {
         match self {
             LLMType::Cloud(cloud_model) => format!("{} - Cloud", cloud_model.description()),
             LLMType::Local(local_model) => format!("{} - Local", local_model.description()),
             LLMType::Ollama(model) => format!("Ollama - {}", model),
+            LLMType::OpenAiCompatible { base_url, model } => format!("{} - {}", model, base_url),
+            LLMType::CustomGguf(custom_model) => format!("{} - Custom GGUF", custom_model.name),
         }
     }
-}
-impl LocalModel {
-    pub fn source(&self) -> LlamaSource {
+
+    /// Where the text goes if it leaves this machine, None for local models
+    /// and the Ollama server of the settings.
+    pub fn remote_endpoint(&self) -> Option<String> {
         match self {
-            LocalModel::Llama32S1bChat => LlamaSource::llama_3_2_1b_chat(),
-            LocalModel::Llama32S3bChat => LlamaSource::llama_3_2_3b_chat(),
+            LLMType::Cloud(_) => Some("PlugOvr cloud".to_string()),
+            LLMType::OpenAiCompatible { base_url, .. } if !is_loopback_url(base_url) => {
+                Some(base_url.clone())
+            }
+            _ => None,
+        }
+    }
+}
+const OLLAMA_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+const KNOWLEDGE_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// A request started by `LLMSelector::process_input`. Every caller keeps its
+/// own, so the assistant window, the API and the CLI never cancel or label
+/// each other's answers.
+pub struct Generation {
+    /// None if the answer came from the cache.
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel: watch::Sender<bool>,
+    answered_by: Arc<Mutex<Option<(String, bool)>>>,
+    chunk_progress: Arc<Mutex<Option<ChunkProgress>>>,
+    knowledge: Vec<Snippet>,
+    from_cache: bool,
+}
+
+impl Generation {
+    /// Stops the generation, the partial answer is kept.
+    pub fn cancel(&self) {
+        let _ = self.cancel.send(true);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.as_ref().is_none_or(|task| task.is_finished())
+    }
+
+    /// Waits until the answer, the finish reason or the error are set.
+    pub async fn finished(self) -> Result<(), tokio::task::JoinError> {
+        match self.task {
+            Some(task) => task.await,
+            None => Ok(()),
         }
     }
+
+    /// Backend that answered and whether it was a fallback.
+    pub fn answered_by(&self) -> Option<(String, bool)> {
+        self.answered_by.lock().unwrap().clone()
+    }
+
+    /// Which chunk of a long selection is being processed.
+    pub fn chunk_progress(&self) -> Option<ChunkProgress> {
+        *self.chunk_progress.lock().unwrap()
+    }
+
+    /// The snippets of the knowledge folder that were added to the prompt.
+    pub fn knowledge(&self) -> &[Snippet] {
+        &self.knowledge
+    }
+
+    /// Notes the knowledge snippets came from.
+    pub fn knowledge_sources(&self) -> Vec<String> {
+        knowledge::sources(&self.knowledge)
+    }
+
+    pub fn from_cache(&self) -> bool {
+        self.from_cache
+    }
 }
 
 pub struct LLMSelector {
     llm_type: LLMType,
     model: Arc<Mutex<Option<Llama>>>,
-    show_window: bool,
-    download_progress: Arc<Mutex<f32>>,
     download_error: Arc<Mutex<Option<String>>>,
+    downloads: DownloadManager,
     pub user_info: Arc<Mutex<Option<UserInfo>>>,
     ollama: Arc<Mutex<Option<Ollama>>>,
-    pub ollama_models: Arc<Mutex<Option<Vec<ollama_rs::models::LocalModel>>>>,
+    pub ollama_models: Arc<Mutex<Option<Vec<OllamaModelInfo>>>>,
+    ollama_url: Arc<Mutex<String>>,
+    ollama_pull_progress: Arc<Mutex<Option<(String, f32)>>>,
+    ollama_error: Arc<Mutex<Option<String>>>,
+    pub openai_base_url: String,
+    pub openai_model: String,
+    pub openai_api_key: String,
+    /// API keys of OpenAI compatible servers by base URL.
+    openai_api_keys: BTreeMap<String, String>,
+    pub generation_config: GenerationConfig,
+    pub local_quantization: Quantization,
+    custom_models: Vec<CustomModel>,
+    pub cache_settings: CacheSettings,
+    /// Tried in this order when the selected model fails before answering.
+    pub fallback_llms: Vec<LLMType>,
+    /// Seconds a model may take to accept a request before the next
+    /// fallback model is tried.
+    pub fallback_timeout_secs: u64,
+    knowledge: Arc<Mutex<KnowledgeBase>>,
+    /// Name of the active settings profile.
+    profile: String,
+    profile_names: Vec<String>,
+    /// The profile only allows models that run on this machine.
+    local_only: bool,
 }
 
 impl LLMSelector {
     pub fn new(user_info: Arc<Mutex<Option<UserInfo>>>) -> Self {
-        let llm_type = load_llm_type().unwrap_or(LLMType::Cloud(CloudModel::AnthropicHaiku));
-        let ollama = Ollama::default();
+        let settings = settings::load();
+        let profile = settings.profile();
+        let profile_names = settings.profiles.keys().cloned().collect();
+        let llm_type = profile.llm_type;
+        let ollama_url = settings.ollama_url;
+        let ollama = ollama_from_url(&ollama_url).unwrap_or_default();
         let ollama_models = Arc::new(Mutex::new(None));
+        let ollama_url = Arc::new(Mutex::new(ollama_url));
+        let knowledge = Arc::new(Mutex::new(KnowledgeBase::new(KnowledgeBase::default_dir())));
         {
+            // Notes are edited outside of PlugOvr, requests search the last index.
+            let knowledge = knowledge.clone();
+            tokio::task::spawn(async move {
+                loop {
+                    refresh_knowledge(&knowledge).await;
+                    tokio::time::sleep(KNOWLEDGE_REFRESH_INTERVAL).await;
+                }
+            });
+        }
+        {
+            // Picks up models pulled outside of PlugOvr and a restarted Ollama.
+            let ollama_url = ollama_url.clone();
             let ollama_models = ollama_models.clone();
             tokio::task::spawn(async move {
-                if let Ok(models) = ollama.list_local_models().await {
-                    *ollama_models.lock().unwrap() = Some(models);
+                loop {
+                    let url = ollama_url.lock().unwrap().clone();
+                    refresh_ollama_models(&url, &ollama_models).await;
+                    tokio::time::sleep(OLLAMA_REFRESH_INTERVAL).await;
                 }
             });
         }
-        let ollama = Ollama::default();
+        let (openai_base_url, openai_model) = match &llm_type {
+            LLMType::OpenAiCompatible { base_url, model } => (base_url.clone(), model.clone()),
+            _ => ("http://127.0.0.1:8000/v1".to_string(), String::new()),
+        };
+        let openai_api_keys = settings.openai_api_keys;
+        let openai_api_key = openai_api_keys
+            .get(&openai_base_url)
+            .cloned()
+            .unwrap_or_default();
+        let local_quantization = match &llm_type {
+            LLMType::Local(local_model) => local_model.quantization().unwrap_or_default(),
+            _ => Quantization::default(),
+        };
         LLMSelector {
             llm_type,
             model: Arc::new(Mutex::new(None)),
-            show_window: false,
-            download_progress: Arc::new(Mutex::new(0.0)),
             download_error: Arc::new(Mutex::new(None)),
+            downloads: DownloadManager::new(DownloadManager::default_dir()),
             user_info,
             ollama: Arc::new(Mutex::new(Some(ollama))),
             ollama_models,
+            ollama_url,
+            ollama_pull_progress: Arc::new(Mutex::new(None)),
+            ollama_error: Arc::new(Mutex::new(None)),
+            openai_base_url,
+            openai_model,
+            openai_api_key,
+            openai_api_keys,
+            generation_config: settings.generation,
+            local_quantization,
+            custom_models: settings.custom_models,
+            cache_settings: settings.cache,
+            fallback_llms: settings.fallback_llms,
+            fallback_timeout_secs: settings.fallback_timeout_secs,
+            knowledge,
+            profile: settings.active_profile,
+            profile_names,
+            local_only: profile.local_only,
         }
     }
 
     pub async fn load_model(&self) {
-        let mut model = self.model.lock().unwrap();
-        *model = match &self.llm_type {
-            LLMType::Local(LocalModel::Llama32S1bChat) => Some(
-                Llama::builder()
-                    .with_source(LlamaSource::llama_3_2_1b_chat())
-                    .build()
-                    .await
-                    .unwrap(),
-            ),
-            LLMType::Local(LocalModel::Llama32S3bChat) => Some(
-                Llama::builder()
-                    .with_source(LlamaSource::llama_3_2_3b_chat())
-                    .build()
-                    .await
-                    .unwrap(),
-            ),
-            LLMType::Cloud(CloudModel::AnthropicHaiku) => None,
-            LLMType::Cloud(CloudModel::AnthropicSonnet3_5) => None,
-            LLMType::Ollama(_model) => None,
+        let downloads = self.downloads.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || import_kalosm_models(&downloads)).await
+        {
+            eprintln!("Failed to import models of the kalosm cache: {}", e);
+        }
+        let source = match &self.llm_type {
+            LLMType::Local(local_model) => {
+                if !self.downloads.is_downloaded(&local_model.files().to_vec()) {
+                    *self.download_error.lock().unwrap() = Some(format!(
+                        "{} is not downloaded yet, download or import it in the model selection",
+                        local_model.description()
+                    ));
+                    return;
+                }
+                local_model.source(self.downloads.dir())
+            }
+            LLMType::CustomGguf(custom_model) => custom_model.source(),
+            LLMType::Cloud(_) | LLMType::Ollama(_) | LLMType::OpenAiCompatible { .. } => {
+                *self.model.lock().unwrap() = None;
+                return;
+            }
         };
+        let llama = match Llama::builder().with_source(source).build().await {
+            Ok(llama) => Some(llama),
+            Err(e) => {
+                eprintln!("Failed to load model: {}", e);
+                *self.download_error.lock().unwrap() = Some(e.to_string());
+                None
+            }
+        };
+        *self.model.lock().unwrap() = llama;
+    }
+
+    pub fn backend_for(&self, llm_type: &LLMType) -> Arc<dyn LlmBackend> {
+        match llm_type {
+            LLMType::Cloud(cloud_model) => {
+                Arc::new(CloudBackend::new(*cloud_model, self.user_info.clone()))
+            }
+            LLMType::Local(local_model) => Arc::new(LocalBackend::new(
+                self.model.clone(),
+                local_model.description(),
+                local_model.context_window(),
+            )),
+            LLMType::Ollama(model) => {
+                let ollama = self.ollama.lock().unwrap().clone().unwrap_or_default();
+                let url = self.ollama_url.lock().unwrap().clone();
+                Arc::new(OllamaBackend::new(ollama, url, model.clone()))
+            }
+            LLMType::OpenAiCompatible { base_url, model } => {
+                Arc::new(OpenAiCompatibleBackend::new(
+                    base_url.clone(),
+                    model.clone(),
+                    self.openai_api_keys
+                        .get(base_url)
+                        .cloned()
+                        .unwrap_or_default(),
+                ))
+            }
+            LLMType::CustomGguf(custom_model) => Arc::new(LocalBackend::new(
+                self.model.clone(),
+                custom_model.name.clone(),
+                DEFAULT_CONTEXT_WINDOW,
+            )),
+        }
     }
 
     pub fn process_input(
         &self,
-        prompt: String,
-        context: String,
-        screenshots: Vec<(ImageBuffer<Rgba<u8>, Vec<u8>>, egui::Pos2)>,
-        instruction: String,
+        mut request: LlmRequest,
         ai_answer: Arc<Mutex<String>>,
-        max_tokens_reached: Arc<Mutex<bool>>,
+        finish_reason: Arc<Mutex<Option<FinishReason>>>,
+        llm_error: Arc<Mutex<Option<LlmError>>>,
         spinner: Arc<Mutex<bool>>,
         llm_from_template: Option<LLMType>,
-    ) -> Result<tokio::task::JoinHandle<()>, Box<dyn Error + Send + Sync>> {
+    ) -> Result<Generation, LlmError> {
         let mut llm_type = self.llm_type.clone();
         if let Some(llm_from_template) = llm_from_template {
             llm_type = llm_from_template;
         }
 
-        let model = self.model.clone();
-        let spinner_clone = spinner.clone();
-        let user_info = self.user_info.clone();
-        if (llm_type == LLMType::Cloud(CloudModel::AnthropicHaiku)
-            || llm_type == LLMType::Cloud(CloudModel::AnthropicSonnet3_5))
-            && user_info.lock().unwrap().is_none()
-        {
-            *ai_answer.lock().unwrap() =
-                "Please login to use cloud LLM or switch to local LLM".to_string();
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Please login to use cloud LLM or switch to local LLM",
+        if !self.is_allowed(&llm_type) {
+            let error = LlmError::NotAllowed(format!(
+                "The profile {} only allows local models, {} is not one",
+                self.profile,
+                llm_type.description()
+            ));
+            *llm_error.lock().unwrap() = Some(error.clone());
+            return Err(error);
+        }
+        // Each backend is metered on its own, so a fallback answer is credited
+        // to the model that gave it and failed attempts don't add to its times.
+        let mut attempts = vec![];
+        let primary_skipped =
+            matches!(llm_type, LLMType::Cloud(_)) && self.user_info.lock().unwrap().is_none();
+        if !primary_skipped {
+            attempts.push(Arc::new(MeteredBackend::new(
+                self.backend_for(&llm_type),
+                llm_type.description(),
             )));
         }
-        let ollama = self.ollama.clone();
-
-        let handle = tokio::task::spawn_blocking(move || {
-            *spinner_clone.lock().unwrap() = true;
-            let llm_type_clone = llm_type.clone();
-            let result: Result<(String, bool), Box<dyn Error + Send + Sync>> = match llm_type {
-                LLMType::Cloud(CloudModel::AnthropicHaiku)
-                | LLMType::Cloud(CloudModel::AnthropicSonnet3_5) => {
-                    let user_info = user_info.lock().unwrap().as_ref().unwrap().clone();
-                    let model = llm_type.clone().to_string();
-                    #[cfg(feature = "cs")]
-                    {
-                        Ok(call_aws_lambda(
-                            user_info,
-                            prompt.clone(),
-                            model,
-                            &screenshots,
-                        ))
-                    }
-                    #[cfg(not(feature = "cs"))]
-                    {
-                        Ok((
-                            "Download PlugOvr from https://plugovr.ai to use cloud LLM".to_string(),
-                            false,
-                        ))
-                    }
+        for fallback_llm in &self.fallback_llms {
+            if *fallback_llm != llm_type && self.is_allowed(fallback_llm) {
+                attempts.push(Arc::new(MeteredBackend::new(
+                    self.backend_for(fallback_llm),
+                    fallback_llm.description(),
+                )));
+            }
+        }
+        if attempts.is_empty() {
+            let error = LlmError::AuthExpired(
+                "Please login to use cloud LLM or switch to local LLM".to_string(),
+            );
+            *llm_error.lock().unwrap() = Some(error.clone());
+            return Err(error);
+        }
+        *llm_error.lock().unwrap() = None;
+        let answered_by = Arc::new(Mutex::new(None));
+        let chunk_progress = Arc::new(Mutex::new(None));
+        let backend: Arc<dyn LlmBackend> = if let [attempt] = attempts.as_slice() {
+            *answered_by.lock().unwrap() = primary_skipped.then(|| (attempt.name(), true));
+            attempt.clone()
+        } else {
+            Arc::new(FallbackBackend::new(
+                attempts
+                    .iter()
+                    .map(|attempt| attempt.clone() as Arc<dyn LlmBackend>)
+                    .collect(),
+                answered_by.clone(),
+                std::time::Duration::from_secs(self.fallback_timeout_secs),
+                primary_skipped,
+            ))
+        };
+        let backend: Arc<dyn LlmBackend> =
+            Arc::new(ChunkedBackend::new(backend, chunk_progress.clone()));
+        // The request carries the template options, they win over the model settings.
+        request.options = self
+            .generation_config
+            .for_model(&llm_type.description())
+            .merged_with(&request.options);
+        self.add_knowledge(&mut request);
+        let (cancel_sender, cancel_receiver) = watch::channel(false);
+        let mut generation = Generation {
+            task: None,
+            cancel: cancel_sender,
+            answered_by: answered_by.clone(),
+            chunk_progress,
+            knowledge: request.knowledge.clone(),
+            from_cache: false,
+        };
+        let response_cache = self.response_cache();
+        let cache_key = ResponseCache::key(&backend.name(), &request);
+        if !request.bypass_cache {
+            if let Some(answer) = response_cache.get(&cache_key) {
+                *ai_answer.lock().unwrap() = answer;
+                *finish_reason.lock().unwrap() = Some(FinishReason::Stop);
+                *answered_by.lock().unwrap() = None;
+                generation.from_cache = true;
+                return Ok(generation);
+            }
+        }
+        let json_schema = request.options.json_schema.clone();
+
+        *spinner.lock().unwrap() = true;
+        *finish_reason.lock().unwrap() = None;
+        let handle = tokio::task::spawn(async move {
+            let metrics_request = request.clone();
+            let result = generate(&*backend, request, ai_answer.clone(), cancel_receiver).await;
+            let answered_by = answered_by.lock().unwrap().clone();
+            // A fallback answers under its own name, so the cache uses it.
+            let cache_key = match &answered_by {
+                Some((name, true)) => ResponseCache::key(name, &metrics_request),
+                _ => cache_key,
+            };
+            let cancelled = matches!(result, Ok((_, FinishReason::Cancelled)));
+            // Backends without constrained decoding may still wrap or break the JSON.
+            let result = match (result, &json_schema) {
+                (Ok((answer, FinishReason::Stop)), Some(schema)) => {
+                    structured::validated_json(&answer, schema)
+                        .map(|json| (json, FinishReason::Stop))
+                        .map_err(LlmError::InvalidAnswer)
                 }
-                LLMType::Local(_) => {
-                    use tokio::runtime::Runtime;
-                    let rt = Runtime::new().unwrap();
-                    rt.block_on(async {
-                        let result = call_local_llm(
-                            prompt.clone(),
-                            context,
-                            instruction,
-                            ai_answer.clone(),
-                            model,
-                        )
-                        .await;
-                        Ok((result?, false))
-                    })
+                (result, _) => result.map_err(|e| LlmError::from_backend_error(&e)),
+            };
+            let error = match result {
+                Ok((answer, reason)) => {
+                    // Only complete answers are worth replaying.
+                    if reason == FinishReason::Stop {
+                        response_cache
+                            .put(&cache_key, &answer)
+                            .unwrap_or_else(|e| eprintln!("Failed to cache answer: {}", e));
+                    }
+                    *ai_answer.lock().unwrap() = answer;
+                    *finish_reason.lock().unwrap() = Some(reason);
+                    None
                 }
-                LLMType::Ollama(model) => {
-                    use tokio::runtime::Runtime;
-                    let rt = Runtime::new().unwrap();
-                    let model_clone = model.clone();
-                    rt.block_on(async {
-                        let result = call_ollama(
-                            ollama,
-                            model_clone,
-                            prompt.clone(),
-                            context,
-                            instruction,
-                            ai_answer.clone(),
-                            &screenshots,
-                        )
-                        .await;
-                        Ok((result?, false))
-                    })
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    let details = e.details().to_string();
+                    *llm_error.lock().unwrap() = Some(e);
+                    Some(details)
                 }
             };
-
-            let mut result = result.unwrap();
-            #[cfg(feature = "cs")]
-            if result.0.contains("Access token expired") {
-                match get_user() {
-                    Ok(user_info_tmp) => {
-                        *user_info.lock().unwrap() = Some(user_info_tmp);
-
-                        if llm_type_clone == LLMType::Cloud(CloudModel::AnthropicHaiku)
-                            || llm_type_clone == LLMType::Cloud(CloudModel::AnthropicSonnet3_5)
-                        {
-                            let user_info = user_info.lock().unwrap().as_ref().unwrap().clone();
-                            let model = llm_type_clone.clone().to_string();
-                            result = call_aws_lambda(user_info, prompt, model, &screenshots);
-                        }
-                    }
-                    Err(_e) => {
-                        *user_info.lock().unwrap() = None;
+            *spinner.lock().unwrap() = false;
+            for attempt in &attempts {
+                let error = match &answered_by {
+                    Some((name, _)) if *name != attempt.name() => {
+                        Some("No answer, a fallback was used".to_string())
                     }
+                    _ => error.clone(),
+                };
+                if let Some(metrics) = attempt.take_metrics(&metrics_request, error, cancelled) {
+                    save_metrics(&metrics)
+                        .unwrap_or_else(|e| eprintln!("Failed to save metrics: {}", e));
                 }
             }
-            *ai_answer.lock().unwrap() = result.0;
-            *max_tokens_reached.lock().unwrap() = result.1;
-            *spinner.lock().unwrap() = false;
         });
-        Ok(handle)
-    }
-
-    pub fn show_selection_window(&mut self, ctx: &Context) {
-        Window::new("LLM Selection")
-            .open(&mut self.show_window)
-            .collapsible(false)
-            .max_width(400.0)
-            .show(ctx, |ui| {
-                ui.heading("Cloud Models");
-                let cloud_models = CloudModel::iter().collect::<Vec<_>>();
-                for cloud_model in cloud_models {
-                    if ui
-                        .radio_value(
-                            &mut self.llm_type,
-                            LLMType::Cloud(cloud_model),
-                            LLMType::Cloud(cloud_model).description(),
-                        )
-                        .changed()
-                    {
-                        save_llm_type(LLMType::Cloud(cloud_model))
-                            .unwrap_or_else(|e| eprintln!("Failed to save LLM type: {}", e));
-                    }
-                }
+        generation.task = Some(handle);
+        Ok(generation)
+    }
 
-                ui.heading("Local Models");
-                for local_model in LocalModel::iter() {
-                    let requires_download = Llama::builder()
-                        .with_source(local_model.source())
-                        .requires_download();
-
-                    ui.horizontal(|ui| {
-                        if ui
-                            .add_enabled(
-                                !requires_download,
-                                egui::RadioButton::new(
-                                    self.llm_type == LLMType::Local(local_model),
-                                    LLMType::Local(local_model).description(),
-                                ),
-                            )
-                            .clicked()
-                        {
-                            self.llm_type = LLMType::Local(local_model);
-                            save_llm_type(LLMType::Local(local_model))
-                                .unwrap_or_else(|e| eprintln!("Failed to save LLM type: {}", e));
-
-                            let model = self.model.clone();
-                            let llama_source = local_model.source();
-
-                            tokio::spawn(async move {
-                                let llama = Llama::builder()
-                                    .with_source(llama_source)
-                                    .build()
-                                    .await
-                                    .unwrap();
-                                if let Ok(mut model_guard) = model.lock() {
-                                    *model_guard = Some(llama);
-                                } else {
-                                    eprintln!("Failed to acquire lock on model");
-                                }
-                            });
-                        }
-                        if !requires_download {
-                            ui.label("Downloaded");
-                        } else if ui.button("Download").clicked() {
-                            let llama_source = local_model.source();
-                            let download_progress = self.download_progress.clone();
-                            let download_error = self.download_error.clone();
-                            tokio::spawn(async move {
-                                let download_progress = download_progress.clone();
-                                let _llama = match Llama::builder()
-                                    .with_source(llama_source)
-                                    .build_with_loading_handler(move |x| match x.clone() {
-                                        ModelLoadingProgress::Downloading { .. } => {
-                                            *download_progress.lock().unwrap() = x.progress()
-                                        }
-                                        ModelLoadingProgress::Loading { progress } => {
-                                            *download_progress.lock().unwrap() = progress;
-                                        }
-                                    })
-                                    .await
-                                {
-                                    Ok(llama) => llama,
-                                    Err(e) => {
-                                        eprintln!("Failed to download/load model: {}", e);
-                                        *download_error.lock().unwrap() = Some(e.to_string());
-                                        return;
-                                    }
-                                };
-                            });
-                        }
-                    });
-                }
-                if let Some(ollama_models) = self.ollama_models.lock().unwrap().as_ref() {
-                    ui.heading("Ollama Models");
-                    let ollama_models = ollama_models.clone();
-                    if ollama_models.is_empty() {
-                        ui.label(
-                            "No ollama models found, pull some models with e.g. ollama pull llama3.2:1b"
-                        );
-                    } else {
-                        for ollama_model in ollama_models {
-                            if ui
-                            .radio_value(
-                                &mut self.llm_type,
-                                LLMType::Ollama(ollama_model.name.clone()),
-                                LLMType::Ollama(ollama_model.name.clone()).description(),
-                            )
-                            .changed()
-                            {
-                                save_llm_type(LLMType::Ollama(ollama_model.name.clone()))
-                                    .unwrap_or_else(|e| eprintln!("Failed to save LLM type: {}", e));
-                            }
-                        }
-                    }
-                } else {
-                    ui.heading("Ollama not installed");
-                }
+    /// Adds the best matching snippets of the knowledge folder if the
+    /// template asks for them. Follow-ups already have them in the history.
+    fn add_knowledge(&self, request: &mut LlmRequest) {
+        if !request.history.is_empty() {
+            return;
+        }
+        if request.knowledge.is_empty() {
+            if let Some(count) = request.options.knowledge_snippets {
+                let knowledge = self.knowledge.lock().unwrap();
+                let query = format!("{}\n{}", request.instruction, request.context);
+                request.knowledge = knowledge.search(&query, count);
+            }
+        }
+    }
 
-                if *self.download_progress.lock().unwrap() > 0.0 {
-                    ui.label("Download progress");
-                    ui.add(
-                        egui::ProgressBar::new(*self.download_progress.lock().unwrap())
-                            .show_percentage(),
-                    );
-                }
-                if let Some(error) = self.download_error.lock().unwrap().as_ref() {
-                    ui.colored_label(ui.visuals().error_fg_color, error);
-                }
-            });
+    /// Every model that can currently be selected in the active profile.
+    pub fn available_llm_types(&self) -> Vec<LLMType> {
+        let mut llm_types: Vec<LLMType> = CloudModel::iter().map(LLMType::Cloud).collect();
+        llm_types.extend(LocalModel::iter().map(|local_model| {
+            LLMType::Local(local_model.with_quantization(self.local_quantization))
+        }));
+        llm_types.extend(self.custom_models.iter().cloned().map(LLMType::CustomGguf));
+        if let Some(ollama_models) = self.ollama_models.lock().unwrap().as_ref() {
+            llm_types.extend(
+                ollama_models
+                    .iter()
+                    .map(|ollama_model| LLMType::Ollama(ollama_model.name.clone())),
+            );
+        }
+        if !self.openai_model.is_empty() {
+            llm_types.push(self.openai_compatible_llm_type());
+        }
+        llm_types.retain(|llm_type| self.is_allowed(llm_type));
+        llm_types
+    }
+
+    /// Selects a model and saves it in the active profile, local models are
+    /// loaded in the background.
+    pub fn select_llm_type(&mut self, llm_type: LLMType) {
+        self.llm_type = llm_type.clone();
+        save_llm_type(llm_type).unwrap_or_else(|e| eprintln!("Failed to save LLM type: {}", e));
+        let source = match &self.llm_type {
+            LLMType::Local(local_model) => local_model.source(self.downloads.dir()),
+            LLMType::CustomGguf(custom_model) => custom_model.source(),
+            LLMType::OpenAiCompatible { .. } => {
+                self.save_openai_api_key();
+                return;
+            }
+            LLMType::Cloud(_) | LLMType::Ollama(_) => return,
+        };
+        spawn_load_model(self.model.clone(), source, self.download_error.clone());
     }
 
-    pub fn toggle_window(&mut self) {
-        self.show_window = !self.show_window;
+    pub fn downloads(&self) -> &DownloadManager {
+        &self.downloads
+    }
+
+    pub fn download_error(&self) -> Option<String> {
+        self.download_error.lock().unwrap().clone()
+    }
+
+    /// Deletes the files of a downloaded model, it is unloaded if selected.
+    pub fn delete_local_model(&self, local_model: LocalModel) {
+        if self.llm_type == LLMType::Local(local_model) {
+            *self.model.lock().unwrap() = None;
+        }
+        if let Err(e) = self
+            .downloads
+            .delete(&local_model.to_string(), &local_model.files().to_vec())
+        {
+            *self.download_error.lock().unwrap() = Some(format!("Failed to delete model: {}", e));
+        }
+    }
+
+    /// Imports the catalog models found in `dir` and returns how many.
+    pub fn import_models(&self, dir: &std::path::Path) -> usize {
+        let (imported, errors) = import_local_models(&self.downloads, dir);
+        *self.download_error.lock().unwrap() = (imported == 0).then(|| errors.join("\n"));
+        imported
+    }
+
+    pub fn add_custom_model(&mut self, custom_model: CustomModel) -> Result<(), String> {
+        custom_model.validate()?;
+        self.custom_models.push(custom_model);
+        self.save_custom_models();
+        Ok(())
+    }
+
+    pub fn remove_custom_model(&mut self, index: usize) {
+        self.custom_models.remove(index);
+        self.save_custom_models();
+    }
+
+    fn save_custom_models(&self) {
+        let custom_models = self.custom_models.clone();
+        settings::update(|settings| settings.custom_models = custom_models)
+            .unwrap_or_else(|e| eprintln!("Failed to save custom models: {}", e));
+    }
+
+    pub fn ollama_url(&self) -> String {
+        self.ollama_url.lock().unwrap().clone()
+    }
+
+    /// Connects to the Ollama server at `url` and saves it.
+    pub fn set_ollama_url(&self, url: &str) {
+        match ollama_from_url(url) {
+            Ok(ollama) => {
+                *self.ollama.lock().unwrap() = Some(ollama);
+                *self.ollama_url.lock().unwrap() = url.to_string();
+                *self.ollama_error.lock().unwrap() = None;
+                settings::update(|settings| settings.ollama_url = url.to_string())
+                    .unwrap_or_else(|e| eprintln!("Failed to save Ollama URL: {}", e));
+                let url = url.to_string();
+                let ollama_models = self.ollama_models.clone();
+                tokio::spawn(async move {
+                    refresh_ollama_models(&url, &ollama_models).await;
+                });
+            }
+            Err(e) => {
+                *self.ollama_error.lock().unwrap() = Some(format!("Invalid Ollama URL: {}", e));
+            }
+        }
+    }
+
+    pub fn ollama_error(&self) -> Option<String> {
+        self.ollama_error.lock().unwrap().clone()
+    }
+
+    /// Status and fraction of the running pull, if any.
+    pub fn ollama_pull_progress(&self) -> Option<(String, f32)> {
+        self.ollama_pull_progress.lock().unwrap().clone()
+    }
+
+    pub fn pull_ollama_model(&self, model: String) {
+        let ollama = self.ollama.lock().unwrap().clone().unwrap_or_default();
+        let url = self.ollama_url();
+        let ollama_models = self.ollama_models.clone();
+        let ollama_error = self.ollama_error.clone();
+        let progress = self.ollama_pull_progress.clone();
+        *progress.lock().unwrap() = Some(("starting".to_string(), 0.0));
+        tokio::spawn(async move {
+            if let Err(e) = pull_ollama_model(ollama, model, progress.clone()).await {
+                *ollama_error.lock().unwrap() = Some(format!("Failed to pull model: {}", e));
+            }
+            *progress.lock().unwrap() = None;
+            refresh_ollama_models(&url, &ollama_models).await;
+        });
+    }
+
+    pub fn delete_ollama_model(&self, model: String) {
+        let ollama = self.ollama.lock().unwrap().clone().unwrap_or_default();
+        let url = self.ollama_url();
+        let ollama_models = self.ollama_models.clone();
+        let ollama_error = self.ollama_error.clone();
+        tokio::spawn(async move {
+            if let Err(e) = ollama.delete_model(model).await {
+                *ollama_error.lock().unwrap() = Some(format!("Failed to delete model: {}", e));
+            }
+            refresh_ollama_models(&url, &ollama_models).await;
+        });
+    }
+
+    pub fn save_fallback_llms(&self) {
+        let fallback_llms = self.fallback_llms.clone();
+        let timeout_secs = self.fallback_timeout_secs;
+        settings::update(|settings| {
+            settings.fallback_llms = fallback_llms;
+            settings.fallback_timeout_secs = timeout_secs;
+        })
+        .unwrap_or_else(|e| eprintln!("Failed to save fallback models: {}", e));
+    }
+
+    fn response_cache(&self) -> ResponseCache {
+        ResponseCache::new(ResponseCache::default_dir(), self.cache_settings.clone())
+    }
+
+    pub fn cache_size(&self) -> u64 {
+        self.response_cache().size()
+    }
+
+    pub fn clear_cache(&self) {
+        self.response_cache()
+            .clear()
+            .unwrap_or_else(|e| eprintln!("Failed to clear cache: {}", e));
+    }
+
+    pub fn save_cache_settings(&self) {
+        let cache_settings = self.cache_settings.clone();
+        settings::update(|settings| settings.cache = cache_settings)
+            .unwrap_or_else(|e| eprintln!("Failed to save cache settings: {}", e));
+    }
+
+    pub fn knowledge(&self) -> std::sync::MutexGuard<'_, KnowledgeBase> {
+        self.knowledge.lock().unwrap()
+    }
+
+    /// Rereads the knowledge folder now instead of waiting for the refresh loop.
+    pub fn reload_knowledge(&self) {
+        let knowledge = self.knowledge.clone();
+        tokio::task::spawn(async move { refresh_knowledge(&knowledge).await });
+    }
+
+    pub fn save_generation_config(&self) {
+        let generation_config = self.generation_config.clone();
+        settings::update(|settings| settings.generation = generation_config)
+            .unwrap_or_else(|e| eprintln!("Failed to save generation options: {}", e));
     }
 
     pub fn get_llm_type(&self) -> LLMType {
         self.llm_type.clone()
     }
+
+    /// Selects a model for this session only, the saved selection stays.
+    pub fn set_llm_type(&mut self, llm_type: LLMType) {
+        self.llm_type = llm_type;
+    }
+
+    /// Switches to the model and the privacy rules of a settings profile.
+    pub fn apply_profile(&mut self, name: &str, profile: &Profile) {
+        self.profile = name.to_string();
+        self.local_only = profile.local_only;
+        if self.llm_type == profile.llm_type {
+            return;
+        }
+        self.llm_type = profile.llm_type.clone();
+        match &self.llm_type {
+            LLMType::OpenAiCompatible { base_url, model } => {
+                self.openai_base_url = base_url.clone();
+                self.openai_model = model.clone();
+                self.openai_api_key = self
+                    .openai_api_keys
+                    .get(base_url)
+                    .cloned()
+                    .unwrap_or_default();
+            }
+            LLMType::Local(local_model) => {
+                self.local_quantization = local_model.quantization().unwrap_or_default();
+            }
+            _ => {}
+        }
+        let source = match &self.llm_type {
+            LLMType::Local(local_model)
+                if self.downloads.is_downloaded(&local_model.files().to_vec()) =>
+            {
+                local_model.source(self.downloads.dir())
+            }
+            LLMType::CustomGguf(custom_model) => custom_model.source(),
+            _ => {
+                *self.model.lock().unwrap() = None;
+                return;
+            }
+        };
+        spawn_load_model(self.model.clone(), source, self.download_error.clone());
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    pub fn profile_names(&self) -> &[String] {
+        &self.profile_names
+    }
+
+    /// The active profile only allows models that run on this machine.
+    pub fn local_only(&self) -> bool {
+        self.local_only
+    }
+
+    /// The profiles offered in the selection window.
+    pub fn set_profile_names(&mut self, names: Vec<String>) {
+        self.profile_names = names;
+    }
+
+    /// Whether the active profile lets `llm_type` answer.
+    pub fn is_allowed(&self, llm_type: &LLMType) -> bool {
+        !self.local_only || self.runs_locally(llm_type)
+    }
+
+    /// Whether `llm_type` runs on this machine, so the text never leaves it.
+    pub fn runs_locally(&self, llm_type: &LLMType) -> bool {
+        match llm_type {
+            LLMType::Local(_) | LLMType::CustomGguf(_) => true,
+            LLMType::Ollama(_) => is_loopback_url(&self.ollama_url.lock().unwrap()),
+            LLMType::OpenAiCompatible { base_url, .. } => is_loopback_url(base_url),
+            LLMType::Cloud(_) => false,
+        }
+    }
+
+    /// Finds a model by its description, e.g. `Ollama - llama3.2`, its
+    /// identifier, e.g. `Llama32S1bChat`, or the name of an Ollama model.
+    pub fn find_llm_type(&self, name: &str) -> Option<LLMType> {
+        self.available_llm_types().into_iter().find(|llm_type| {
+            llm_type.description().eq_ignore_ascii_case(name)
+                || llm_type.to_string().eq_ignore_ascii_case(name)
+        })
+    }
+
+    /// Fetches the Ollama models now instead of waiting for the refresh loop.
+    pub async fn refresh_models(&self) {
+        let url = self.ollama_url.lock().unwrap().clone();
+        refresh_ollama_models(&url, &self.ollama_models).await;
+    }
+
+    pub fn custom_models(&self) -> Vec<CustomModel> {
+        self.custom_models.clone()
+    }
+
+    pub fn openai_compatible_llm_type(&self) -> LLMType {
+        LLMType::OpenAiCompatible {
+            base_url: self.openai_base_url.trim_end_matches('/').to_string(),
+            model: self.openai_model.clone(),
+        }
+    }
+
+    /// Stores the key of the entered server beside the model, an empty key
+    /// removes it.
+    pub fn save_openai_api_key(&mut self) {
+        let base_url = self.openai_base_url.trim_end_matches('/').to_string();
+        if self.openai_api_key.is_empty() {
+            self.openai_api_keys.remove(&base_url);
+        } else {
+            self.openai_api_keys
+                .insert(base_url, self.openai_api_key.clone());
+        }
+        let openai_api_keys = self.openai_api_keys.clone();
+        settings::update(|settings| settings.openai_api_keys = openai_api_keys)
+            .unwrap_or_else(|e| eprintln!("Failed to save API key: {}", e));
+    }
 }
-// Add this new function to save the LLMType
 fn save_llm_type(llm_type: LLMType) -> std::io::Result<()> {
-    let mut path = dirs::home_dir().expect("Unable to get home directory");
-    path.push(".plugovr");
-    std::fs::create_dir_all(&path)?;
-    path.push("llm_type.json");
-
-    let serialized = serde_json::to_string(&llm_type)?;
-    let mut file = File::create(path)?;
-    file.write_all(serialized.as_bytes())?;
-    Ok(())
+    settings::update(|settings| settings.profile_mut().llm_type = llm_type)
+}
+
+fn is_loopback_url(url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    match url.host_str() {
+        Some("localhost") => true,
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback()),
+        None => false,
+    }
+}
+
+/// Rebuilds the knowledge index on a copy off the async threads, requests
+/// keep searching the old index meanwhile.
+async fn refresh_knowledge(knowledge: &Arc<Mutex<KnowledgeBase>>) {
+    let mut copy = knowledge.lock().unwrap().clone();
+    match tokio::task::spawn_blocking(move || {
+        copy.refresh();
+        copy
+    })
+    .await
+    {
+        Ok(refreshed) => *knowledge.lock().unwrap() = refreshed,
+        Err(e) => eprintln!("Failed to refresh the knowledge folder: {}", e),
+    }
+}
+
+async fn refresh_ollama_models(
+    url: &str,
+    ollama_models: &Arc<Mutex<Option<Vec<OllamaModelInfo>>>>,
+) {
+    let models = list_ollama_models(url).await.ok();
+    *ollama_models.lock().unwrap() = models;
+}
+
+/// Older versions let kalosm download the Llama models into its own cache.
+/// They are imported once instead of being downloaded again.
+fn import_kalosm_models(downloads: &DownloadManager) {
+    let Some(cache_dir) = kalosm_cache_dir().filter(|dir| dir.is_dir()) else {
+        return;
+    };
+    for local_model in [LocalModel::Llama32S1bChat, LocalModel::Llama32S3bChat] {
+        let files = local_model.files().to_vec();
+        let in_cache = files.iter().all(|file| {
+            cache_dir
+                .join(&file.repo)
+                .join("main")
+                .join(&file.file)
+                .is_file()
+        });
+        if downloads.is_downloaded(&files) || !in_cache {
+            continue;
+        }
+        match downloads.import(&cache_dir, &files) {
+            Ok(()) => println!(
+                "Imported {} from the kalosm cache",
+                local_model.description()
+            ),
+            Err(e) => eprintln!("Failed to import {}: {}", local_model.description(), e),
+        }
+    }
+}
+
+/// Imports every catalog model, in every quantization, found in `dir`.
+fn import_local_models(downloads: &DownloadManager, dir: &std::path::Path) -> (usize, Vec<String>) {
+    let mut imported = 0;
+    let mut errors = vec![];
+    for local_model in LocalModel::iter().flat_map(|local_model| {
+        Quantization::iter().map(move |quantization| local_model.with_quantization(quantization))
+    }) {
+        let files = local_model.files().to_vec();
+        if downloads.is_downloaded(&files) {
+            continue;
+        }
+        match downloads.import(dir, &files) {
+            Ok(()) => imported += 1,
+            Err(e) => errors.push(e),
+        }
+    }
+    (imported, errors)
 }
 
-// Add this new function to load the LLMType
-fn load_llm_type() -> std::io::Result<LLMType> {
-    let mut path = dirs::home_dir().expect("Unable to get home directory");
-    path.push(".plugovr");
-    path.push("llm_type.json");
-
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let llm_type: LLMType = serde_json::from_str(&contents)?;
-    Ok(llm_type)
+fn spawn_load_model(
+    model: Arc<Mutex<Option<Llama>>>,
+    source: LlamaSource,
+    download_error: Arc<Mutex<Option<String>>>,
+) {
+    tokio::spawn(async move {
+        match Llama::builder().with_source(source).build().await {
+            Ok(llama) => {
+                if let Ok(mut model_guard) = model.lock() {
+                    *model_guard = Some(llama);
+                } else {
+                    eprintln!("Failed to acquire lock on model");
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to load model: {}", e);
+                *download_error.lock().unwrap() = Some(e.to_string());
+            }
+        }
+    });
 }
 
 impl fmt::Display for LLMType {
@@ -536,9 +932,10 @@ impl fmt::Display for LLMType {
         match self {
             LLMType::Cloud(CloudModel::AnthropicHaiku) => write!(f, "AnthropicHaiku"),
             LLMType::Cloud(CloudModel::AnthropicSonnet3_5) => write!(f, "AnthropicSonnet3_5"),
-            LLMType::Local(LocalModel::Llama32S1bChat) => write!(f, "Llama32S1bChat"),
-            LLMType::Local(LocalModel::Llama32S3bChat) => write!(f, "Llama32S3bChat"),
+            LLMType::Local(local_model) => write!(f, "{}", local_model),
             LLMType::Ollama(model) => write!(f, "{}", model),
+            LLMType::OpenAiCompatible { model, .. } => write!(f, "{}", model),
+            LLMType::CustomGguf(custom_model) => write!(f, "{}", custom_model.name),
         }
     }
 }
@@ -0,0 +1,167 @@
+use crate::llm::knowledge::Snippet;
+use crate::llm::options::GenerationSettings;
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use plugovr_types::Screenshots;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+pub type BackendError = Box<dyn Error + Send + Sync>;
+pub type TokenStream = BoxStream<'static, Result<LlmChunk, BackendError>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FinishReason {
+    Stop,
+    MaxTokens,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LlmChunk {
+    Token(String),
+    Finished(FinishReason),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::System => write!(f, "System"),
+            Role::User => write!(f, "User"),
+            Role::Assistant => write!(f, "Assistant"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConversationMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+impl ConversationMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct LlmRequest {
+    pub context: String,
+    pub instruction: String,
+    pub screenshots: Screenshots,
+    /// Earlier turns of a follow-up conversation, oldest first.
+    pub history: Vec<ConversationMessage>,
+    /// Sampling options, already resolved from global, model and template settings.
+    pub options: GenerationSettings,
+    /// Part of the answer generated before it was cut off. The streamed tokens
+    /// are appended to it when continuing.
+    pub answer_prefix: String,
+    /// Ask the model again even if the response cache has an answer.
+    pub bypass_cache: bool,
+    /// Template the instruction came from, e.g. `@correct`.
+    pub template: Option<String>,
+    /// Snippets of the user's notes added to the prompt.
+    pub knowledge: Vec<Snippet>,
+}
+
+/// A provider that can answer a prompt. Backends stream their answer token by
+/// token and end the stream with a `LlmChunk::Finished`.
+pub trait LlmBackend: Send + Sync {
+    fn name(&self) -> String;
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>>;
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>>;
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>>;
+
+    /// Tokens the model can attend to, prompt and answer together.
+    fn context_window(&self) -> usize {
+        DEFAULT_CONTEXT_WINDOW
+    }
+
+    /// Backends with access to their tokenizer count exactly, the others estimate.
+    fn count_tokens(&self, text: &str) -> usize {
+        estimate_tokens(text)
+    }
+}
+
+pub const DEFAULT_CONTEXT_WINDOW: usize = 4096;
+
+/// Roughly four characters per token for English text.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+pub fn backend_error(msg: impl Into<String>) -> BackendError {
+    Box::new(std::io::Error::other(msg.into()))
+}
+
+/// Runs a request against a backend and mirrors the partial answer into `ai_answer`.
+/// Setting `cancel` to true drops the stream and keeps the partial answer.
+pub async fn generate(
+    backend: &dyn LlmBackend,
+    request: LlmRequest,
+    ai_answer: Arc<Mutex<String>>,
+    mut cancel: watch::Receiver<bool>,
+) -> Result<(String, FinishReason), BackendError> {
+    let response = Arc::new(Mutex::new(request.answer_prefix.clone()));
+    tokio::select! {
+        biased;
+        _ = cancelled(&mut cancel) => {
+            let response = response.lock().unwrap().clone();
+            Ok((response, FinishReason::Cancelled))
+        }
+        result = stream_answer(backend, request, ai_answer, response.clone()) => result,
+    }
+}
+
+async fn stream_answer(
+    backend: &dyn LlmBackend,
+    request: LlmRequest,
+    ai_answer: Arc<Mutex<String>>,
+    response: Arc<Mutex<String>>,
+) -> Result<(String, FinishReason), BackendError> {
+    let mut stream = backend.stream(request).await?;
+    let mut finish_reason = FinishReason::Stop;
+    while let Some(chunk) = stream.next().await {
+        match chunk? {
+            LlmChunk::Token(token) => {
+                let mut response = response.lock().unwrap();
+                response.push_str(&token);
+                *ai_answer.lock().unwrap() = response.clone();
+            }
+            LlmChunk::Finished(reason) => finish_reason = reason,
+        }
+    }
+    let response = response.lock().unwrap().clone();
+    Ok((response, finish_reason))
+}
+
+async fn cancelled(cancel: &mut watch::Receiver<bool>) {
+    while !*cancel.borrow_and_update() {
+        if cancel.changed().await.is_err() {
+            // Nobody can cancel anymore, wait for the answer instead.
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Aborts a producer task once the stream holding it is dropped.
+pub struct AbortOnDrop(pub tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
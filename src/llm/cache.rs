@@ -0,0 +1,214 @@
+use crate::llm::backend::LlmRequest;
+use crate::llm::prompt::build_messages;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    pub enabled: bool,
+    pub max_size_mb: u64,
+    pub ttl_hours: u64,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_size_mb: 50,
+            ttl_hours: 24 * 7,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    /// Everything the key was derived from, compared on lookup so a hash
+    /// collision can never return the answer of another prompt.
+    key: String,
+    created: u64,
+    answer: String,
+}
+
+/// Answers of earlier requests, one json file per request under `~/.plugovr/cache`.
+#[derive(Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    settings: CacheSettings,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf, settings: CacheSettings) -> Self {
+        Self { dir, settings }
+    }
+
+    pub fn default_dir() -> PathBuf {
+        crate::settings::config_path("cache")
+    }
+
+    /// Describes backend, model, generation options, final prompt and
+    /// screenshots of a request.
+    pub fn key(backend: &str, request: &LlmRequest) -> String {
+        let mut key = format!(
+            "{}\n{}\n",
+            backend,
+            serde_json::to_string(&request.options).unwrap_or_default()
+        );
+        for message in build_messages(request) {
+            key += &format!("{}: {}\n", message.role, message.content);
+        }
+        for (screenshot, _) in &request.screenshots {
+            key += &format!(
+                "screenshot {}x{} {:016x}\n",
+                screenshot.width(),
+                screenshot.height(),
+                fnv1a(screenshot.as_raw())
+            );
+        }
+        key += &request.answer_prefix;
+        key
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        if !self.settings.enabled {
+            return None;
+        }
+        let path = self.entry_path(key);
+        let entry: CacheEntry = serde_json::from_str(&std::fs::read_to_string(&path).ok()?).ok()?;
+        if entry.key != key {
+            return None;
+        }
+        if now().saturating_sub(entry.created) > self.ttl().as_secs() {
+            let _ = std::fs::remove_file(path);
+            return None;
+        }
+        Some(entry.answer)
+    }
+
+    pub fn put(&self, key: &str, answer: &str) -> std::io::Result<()> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let entry = CacheEntry {
+            key: key.to_string(),
+            created: now(),
+            answer: answer.to_string(),
+        };
+        std::fs::write(self.entry_path(key), serde_json::to_string(&entry)?)?;
+        self.evict()
+    }
+
+    /// Removes expired entries and then the oldest ones until the cache fits
+    /// into the size limit.
+    pub fn evict(&self) -> std::io::Result<()> {
+        let mut entries = vec![];
+        for file in std::fs::read_dir(&self.dir)? {
+            let file = file?;
+            let metadata = file.metadata()?;
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if age > self.ttl() {
+                std::fs::remove_file(file.path())?;
+            } else {
+                entries.push((modified, metadata.len(), file.path()));
+            }
+        }
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        let max_size = self.settings.max_size_mb * 1024 * 1024;
+        for (_, len, path) in entries {
+            if size <= max_size {
+                break;
+            }
+            std::fs::remove_file(path)?;
+            size -= len;
+        }
+        Ok(())
+    }
+
+    pub fn size(&self) -> u64 {
+        std::fs::read_dir(&self.dir)
+            .map(|files| {
+                files
+                    .flatten()
+                    .filter_map(|file| file.metadata().ok())
+                    .map(|metadata| metadata.len())
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn clear(&self) -> std::io::Result<()> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        Duration::from_secs(self.settings.ttl_hours * 60 * 60)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(key.as_bytes())))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Stable across Rust releases, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(context: &str) -> LlmRequest {
+        LlmRequest {
+            context: context.to_string(),
+            instruction: "Provide a short summary of the text".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_roundtrip_and_miss() {
+        let dir = std::env::temp_dir().join(format!("plugovr_cache_test_{}", std::process::id()));
+        let cache = ResponseCache::new(
+            dir.clone(),
+            CacheSettings {
+                enabled: true,
+                ..Default::default()
+            },
+        );
+        let key = ResponseCache::key("Ollama - llama3.2", &request("some text"));
+        assert_eq!(cache.get(&key), None);
+        cache.put(&key, "a summary").unwrap();
+        assert_eq!(cache.get(&key).as_deref(), Some("a summary"));
+
+        let other_model = ResponseCache::key("Ollama - qwen2.5", &request("some text"));
+        assert_eq!(cache.get(&other_model), None);
+        let other_text = ResponseCache::key("Ollama - llama3.2", &request("other text"));
+        assert_eq!(cache.get(&other_text), None);
+
+        cache.clear().unwrap();
+        assert_eq!(cache.get(&key), None);
+    }
+}
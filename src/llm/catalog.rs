@@ -0,0 +1,228 @@
+use crate::llm::downloads::HfFile;
+use kalosm::language::{FileSource, LlamaSource};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use strum::EnumIter;
+
+#[derive(Clone, Copy, Default, PartialEq, EnumIter, Serialize, Deserialize)]
+pub enum Quantization {
+    #[default]
+    Q4KM,
+    Q8_0,
+}
+
+impl Quantization {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Quantization::Q4KM => "Q4_K_M",
+            Quantization::Q8_0 => "Q8_0",
+        }
+    }
+
+    fn file_suffix(&self) -> &'static str {
+        match self {
+            Quantization::Q4KM => "q4_k_m",
+            Quantization::Q8_0 => "q8_0",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, EnumIter, Serialize, Deserialize)]
+pub enum LocalModel {
+    Llama32S1bChat,
+    Llama32S3bChat,
+    Qwen25S0_5bInstruct(Quantization),
+    Qwen25S1_5bInstruct(Quantization),
+    Qwen25S3bInstruct(Quantization),
+    Mistral7bInstruct(Quantization),
+    Phi35MiniInstruct(Quantization),
+}
+
+impl LocalModel {
+    pub fn description(&self) -> String {
+        let name = match self {
+            LocalModel::Llama32S1bChat => "Llama 3.2 1B Chat",
+            LocalModel::Llama32S3bChat => "Llama 3.2 3B Chat",
+            LocalModel::Qwen25S0_5bInstruct(_) => "Qwen 2.5 0.5B Instruct",
+            LocalModel::Qwen25S1_5bInstruct(_) => "Qwen 2.5 1.5B Instruct",
+            LocalModel::Qwen25S3bInstruct(_) => "Qwen 2.5 3B Instruct",
+            LocalModel::Mistral7bInstruct(_) => "Mistral 7B Instruct v0.3",
+            LocalModel::Phi35MiniInstruct(_) => "Phi 3.5 Mini Instruct",
+        };
+        match self.quantization() {
+            Some(quantization) => format!("{} ({})", name, quantization.description()),
+            None => name.to_string(),
+        }
+    }
+
+    /// None for models that only come in the quantization kalosm ships.
+    pub fn quantization(&self) -> Option<Quantization> {
+        match self {
+            LocalModel::Llama32S1bChat | LocalModel::Llama32S3bChat => None,
+            LocalModel::Qwen25S0_5bInstruct(quantization)
+            | LocalModel::Qwen25S1_5bInstruct(quantization)
+            | LocalModel::Qwen25S3bInstruct(quantization)
+            | LocalModel::Mistral7bInstruct(quantization)
+            | LocalModel::Phi35MiniInstruct(quantization) => Some(*quantization),
+        }
+    }
+
+    pub fn with_quantization(self, quantization: Quantization) -> LocalModel {
+        match self {
+            LocalModel::Llama32S1bChat | LocalModel::Llama32S3bChat => self,
+            LocalModel::Qwen25S0_5bInstruct(_) => LocalModel::Qwen25S0_5bInstruct(quantization),
+            LocalModel::Qwen25S1_5bInstruct(_) => LocalModel::Qwen25S1_5bInstruct(quantization),
+            LocalModel::Qwen25S3bInstruct(_) => LocalModel::Qwen25S3bInstruct(quantization),
+            LocalModel::Mistral7bInstruct(_) => LocalModel::Mistral7bInstruct(quantization),
+            LocalModel::Phi35MiniInstruct(_) => LocalModel::Phi35MiniInstruct(quantization),
+        }
+    }
+
+    /// Tokens we give the model. Below what it was trained on, small models
+    /// lose track of long prompts and the memory use grows with the context.
+    pub fn context_window(&self) -> usize {
+        match self {
+            LocalModel::Llama32S1bChat | LocalModel::Qwen25S0_5bInstruct(_) => 4096,
+            LocalModel::Llama32S3bChat
+            | LocalModel::Qwen25S1_5bInstruct(_)
+            | LocalModel::Qwen25S3bInstruct(_)
+            | LocalModel::Mistral7bInstruct(_)
+            | LocalModel::Phi35MiniInstruct(_) => 8192,
+        }
+    }
+
+    /// The GGUF weights and the tokenizer on HuggingFace.
+    pub fn files(&self) -> ModelFiles {
+        match self {
+            LocalModel::Llama32S1bChat => huggingface_files(
+                "bartowski/Llama-3.2-1B-Instruct-GGUF",
+                "Llama-3.2-1B-Instruct-Q4_K_M.gguf".to_string(),
+                "unsloth/Llama-3.2-1B-Instruct",
+            ),
+            LocalModel::Llama32S3bChat => huggingface_files(
+                "bartowski/Llama-3.2-3B-Instruct-GGUF",
+                "Llama-3.2-3B-Instruct-Q4_K_M.gguf".to_string(),
+                "unsloth/Llama-3.2-3B-Instruct",
+            ),
+            LocalModel::Qwen25S0_5bInstruct(quantization) => qwen_files("0.5B", *quantization),
+            LocalModel::Qwen25S1_5bInstruct(quantization) => qwen_files("1.5B", *quantization),
+            LocalModel::Qwen25S3bInstruct(quantization) => qwen_files("3B", *quantization),
+            LocalModel::Mistral7bInstruct(quantization) => huggingface_files(
+                "bartowski/Mistral-7B-Instruct-v0.3-GGUF",
+                format!(
+                    "Mistral-7B-Instruct-v0.3-{}.gguf",
+                    quantization.description()
+                ),
+                "unsloth/mistral-7b-instruct-v0.3",
+            ),
+            LocalModel::Phi35MiniInstruct(quantization) => huggingface_files(
+                "bartowski/Phi-3.5-mini-instruct-GGUF",
+                format!("Phi-3.5-mini-instruct-{}.gguf", quantization.description()),
+                "microsoft/Phi-3.5-mini-instruct",
+            ),
+        }
+    }
+
+    /// Loads the files the download manager stored in `models_dir`.
+    pub fn source(&self, models_dir: &Path) -> LlamaSource {
+        let files = self.files();
+        LlamaSource::new(FileSource::local(files.model.path(models_dir)))
+            .with_tokenizer(FileSource::local(files.tokenizer.path(models_dir)))
+    }
+}
+
+impl fmt::Display for LocalModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LocalModel::Llama32S1bChat => "Llama32S1bChat",
+            LocalModel::Llama32S3bChat => "Llama32S3bChat",
+            LocalModel::Qwen25S0_5bInstruct(_) => "Qwen25S0_5bInstruct",
+            LocalModel::Qwen25S1_5bInstruct(_) => "Qwen25S1_5bInstruct",
+            LocalModel::Qwen25S3bInstruct(_) => "Qwen25S3bInstruct",
+            LocalModel::Mistral7bInstruct(_) => "Mistral7bInstruct",
+            LocalModel::Phi35MiniInstruct(_) => "Phi35MiniInstruct",
+        };
+        match self.quantization() {
+            Some(quantization) => write!(f, "{}_{}", name, quantization.description()),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Everything needed to run a catalog model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelFiles {
+    pub model: HfFile,
+    pub tokenizer: HfFile,
+}
+
+impl ModelFiles {
+    pub fn to_vec(&self) -> Vec<HfFile> {
+        vec![self.model.clone(), self.tokenizer.clone()]
+    }
+}
+
+fn qwen_files(size: &str, quantization: Quantization) -> ModelFiles {
+    huggingface_files(
+        &format!("Qwen/Qwen2.5-{}-Instruct-GGUF", size),
+        format!(
+            "qwen2.5-{}-instruct-{}.gguf",
+            size.to_lowercase(),
+            quantization.file_suffix()
+        ),
+        &format!("Qwen/Qwen2.5-{}-Instruct", size),
+    )
+}
+
+fn huggingface_files(gguf_repo: &str, gguf_file: String, tokenizer_repo: &str) -> ModelFiles {
+    ModelFiles {
+        model: HfFile::new(gguf_repo, gguf_file),
+        tokenizer: HfFile::new(tokenizer_repo, "tokenizer.json"),
+    }
+}
+
+/// A GGUF file on disk, e.g. a vetted model side-loaded on an offline machine.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomModel {
+    pub name: String,
+    pub model_path: PathBuf,
+    pub tokenizer_path: PathBuf,
+    /// Overrides the chat template stored in the GGUF file.
+    #[serde(default)]
+    pub chat_template: Option<String>,
+}
+
+impl CustomModel {
+    pub fn source(&self) -> LlamaSource {
+        let source = LlamaSource::new(FileSource::local(self.model_path.clone()))
+            .with_tokenizer(FileSource::local(self.tokenizer_path.clone()));
+        match &self.chat_template {
+            Some(chat_template) => source.with_override_chat_template(chat_template.clone()),
+            None => source,
+        }
+    }
+
+    /// Checks the files before the model is registered.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Please enter a name".to_string());
+        }
+        if !self.model_path.is_file() {
+            return Err(format!(
+                "Model file {} not found",
+                self.model_path.display()
+            ));
+        }
+        if self.model_path.extension().and_then(|e| e.to_str()) != Some("gguf") {
+            return Err("The model file must be a .gguf file".to_string());
+        }
+        if !self.tokenizer_path.is_file() {
+            return Err(format!(
+                "Tokenizer file {} not found",
+                self.tokenizer_path.display()
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use crate::llm::backend::{
+    AbortOnDrop, BackendError, FinishReason, LlmBackend, LlmChunk, LlmRequest, TokenStream,
+};
+use crate::llm::error::LlmError;
+use crate::llm::prompt::build_messages;
+use futures::StreamExt;
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// Room for chat template tokens the message contents don't show.
+const PROMPT_MARGIN: usize = 64;
+/// Smallest chunk worth answering, a longer instruction is too long for the
+/// model.
+const MIN_CHUNK_TOKENS: usize = 256;
+/// Paragraphs first, then lines, sentences and words.
+const SEPARATORS: [&str; 4] = ["\n\n", "\n", ". ", " "];
+
+/// How the answers for the chunks of a long selection are put together.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ChunkMode {
+    /// Every chunk is rewritten on its own and the answers are joined.
+    Concatenate,
+    /// The answers are combined by running the template on them again.
+    Reduce,
+}
+
+/// The reason that tells the most about a joined answer, a cut off chunk
+/// makes the whole answer incomplete.
+fn worse(reason: FinishReason, other: FinishReason) -> FinishReason {
+    if reason == FinishReason::Stop {
+        other
+    } else {
+        reason
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkProgress {
+    pub done: usize,
+    pub total: usize,
+    /// The chunk answers are being combined into the final answer.
+    pub reducing: bool,
+}
+
+/// Splits selections that don't fit the context window of the backend and
+/// runs the template on every chunk. Shorter requests pass through unchanged.
+pub struct ChunkedBackend {
+    inner: Arc<dyn LlmBackend>,
+    progress: Arc<Mutex<Option<ChunkProgress>>>,
+}
+
+struct ChunkPlan {
+    mode: ChunkMode,
+    chunks: Vec<String>,
+    chunk_tokens: usize,
+    /// Answer tokens per chunk, together with a chunk they fit the context
+    /// window.
+    max_tokens: u32,
+}
+
+fn context_too_long(message: String) -> BackendError {
+    Box::new(LlmError::ContextTooLong(message))
+}
+
+impl ChunkedBackend {
+    pub fn new(inner: Arc<dyn LlmBackend>, progress: Arc<Mutex<Option<ChunkProgress>>>) -> Self {
+        Self { inner, progress }
+    }
+
+    fn plan(&self, request: &LlmRequest) -> Result<Option<ChunkPlan>, BackendError> {
+        let Some(mode) = request.options.chunking else {
+            return Ok(None);
+        };
+        // Follow-ups and continuations refer to the answer, not the selection.
+        // Joined JSON answers wouldn't match the schema.
+        if request.options.json_schema.is_some()
+            || !request.history.is_empty()
+            || !request.answer_prefix.is_empty()
+            || !request.screenshots.is_empty()
+        {
+            return Ok(None);
+        }
+        let instruction_only = LlmRequest {
+            context: String::new(),
+            ..request.clone()
+        };
+        let overhead = build_messages(&instruction_only)
+            .iter()
+            .map(|message| self.inner.count_tokens(&message.content))
+            .sum::<usize>()
+            + PROMPT_MARGIN;
+        let answer_tokens = request.options.max_tokens.unwrap_or(500) as usize;
+        let available = self.inner.context_window().saturating_sub(overhead);
+        if self.inner.count_tokens(&request.context) + answer_tokens <= available {
+            return Ok(None);
+        }
+        // A chunk and its answer share the room the instruction leaves.
+        let max_tokens = match mode {
+            // A rewrite is about as long as its input.
+            ChunkMode::Concatenate => available / 2,
+            ChunkMode::Reduce => answer_tokens.min(available / 2),
+        };
+        let chunk_tokens = available - max_tokens;
+        if chunk_tokens < MIN_CHUNK_TOKENS {
+            return Err(context_too_long(format!(
+                "The instruction leaves {} of the {} tokens of the context window for the text",
+                available,
+                self.inner.context_window()
+            )));
+        }
+        let count = |text: &str| self.inner.count_tokens(text);
+        Ok(Some(ChunkPlan {
+            mode,
+            chunks: split_into_chunks(&request.context, chunk_tokens, &count),
+            chunk_tokens,
+            max_tokens: max_tokens as u32,
+        }))
+    }
+}
+
+impl LlmBackend for ChunkedBackend {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>> {
+        *self.progress.lock().unwrap() = None;
+        let plan = match self.plan(&request) {
+            Ok(Some(plan)) => plan,
+            Ok(None) => return self.inner.stream(request),
+            Err(e) => return Box::pin(async move { Err(e) }),
+        };
+        let inner = self.inner.clone();
+        let progress = self.progress.clone();
+        Box::pin(async move {
+            let (tx, rx) = mpsc::unbounded();
+            let producer = tokio::spawn(async move {
+                let result = match plan.mode {
+                    ChunkMode::Concatenate => {
+                        concatenate(&*inner, request, plan, &progress, &tx).await
+                    }
+                    ChunkMode::Reduce => reduce(&*inner, request, plan, &progress, &tx).await,
+                };
+                *progress.lock().unwrap() = None;
+                if let Err(e) = result {
+                    let _ = tx.unbounded_send(Err(e));
+                }
+            });
+            let producer = AbortOnDrop(producer);
+            Ok(rx
+                .map(move |chunk| {
+                    let _ = &producer;
+                    chunk
+                })
+                .boxed())
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>> {
+        self.inner.list_models()
+    }
+
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>> {
+        self.inner.health_check()
+    }
+
+    fn context_window(&self) -> usize {
+        self.inner.context_window()
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.inner.count_tokens(text)
+    }
+}
+
+type ChunkSender = mpsc::UnboundedSender<Result<LlmChunk, BackendError>>;
+
+fn chunk_request(request: &LlmRequest, context: String) -> LlmRequest {
+    LlmRequest {
+        context,
+        ..request.clone()
+    }
+}
+
+/// Streams the answer of every chunk as it comes and finishes with the worst
+/// reason of the chunks.
+async fn concatenate(
+    inner: &dyn LlmBackend,
+    request: LlmRequest,
+    plan: ChunkPlan,
+    progress: &Mutex<Option<ChunkProgress>>,
+    tx: &ChunkSender,
+) -> Result<(), BackendError> {
+    let total = plan.chunks.len();
+    let mut finish_reason = FinishReason::Stop;
+    for (done, chunk) in plan.chunks.into_iter().enumerate() {
+        *progress.lock().unwrap() = Some(ChunkProgress {
+            done,
+            total,
+            reducing: false,
+        });
+        if done > 0 {
+            let _ = tx.unbounded_send(Ok(LlmChunk::Token("\n\n".to_string())));
+        }
+        let mut chunk_request = chunk_request(&request, chunk);
+        chunk_request.options.max_tokens = Some(plan.max_tokens);
+        let mut stream = inner.stream(chunk_request).await?;
+        while let Some(chunk) = stream.next().await {
+            match chunk? {
+                LlmChunk::Token(token) => {
+                    let _ = tx.unbounded_send(Ok(LlmChunk::Token(token)));
+                }
+                LlmChunk::Finished(reason) => finish_reason = worse(finish_reason, reason),
+            }
+        }
+    }
+    let _ = tx.unbounded_send(Ok(LlmChunk::Finished(finish_reason)));
+    Ok(())
+}
+
+/// Answers every chunk, then runs the template on the joined answers until
+/// they fit in one request. Only the final answer is streamed.
+async fn reduce(
+    inner: &dyn LlmBackend,
+    request: LlmRequest,
+    plan: ChunkPlan,
+    progress: &Mutex<Option<ChunkProgress>>,
+    tx: &ChunkSender,
+) -> Result<(), BackendError> {
+    let count = |text: &str| inner.count_tokens(text);
+    let mut chunks = plan.chunks;
+    let mut reducing = false;
+    let mut finish_reason = FinishReason::Stop;
+    loop {
+        let total = chunks.len();
+        let mut answers = vec![];
+        for (done, chunk) in chunks.into_iter().enumerate() {
+            *progress.lock().unwrap() = Some(ChunkProgress {
+                done,
+                total,
+                reducing,
+            });
+            let mut chunk_request = chunk_request(&request, chunk);
+            chunk_request.options.max_tokens = Some(plan.max_tokens);
+            let (answer, reason) = collect_answer(inner, chunk_request).await?;
+            finish_reason = worse(finish_reason, reason);
+            answers.push(answer);
+        }
+        let combined = answers.join("\n\n");
+        chunks = split_into_chunks(&combined, plan.chunk_tokens, &count);
+        if chunks.len() > 1 && chunks.len() >= total {
+            return Err(context_too_long(format!(
+                "The answers of the {} chunks don't get shorter than {} chunks",
+                total,
+                chunks.len()
+            )));
+        }
+        if chunks.len() <= 1 {
+            *progress.lock().unwrap() = Some(ChunkProgress {
+                done: total,
+                total,
+                reducing: true,
+            });
+            let mut final_request = chunk_request(&request, combined);
+            final_request.options.max_tokens = Some(plan.max_tokens);
+            let mut stream = inner.stream(final_request).await?;
+            while let Some(chunk) = stream.next().await {
+                match chunk? {
+                    LlmChunk::Finished(reason) => finish_reason = worse(finish_reason, reason),
+                    chunk => {
+                        let _ = tx.unbounded_send(Ok(chunk));
+                    }
+                }
+            }
+            let _ = tx.unbounded_send(Ok(LlmChunk::Finished(finish_reason)));
+            return Ok(());
+        }
+        reducing = true;
+    }
+}
+
+async fn collect_answer(
+    inner: &dyn LlmBackend,
+    request: LlmRequest,
+) -> Result<(String, FinishReason), BackendError> {
+    let mut stream = inner.stream(request).await?;
+    let mut answer = String::new();
+    let mut finish_reason = FinishReason::Stop;
+    while let Some(chunk) = stream.next().await {
+        match chunk? {
+            LlmChunk::Token(token) => answer.push_str(&token),
+            LlmChunk::Finished(reason) => finish_reason = reason,
+        }
+    }
+    Ok((answer.trim().to_string(), finish_reason))
+}
+
+/// Packs paragraphs into chunks of at most `max_tokens`. Paragraphs that are
+/// too long on their own are split at lines, sentences and finally words.
+pub fn split_into_chunks(
+    text: &str,
+    max_tokens: usize,
+    count_tokens: &dyn Fn(&str) -> usize,
+) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut current = String::new();
+    let mut current_tokens = 0;
+    for piece in split_pieces(text, max_tokens, count_tokens, &SEPARATORS) {
+        let tokens = count_tokens(piece);
+        if current_tokens + tokens > max_tokens && !current.trim().is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            current_tokens = 0;
+        }
+        current.push_str(piece);
+        current_tokens += tokens;
+    }
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn split_pieces<'a>(
+    text: &'a str,
+    max_tokens: usize,
+    count_tokens: &dyn Fn(&str) -> usize,
+    separators: &[&str],
+) -> Vec<&'a str> {
+    match separators.split_first() {
+        Some((separator, rest)) if count_tokens(text) > max_tokens => text
+            .split_inclusive(separator)
+            .flat_map(|piece| split_pieces(piece, max_tokens, count_tokens, rest))
+            .collect(),
+        _ => vec![text],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::backend::{DEFAULT_CONTEXT_WINDOW, estimate_tokens, generate};
+    use crate::llm::mock::MockBackend;
+    use crate::llm::options::GenerationSettings;
+    use tokio::sync::watch;
+
+    #[test]
+    fn test_split_keeps_paragraphs_together() {
+        let paragraph = "word ".repeat(40);
+        let text = format!("{paragraph}\n\n{paragraph}\n\n{paragraph}");
+        let chunks = split_into_chunks(&text, 120, &estimate_tokens);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= 120));
+        assert_eq!(chunks.concat(), text);
+
+        let long_paragraph = "word ".repeat(200);
+        let chunks = split_into_chunks(&long_paragraph, 120, &estimate_tokens);
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), long_paragraph);
+    }
+
+    #[tokio::test]
+    async fn test_map_reduce_combines_chunk_answers() {
+        let mut request = LlmRequest {
+            context: "A sentence of a long report.\n\n".repeat(1000),
+            instruction: "Provide a short summary of the text".to_string(),
+            options: GenerationSettings {
+                chunking: Some(ChunkMode::Reduce),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let progress = Arc::new(Mutex::new(None));
+        let backend = ChunkedBackend::new(Arc::new(MockBackend::new("short summary")), progress);
+        assert!(backend.plan(&request).unwrap().unwrap().chunks.len() > 1);
+        let (_cancel, cancel_receiver) = watch::channel(false);
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let (answer, finish_reason) =
+            generate(&backend, request.clone(), ai_answer, cancel_receiver)
+                .await
+                .unwrap();
+        assert_eq!(answer, "short summary");
+        assert_eq!(finish_reason, FinishReason::Stop);
+
+        // A chunk that was cut off makes the joined answer incomplete.
+        request.options.chunking = Some(ChunkMode::Concatenate);
+        let backend = ChunkedBackend::new(
+            Arc::new(MockBackend::new("part").with_finish_reason(FinishReason::MaxTokens)),
+            Arc::new(Mutex::new(None)),
+        );
+        let chunks = backend.plan(&request).unwrap().unwrap().chunks.len();
+        let (_cancel, cancel_receiver) = watch::channel(false);
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let (answer, finish_reason) =
+            generate(&backend, request.clone(), ai_answer, cancel_receiver)
+                .await
+                .unwrap();
+        assert_eq!(answer, vec!["part"; chunks].join("\n\n"));
+        assert_eq!(finish_reason, FinishReason::MaxTokens);
+
+        request.options.json_schema = Some(serde_json::json!({ "type": "object" }));
+        assert!(backend.plan(&request).unwrap().is_none());
+
+        // An instruction that fills the context window leaves no room to chunk.
+        request.options.json_schema = None;
+        request.instruction = "word ".repeat(DEFAULT_CONTEXT_WINDOW);
+        assert!(matches!(
+            backend
+                .plan(&request)
+                .map_err(|e| LlmError::from_backend_error(&e)),
+            Err(LlmError::ContextTooLong(_))
+        ));
+    }
+}
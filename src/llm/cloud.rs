@@ -0,0 +1,174 @@
+use crate::llm::backend::{
+    BackendError, FinishReason, LlmBackend, LlmChunk, LlmRequest, TokenStream,
+};
+use crate::llm::error::LlmError;
+#[cfg(feature = "cs")]
+use crate::llm::prompt::{build_messages, flatten_messages};
+use crate::llm::{CloudModel, LLMType};
+use futures::StreamExt;
+use futures::future::BoxFuture;
+#[cfg(feature = "cs")]
+use plugovr_cs::cloud_llm::call_aws_lambda;
+#[cfg(feature = "cs")]
+use plugovr_cs::user_management::get_user;
+use plugovr_types::UserInfo;
+use std::sync::{Arc, Mutex};
+use strum::IntoEnumIterator;
+
+pub struct CloudBackend {
+    cloud_model: CloudModel,
+    user_info: Arc<Mutex<Option<UserInfo>>>,
+}
+
+impl CloudBackend {
+    pub fn new(cloud_model: CloudModel, user_info: Arc<Mutex<Option<UserInfo>>>) -> Self {
+        Self {
+            cloud_model,
+            user_info,
+        }
+    }
+}
+
+fn login_required(message: &str) -> BackendError {
+    Box::new(LlmError::AuthExpired(message.to_string()))
+}
+
+#[cfg(feature = "cs")]
+fn call_cloud(
+    user_info: Arc<Mutex<Option<UserInfo>>>,
+    model: String,
+    request: LlmRequest,
+) -> Result<(String, bool), BackendError> {
+    let current_user =
+        user_info.lock().unwrap().clone().ok_or_else(|| {
+            login_required("Please login to use cloud LLM or switch to local LLM")
+        })?;
+    let prompt = flatten_messages(&build_messages(&request));
+    let mut result = call_aws_lambda(
+        current_user,
+        prompt.clone(),
+        model.clone(),
+        &request.screenshots,
+    );
+    if result.0.contains("Access token expired") {
+        match get_user() {
+            Ok(user_info_tmp) => {
+                *user_info.lock().unwrap() = Some(user_info_tmp.clone());
+                result = call_aws_lambda(user_info_tmp, prompt, model, &request.screenshots);
+            }
+            Err(_e) => {
+                *user_info.lock().unwrap() = None;
+            }
+        }
+    }
+    if let Some(error) = lambda_error(&result.0) {
+        return Err(Box::new(error));
+    }
+    Ok(result)
+}
+
+/// The lambda answers failures instead of raising them: an expired session as
+/// text, other errors as JSON with a status code and an error message.
+#[cfg(feature = "cs")]
+fn lambda_error(answer: &str) -> Option<LlmError> {
+    if answer.contains("Access token expired") {
+        return Some(LlmError::AuthExpired(
+            "Access token expired, please login again".to_string(),
+        ));
+    }
+    let value = serde_json::from_str::<serde_json::Value>(answer.trim()).ok()?;
+    let response = value.as_object()?;
+    let status = ["statusCode", "status"]
+        .iter()
+        .find_map(|key| response.get(*key)?.as_u64());
+    // `errorMessage` is what AWS returns when the function itself fails, a
+    // JSON answer of the model has more than an error field.
+    let error = response
+        .get("errorMessage")
+        .or_else(|| response.get("error").filter(|_| response.len() <= 2))
+        .map(|error| {
+            error
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or(error.to_string())
+        });
+    if error.is_none() && status.is_none_or(|status| status < 400) {
+        return None;
+    }
+    let message = error.unwrap_or_else(|| {
+        format!(
+            "The cloud answered with status {}",
+            status.unwrap_or_default()
+        )
+    });
+    Some(match status {
+        Some(status) => LlmError::from_status(status as u16, message),
+        None => LlmError::Other(message),
+    })
+}
+
+#[cfg(not(feature = "cs"))]
+fn call_cloud(
+    _user_info: Arc<Mutex<Option<UserInfo>>>,
+    _model: String,
+    _request: LlmRequest,
+) -> Result<(String, bool), BackendError> {
+    Err(login_required(
+        "Please login to use cloud LLM: download PlugOvr from https://plugovr.ai",
+    ))
+}
+
+/// Both Anthropic models accept 200k tokens.
+const CLOUD_CONTEXT_WINDOW: usize = 200_000;
+
+impl LlmBackend for CloudBackend {
+    fn name(&self) -> String {
+        LLMType::Cloud(self.cloud_model).description()
+    }
+
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>> {
+        let user_info = self.user_info.clone();
+        let model = LLMType::Cloud(self.cloud_model).to_string();
+        Box::pin(async move {
+            // The lambda call is blocking and answers in one piece.
+            let (answer, max_tokens_reached) =
+                tokio::task::spawn_blocking(move || call_cloud(user_info, model, request))
+                    .await??;
+            let finish_reason = if max_tokens_reached {
+                FinishReason::MaxTokens
+            } else {
+                FinishReason::Stop
+            };
+            Ok(futures::stream::iter(vec![
+                Ok(LlmChunk::Token(answer)),
+                Ok(LlmChunk::Finished(finish_reason)),
+            ])
+            .boxed())
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>> {
+        Box::pin(async move {
+            Ok(CloudModel::iter()
+                .map(|cloud_model| LLMType::Cloud(cloud_model).to_string())
+                .collect())
+        })
+    }
+
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>> {
+        let logged_in = self.user_info.lock().unwrap().is_some();
+        Box::pin(async move {
+            if logged_in {
+                Ok(())
+            } else {
+                Err(login_required(
+                    "Please login to use cloud LLM or switch to local LLM",
+                ))
+            }
+        })
+    }
+
+    fn context_window(&self) -> usize {
+        CLOUD_CONTEXT_WINDOW
+    }
+}
@@ -0,0 +1,499 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const HUGGINGFACE_URL: &str = "https://huggingface.co";
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+/// A file in the main branch of a HuggingFace repository.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HfFile {
+    pub repo: String,
+    pub file: String,
+}
+
+impl HfFile {
+    pub fn new(repo: &str, file: impl Into<String>) -> Self {
+        Self {
+            repo: repo.to_string(),
+            file: file.into(),
+        }
+    }
+
+    fn url(&self) -> String {
+        format!(
+            "{}/{}/resolve/main/{}",
+            HUGGINGFACE_URL, self.repo, self.file
+        )
+    }
+
+    /// `<models dir>/<owner>/<repo>/<file>`
+    pub fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(&self.repo).join(&self.file)
+    }
+}
+
+/// Where the data is written until the download is complete. Its size is the
+/// offset to resume from.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    PathBuf::from(partial)
+}
+
+/// The sha256 the file was verified against, kept to verify it again later.
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut checksum = path.as_os_str().to_owned();
+    checksum.push(".sha256");
+    PathBuf::from(checksum)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DownloadState {
+    Downloading {
+        file: String,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    Verifying,
+    /// The check the user asked for passed. Without a checksum only the file
+    /// format could be checked.
+    Verified {
+        checksum: bool,
+    },
+    Failed(String),
+}
+
+impl DownloadState {
+    pub fn progress(&self) -> f32 {
+        match self {
+            DownloadState::Downloading {
+                downloaded,
+                total: Some(total),
+                ..
+            } if *total > 0 => *downloaded as f32 / *total as f32,
+            DownloadState::Verifying => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Downloads model files into `~/.plugovr/models`, several models at a time.
+/// Interrupted downloads resume where they stopped and every file is checked
+/// against the sha256 HuggingFace publishes before it is used.
+#[derive(Clone)]
+pub struct DownloadManager {
+    dir: PathBuf,
+    /// Running and failed downloads by model key, finished ones are removed.
+    downloads: Arc<Mutex<HashMap<String, DownloadState>>>,
+    tasks: Arc<Mutex<HashMap<String, tokio::task::AbortHandle>>>,
+}
+
+impl DownloadManager {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            downloads: Arc::new(Mutex::new(HashMap::new())),
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn default_dir() -> PathBuf {
+        crate::settings::config_path("models")
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn state(&self, key: &str) -> Option<DownloadState> {
+        self.downloads.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn is_downloading(&self, key: &str) -> bool {
+        self.tasks.lock().unwrap().contains_key(key)
+    }
+
+    pub fn is_downloaded(&self, files: &[HfFile]) -> bool {
+        files.iter().all(|file| file.path(&self.dir).is_file())
+    }
+
+    /// True if a GGUF file has no recorded sha256, because HuggingFace didn't
+    /// publish one or it was imported without one. Only its format was checked.
+    pub fn is_unverified(&self, files: &[HfFile]) -> bool {
+        files.iter().any(|file| {
+            file.file.ends_with(".gguf") && !checksum_path(&file.path(&self.dir)).is_file()
+        })
+    }
+
+    /// True if a download was interrupted and can be resumed.
+    pub fn has_partial(&self, files: &[HfFile]) -> bool {
+        files
+            .iter()
+            .any(|file| partial_path(&file.path(&self.dir)).is_file())
+    }
+
+    /// Bytes on disk, including unfinished downloads.
+    pub fn disk_usage(&self, files: &[HfFile]) -> u64 {
+        files
+            .iter()
+            .flat_map(|file| {
+                let path = file.path(&self.dir);
+                [partial_path(&path), path]
+            })
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    /// Starts or resumes the download of a model unless it is already running.
+    pub fn start(&self, key: String, files: Vec<HfFile>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        if tasks.contains_key(&key) {
+            return;
+        }
+        let dir = self.dir.clone();
+        let downloads = self.downloads.clone();
+        let task_list = self.tasks.clone();
+        let task_key = key.clone();
+        let handle = tokio::spawn(async move {
+            let mut result = Ok(());
+            for file in &files {
+                result = download_file(file, &dir, &task_key, &downloads).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+            match result {
+                Ok(()) => {
+                    downloads.lock().unwrap().remove(&task_key);
+                }
+                Err(e) => {
+                    eprintln!("Failed to download {}: {}", task_key, e);
+                    downloads
+                        .lock()
+                        .unwrap()
+                        .insert(task_key.clone(), DownloadState::Failed(e));
+                }
+            }
+            task_list.lock().unwrap().remove(&task_key);
+        });
+        tasks.insert(key, handle.abort_handle());
+    }
+
+    /// Stops a download, the partial file stays so it can be resumed.
+    pub fn pause(&self, key: &str) {
+        if let Some(task) = self.tasks.lock().unwrap().remove(key) {
+            task.abort();
+        }
+        self.downloads.lock().unwrap().remove(key);
+    }
+
+    pub fn delete(&self, key: &str, files: &[HfFile]) -> std::io::Result<()> {
+        self.pause(key);
+        for file in files {
+            let path = file.path(&self.dir);
+            for path in [partial_path(&path), checksum_path(&path), path] {
+                match std::fs::remove_file(&path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `verify` in the background, hashing gigabytes takes a while. The
+    /// result becomes the state of `key`.
+    pub fn start_verify(&self, key: String, files: Vec<HfFile>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        if tasks.contains_key(&key) {
+            return;
+        }
+        self.downloads
+            .lock()
+            .unwrap()
+            .insert(key.clone(), DownloadState::Verifying);
+        let manager = self.clone();
+        let task_key = key.clone();
+        let handle = tokio::spawn(async move {
+            let checksum = !manager.is_unverified(&files);
+            let verifier = manager.clone();
+            let result = tokio::task::spawn_blocking(move || verifier.verify(&files))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            let state = match result {
+                Ok(()) => DownloadState::Verified { checksum },
+                Err(e) => DownloadState::Failed(e),
+            };
+            manager
+                .downloads
+                .lock()
+                .unwrap()
+                .insert(task_key.clone(), state);
+            manager.tasks.lock().unwrap().remove(&task_key);
+        });
+        tasks.insert(key, handle.abort_handle());
+    }
+
+    /// Checks the format of every file and its sha256 if one was recorded.
+    pub fn verify(&self, files: &[HfFile]) -> Result<(), String> {
+        for file in files {
+            let path = file.path(&self.dir);
+            check_format(&path)?;
+            if let Ok(expected) = std::fs::read_to_string(checksum_path(&path)) {
+                check_sha256(&path, expected.trim())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the files of a model from a directory, e.g. a USB drive on an
+    /// air-gapped machine. Subdirectories are searched as well, so a copy of
+    /// another model cache works. A `<file>.sha256` next to a file is checked.
+    pub fn import(&self, source_dir: &Path, files: &[HfFile]) -> Result<(), String> {
+        let sources = files
+            .iter()
+            .map(|file| {
+                find_file(source_dir, file)
+                    .ok_or_else(|| format!("{} not found in {}", file.file, source_dir.display()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (file, source) in files.iter().zip(sources) {
+            let path = file.path(&self.dir);
+            if path.is_file() {
+                continue;
+            }
+            check_format(&source)?;
+            let expected = std::fs::read_to_string(checksum_path(&source)).ok();
+            if let Some(expected) = &expected {
+                check_sha256(&source, expected.trim())?;
+            }
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            let partial = partial_path(&path);
+            // A hard link saves copying gigabytes within the same disk.
+            let _ = std::fs::remove_file(&partial);
+            if std::fs::hard_link(&source, &partial).is_err() {
+                std::fs::copy(&source, &partial).map_err(|e| e.to_string())?;
+            }
+            if let Some(expected) = &expected {
+                std::fs::write(checksum_path(&path), expected.trim()).map_err(|e| e.to_string())?;
+            }
+            std::fs::rename(&partial, &path).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Where kalosm keeps its downloads. Older versions of PlugOvr let kalosm
+/// download the Llama models there, as `<owner>/<repo>/main/<file>`.
+pub fn kalosm_cache_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("kalosm").join("cache"))
+}
+
+/// Looks for `file` in `dir` and its subdirectories. Many repositories ship a
+/// `tokenizer.json`, so a copy below a directory named like the repository
+/// is preferred.
+fn find_file(dir: &Path, file: &HfFile) -> Option<PathBuf> {
+    let mut matches = vec![];
+    collect_files(dir, &file.file, &mut matches);
+    let repo_name = file.repo.rsplit('/').next().unwrap_or(&file.repo);
+    matches
+        .iter()
+        .find(|path| path.to_string_lossy().contains(repo_name))
+        .or(matches.first())
+        .cloned()
+}
+
+fn collect_files(dir: &Path, file_name: &str, matches: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.is_dir() {
+            collect_files(&path, file_name, matches);
+        } else if path.file_name().and_then(|name| name.to_str()) == Some(file_name) {
+            matches.push(path);
+        }
+    }
+}
+
+/// Asks HuggingFace for the sha256 of a file. The resolve URL redirects to a
+/// CDN, the checksum is only in the headers of the redirect.
+async fn remote_sha256(file: &HfFile) -> Option<String> {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .ok()?;
+    let response = client.head(file.url()).send().await.ok()?;
+    let etag = response.headers().get("x-linked-etag")?.to_str().ok()?;
+    let etag = etag
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .to_lowercase();
+    (etag.len() == 64 && etag.chars().all(|c| c.is_ascii_hexdigit())).then_some(etag)
+}
+
+async fn download_file(
+    file: &HfFile,
+    dir: &Path,
+    key: &str,
+    downloads: &Mutex<HashMap<String, DownloadState>>,
+) -> Result<(), String> {
+    let path = file.path(dir);
+    if path.is_file() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let partial = partial_path(&path);
+    let offset = std::fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+    let expected = remote_sha256(file).await;
+    if expected.is_none() && file.file.ends_with(".gguf") {
+        eprintln!(
+            "No sha256 published for {}, only its format is checked",
+            file.file
+        );
+    }
+
+    let mut request = reqwest::Client::new().get(file.url());
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+    }
+    let mut response = request.send().await.map_err(|e| e.to_string())?;
+    // The download was interrupted after the last byte, only the rename is
+    // missing. The verification below tells whether the file is complete.
+    let complete = offset > 0 && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE;
+    if !complete {
+        response = response.error_for_status().map_err(|e| e.to_string())?;
+        // Servers that ignore the range send the whole file again.
+        let resumed = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+        let mut downloaded = if resumed { offset } else { 0 };
+        let total = response.content_length().map(|length| length + downloaded);
+        let mut output = if resumed {
+            OpenOptions::new().append(true).open(&partial)
+        } else {
+            File::create(&partial)
+        }
+        .map_err(|e| e.to_string())?;
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            output.write_all(&chunk).map_err(|e| e.to_string())?;
+            downloaded += chunk.len() as u64;
+            downloads.lock().unwrap().insert(
+                key.to_string(),
+                DownloadState::Downloading {
+                    file: file.file.clone(),
+                    downloaded,
+                    total,
+                },
+            );
+        }
+        output.flush().map_err(|e| e.to_string())?;
+    }
+
+    downloads
+        .lock()
+        .unwrap()
+        .insert(key.to_string(), DownloadState::Verifying);
+    let verify_path = partial.clone();
+    let verify_expected = expected.clone();
+    let verified = tokio::task::spawn_blocking(move || {
+        check_format(&verify_path)?;
+        match &verify_expected {
+            Some(expected) => check_sha256(&verify_path, expected),
+            None => Ok(()),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    if let Err(e) = verified {
+        // A corrupt partial file would fail again on resume.
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    if let Some(expected) = expected {
+        std::fs::write(checksum_path(&path), expected).map_err(|e| e.to_string())?;
+    }
+    std::fs::rename(&partial, &path).map_err(|e| e.to_string())
+}
+
+/// Catches truncated or wrong files: GGUF files start with a magic number and
+/// tokenizers are JSON.
+fn check_format(path: &Path) -> Result<(), String> {
+    let name = path.display();
+    let mut file = File::open(path).map_err(|e| format!("{}: {}", name, e))?;
+    let file_name = path.to_string_lossy().trim_end_matches(".part").to_string();
+    if file_name.ends_with(".gguf") {
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)
+            .map_err(|_| format!("{} is not a GGUF file", name))?;
+        if &magic != GGUF_MAGIC {
+            return Err(format!("{} is not a GGUF file", name));
+        }
+    } else if file_name.ends_with(".json") {
+        serde_json::from_reader::<_, serde_json::Value>(std::io::BufReader::new(file))
+            .map_err(|e| format!("{} is not valid JSON: {}", name, e))?;
+    }
+    Ok(())
+}
+
+fn check_sha256(path: &Path, expected: &str) -> Result<(), String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| e.to_string())?;
+    let actual = format!("{:x}", hasher.finalize());
+    if actual != expected.to_lowercase() {
+        return Err(format!(
+            "{} is corrupt: sha256 {} does not match {}",
+            path.display(),
+            actual,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_verify_and_delete() {
+        let root = std::env::temp_dir().join(format!("plugovr-models-{}", uuid::Uuid::new_v4()));
+        let usb = root.join("usb").join("nested");
+        std::fs::create_dir_all(&usb).unwrap();
+        std::fs::write(usb.join("model.gguf"), b"GGUF and some weights").unwrap();
+        std::fs::write(usb.join("tokenizer.json"), b"{\"model\": {}}").unwrap();
+        let files = vec![
+            HfFile::new("owner/model-GGUF", "model.gguf"),
+            HfFile::new("owner/model", "tokenizer.json"),
+        ];
+
+        let manager = DownloadManager::new(root.join("models"));
+        assert!(!manager.is_downloaded(&files));
+        manager.import(&root.join("usb"), &files).unwrap();
+        assert!(manager.is_downloaded(&files));
+        assert!(manager.is_unverified(&files));
+        assert_eq!(manager.verify(&files), Ok(()));
+        assert_eq!(manager.disk_usage(&files), 34);
+
+        let model_path = files[0].path(manager.dir());
+        std::fs::write(checksum_path(&model_path), "0".repeat(64)).unwrap();
+        assert!(!manager.is_unverified(&files));
+        assert!(manager.verify(&files).is_err());
+
+        manager.delete("model", &files).unwrap();
+        assert!(!manager.is_downloaded(&files));
+        assert_eq!(manager.disk_usage(&files), 0);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+use crate::llm::backend::BackendError;
+use crate::llm::fallback::FallbackError;
+use std::error::Error;
+use std::fmt;
+
+/// Why a request failed. Shown next to the answer, never inside it, so an
+/// error can't end up in the user's document.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LlmError {
+    /// The server of the backend can't be reached.
+    ConnectionRefused(String),
+    /// The model is not installed, not downloaded or not loaded yet.
+    ModelMissing(String),
+    /// Not logged in, the session expired or the API key was rejected.
+    AuthExpired(String),
+    Timeout(String),
+    /// The prompt does not fit the context window of the model.
+    ContextTooLong(String),
+    /// The answer doesn't match the JSON schema of the template.
+    InvalidAnswer(String),
+    /// The active profile only allows local models.
+    NotAllowed(String),
+    Other(String),
+}
+
+impl LlmError {
+    /// Sorts a backend error into a category by its source chain and message.
+    /// Backends report errors of different crates, most only as text.
+    pub fn from_backend_error(error: &BackendError) -> LlmError {
+        let message = error.to_string();
+        // The selected model decides, the details list every attempt.
+        if let Some((_, primary)) = error
+            .downcast_ref::<FallbackError>()
+            .and_then(|error| error.attempts.first())
+        {
+            return LlmError::from_backend_error(primary).with_details(message);
+        }
+        let error: &(dyn Error + 'static) = error.as_ref();
+        let mut source = Some(error);
+        while let Some(error) = source {
+            // Backends that know the category report it directly.
+            if let Some(error) = error.downcast_ref::<LlmError>() {
+                return error.clone();
+            }
+            if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+                if error.is_timeout() {
+                    return LlmError::Timeout(message);
+                }
+                if error.is_connect() {
+                    return LlmError::ConnectionRefused(message);
+                }
+                if let Some(status) = error.status() {
+                    return LlmError::from_status(status.as_u16(), message);
+                }
+            }
+            if let Some(error) = error.downcast_ref::<std::io::Error>() {
+                match error.kind() {
+                    std::io::ErrorKind::ConnectionRefused => {
+                        return LlmError::ConnectionRefused(message);
+                    }
+                    std::io::ErrorKind::TimedOut => return LlmError::Timeout(message),
+                    _ => {}
+                }
+            }
+            source = error.source();
+        }
+        LlmError::from_message(message)
+    }
+
+    fn with_details(self, details: String) -> LlmError {
+        match self {
+            LlmError::ConnectionRefused(_) => LlmError::ConnectionRefused(details),
+            LlmError::ModelMissing(_) => LlmError::ModelMissing(details),
+            LlmError::AuthExpired(_) => LlmError::AuthExpired(details),
+            LlmError::Timeout(_) => LlmError::Timeout(details),
+            LlmError::ContextTooLong(_) => LlmError::ContextTooLong(details),
+            LlmError::InvalidAnswer(_) => LlmError::InvalidAnswer(details),
+            LlmError::NotAllowed(_) => LlmError::NotAllowed(details),
+            LlmError::Other(_) => LlmError::Other(details),
+        }
+    }
+
+    /// Sorts an HTTP error status, `message` decides for the statuses that
+    /// servers use for several problems, e.g. 400.
+    pub fn from_status(status: u16, message: String) -> LlmError {
+        match status {
+            401 | 403 => LlmError::AuthExpired(message),
+            404 => LlmError::ModelMissing(message),
+            408 | 504 => LlmError::Timeout(message),
+            413 => LlmError::ContextTooLong(message),
+            _ => LlmError::from_message(message),
+        }
+    }
+
+    /// The last resort for errors that only come as text, e.g. the error
+    /// field of an Ollama or OpenAI response. Only phrases these servers use
+    /// for one problem are matched.
+    fn from_message(message: String) -> LlmError {
+        let lower = message.to_lowercase();
+        let contains_any = |patterns: &[&str]| patterns.iter().any(|p| lower.contains(p));
+        if contains_any(&[
+            "maximum context length",
+            "context length exceeded",
+            "exceeds the context window",
+            "prompt is too long",
+        ]) {
+            LlmError::ContextTooLong(message)
+        } else if contains_any(&["connection refused"]) {
+            LlmError::ConnectionRefused(message)
+        } else if contains_any(&["timed out"]) {
+            LlmError::Timeout(message)
+        } else if contains_any(&[
+            "invalid api key",
+            "incorrect api key",
+            "access token expired",
+        ]) {
+            LlmError::AuthExpired(message)
+        } else if contains_any(&["try pulling it", "no such model", "model not found"]) {
+            LlmError::ModelMissing(message)
+        } else {
+            LlmError::Other(message)
+        }
+    }
+
+    /// Stable identifier for API clients.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LlmError::ConnectionRefused(_) => "connection_refused",
+            LlmError::ModelMissing(_) => "model_missing",
+            LlmError::AuthExpired(_) => "auth_expired",
+            LlmError::Timeout(_) => "timeout",
+            LlmError::ContextTooLong(_) => "context_too_long",
+            LlmError::InvalidAnswer(_) => "invalid_answer",
+            LlmError::NotAllowed(_) => "not_allowed",
+            LlmError::Other(_) => "other",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            LlmError::ConnectionRefused(_) => "The model server is not reachable",
+            LlmError::ModelMissing(_) => "The model is not available",
+            LlmError::AuthExpired(_) => "Login required",
+            LlmError::Timeout(_) => "The model took too long to answer",
+            LlmError::ContextTooLong(_) => "The text is too long for this model",
+            LlmError::InvalidAnswer(_) => "The answer has the wrong format",
+            LlmError::NotAllowed(_) => "The model is not allowed in this profile",
+            LlmError::Other(_) => "The request failed",
+        }
+    }
+
+    /// What the user can do about it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            LlmError::ConnectionRefused(_) => {
+                "Check that the server (e.g. Ollama) is running, or switch to another model."
+            }
+            LlmError::ModelMissing(_) => {
+                "Download or pull the model in the model selection, or switch to another model."
+            }
+            LlmError::AuthExpired(_) => "Log in again or check the API key.",
+            LlmError::Timeout(_) => "Retry, or switch to a smaller or faster model.",
+            LlmError::ContextTooLong(_) => {
+                "Select less text or switch to a model with a larger context window."
+            }
+            LlmError::InvalidAnswer(_) => "Retry, or switch to a model that follows the format.",
+            LlmError::NotAllowed(_) => "Switch to a local model or to another profile.",
+            LlmError::Other(_) => "Retry, or switch to another model.",
+        }
+    }
+
+    pub fn details(&self) -> &str {
+        match self {
+            LlmError::ConnectionRefused(details)
+            | LlmError::ModelMissing(details)
+            | LlmError::AuthExpired(details)
+            | LlmError::Timeout(details)
+            | LlmError::ContextTooLong(details)
+            | LlmError::InvalidAnswer(details)
+            | LlmError::NotAllowed(details)
+            | LlmError::Other(details) => details,
+        }
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.title(), self.details())
+    }
+}
+
+impl Error for LlmError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::backend::backend_error;
+
+    #[test]
+    fn test_errors_are_categorized() {
+        let refused: BackendError = Box::new(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "connect failed",
+        ));
+        assert!(matches!(
+            LlmError::from_backend_error(&refused),
+            LlmError::ConnectionRefused(_)
+        ));
+        assert!(matches!(
+            LlmError::from_backend_error(&backend_error(
+                "model \"llama9\" not found, try pulling it first"
+            )),
+            LlmError::ModelMissing(_)
+        ));
+        assert!(matches!(
+            LlmError::from_backend_error(&backend_error(
+                "This model's maximum context length is 8192 tokens"
+            )),
+            LlmError::ContextTooLong(_)
+        ));
+        assert!(matches!(
+            LlmError::from_backend_error(&backend_error("Local model is busy")),
+            LlmError::Other(_)
+        ));
+        // Numbers and words that merely appear in a message don't decide.
+        assert!(matches!(
+            LlmError::from_backend_error(&backend_error(
+                "Request 4011 failed: pull request template not found"
+            )),
+            LlmError::Other(_)
+        ));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::llm::backend::{
+    BackendError, DEFAULT_CONTEXT_WINDOW, LlmBackend, LlmRequest, TokenStream, backend_error,
+    estimate_tokens,
+};
+use crate::llm::error::LlmError;
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const DEFAULT_FALLBACK_TIMEOUT_SECS: u64 = 30;
+
+/// Tries the backends in order until one starts answering. A backend that
+/// can't be reached, whose model is missing or still downloading, or that
+/// doesn't accept the request in time hands over to the next one. Other
+/// errors, e.g. a prompt that is too long, would fail everywhere and are
+/// returned right away.
+pub struct FallbackBackend {
+    backends: Vec<Arc<dyn LlmBackend>>,
+    /// Name of the backend that answered and whether it was a fallback.
+    answered_by: Arc<Mutex<Option<(String, bool)>>>,
+    /// How long a backend may take to open its stream. Waiting for the first
+    /// token isn't limited, a large local model may take long to load.
+    connect_timeout: Duration,
+    /// The selected model can't be used (e.g. not logged in), every backend
+    /// is a fallback.
+    primary_skipped: bool,
+}
+
+impl FallbackBackend {
+    pub fn new(
+        backends: Vec<Arc<dyn LlmBackend>>,
+        answered_by: Arc<Mutex<Option<(String, bool)>>>,
+        connect_timeout: Duration,
+        primary_skipped: bool,
+    ) -> Self {
+        Self {
+            backends,
+            answered_by,
+            connect_timeout,
+            primary_skipped,
+        }
+    }
+}
+
+/// Every backend that was tried failed. The error of the first one is the
+/// source, so the request fails for the reason of the selected model.
+#[derive(Debug)]
+pub struct FallbackError {
+    /// Name of each backend tried and its error, in order.
+    pub attempts: Vec<(String, BackendError)>,
+}
+
+impl fmt::Display for FallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (name, error)) in self.attempts.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", name, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for FallbackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.attempts
+            .first()
+            .map(|(_, error)| error.as_ref() as &(dyn Error + 'static))
+    }
+}
+
+/// Errors after which the next backend may well succeed.
+fn falls_back(error: &BackendError) -> bool {
+    matches!(
+        LlmError::from_backend_error(error),
+        LlmError::ConnectionRefused(_) | LlmError::ModelMissing(_) | LlmError::Timeout(_)
+    )
+}
+
+/// Waits for the first chunk so errors of a stream that opened fine still
+/// count as a failed backend.
+async fn first_chunk(mut stream: TokenStream) -> Result<TokenStream, BackendError> {
+    match stream.next().await {
+        Some(Err(e)) => Err(e),
+        Some(Ok(chunk)) => Ok(futures::stream::once(async { Ok(chunk) })
+            .chain(stream)
+            .boxed()),
+        None => Ok(futures::stream::empty().boxed()),
+    }
+}
+
+impl LlmBackend for FallbackBackend {
+    fn name(&self) -> String {
+        self.backends
+            .first()
+            .map(|backend| backend.name())
+            .unwrap_or_default()
+    }
+
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>> {
+        let backends = self.backends.clone();
+        let answered_by = self.answered_by.clone();
+        let connect_timeout = self.connect_timeout;
+        let primary_skipped = self.primary_skipped;
+        Box::pin(async move {
+            *answered_by.lock().unwrap() = None;
+            let mut attempts = vec![];
+            for (index, backend) in backends.iter().enumerate() {
+                let last = index + 1 == backends.len();
+                let stream = backend.stream(request.clone());
+                let stream = if last {
+                    stream.await
+                } else {
+                    tokio::time::timeout(connect_timeout, stream)
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(Box::new(std::io::Error::new(
+                                std::io::ErrorKind::TimedOut,
+                                format!("No connection within {} s", connect_timeout.as_secs()),
+                            )))
+                        })
+                };
+                let result = match stream {
+                    Ok(stream) => first_chunk(stream).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(stream) => {
+                        *answered_by.lock().unwrap() =
+                            Some((backend.name(), index > 0 || primary_skipped));
+                        return Ok(stream);
+                    }
+                    Err(e) if !last && falls_back(&e) => {
+                        eprintln!("{} failed, trying next backend: {}", backend.name(), e);
+                        attempts.push((backend.name(), e));
+                    }
+                    Err(e) if attempts.is_empty() => return Err(e),
+                    Err(e) => {
+                        attempts.push((backend.name(), e));
+                        break;
+                    }
+                }
+            }
+            Err(Box::new(FallbackError { attempts }))
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>> {
+        let backends = self.backends.clone();
+        Box::pin(async move {
+            let mut models = vec![];
+            for backend in backends {
+                models.extend(backend.list_models().await.unwrap_or_default());
+            }
+            Ok(models)
+        })
+    }
+
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>> {
+        let backends = self.backends.clone();
+        Box::pin(async move {
+            for backend in &backends {
+                if backend.health_check().await.is_ok() {
+                    return Ok(());
+                }
+            }
+            Err(backend_error("No backend is available"))
+        })
+    }
+
+    /// Sized for the preferred backend, a fallback may have to cope with it.
+    fn context_window(&self) -> usize {
+        self.backends
+            .first()
+            .map(|backend| backend.context_window())
+            .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.backends
+            .first()
+            .map(|backend| backend.count_tokens(text))
+            .unwrap_or_else(|| estimate_tokens(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::backend::generate;
+    use crate::llm::chunking::ChunkedBackend;
+    use crate::llm::error::LlmError;
+    use crate::llm::metrics::MeteredBackend;
+    use crate::llm::mock::MockBackend;
+    use tokio::sync::watch;
+
+    #[tokio::test]
+    async fn test_falls_back_on_connection_error() {
+        let answered_by = Arc::new(Mutex::new(None));
+        let backend = FallbackBackend::new(
+            vec![
+                Arc::new(MockBackend::failing("connection refused")),
+                Arc::new(MockBackend::new("answer of the fallback")),
+            ],
+            answered_by.clone(),
+            Duration::from_secs(DEFAULT_FALLBACK_TIMEOUT_SECS),
+            false,
+        );
+        let request = LlmRequest {
+            instruction: "hello".to_string(),
+            ..Default::default()
+        };
+        let (_cancel, cancel_receiver) = watch::channel(false);
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let (answer, _) = generate(&backend, request, ai_answer, cancel_receiver)
+            .await
+            .unwrap();
+        assert_eq!(answer, "answer of the fallback");
+        assert_eq!(
+            *answered_by.lock().unwrap(),
+            Some(("Mock".to_string(), true))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failure_keeps_the_error_of_the_selected_backend() {
+        let metered = |backend: MockBackend| -> Arc<dyn LlmBackend> {
+            Arc::new(MeteredBackend::new(Arc::new(backend), "Mock".to_string()))
+        };
+        let backend = ChunkedBackend::new(
+            Arc::new(FallbackBackend::new(
+                vec![
+                    metered(MockBackend::refusing()),
+                    metered(MockBackend::failing(
+                        "model \"llama9\" not found, try pulling it first",
+                    )),
+                ],
+                Arc::new(Mutex::new(None)),
+                Duration::from_secs(DEFAULT_FALLBACK_TIMEOUT_SECS),
+                false,
+            )),
+            Arc::new(Mutex::new(None)),
+        );
+        let (_cancel, cancel_receiver) = watch::channel(false);
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let error = generate(&backend, LlmRequest::default(), ai_answer, cancel_receiver)
+            .await
+            .unwrap_err();
+        let attempts = &error.downcast_ref::<FallbackError>().unwrap().attempts;
+        assert_eq!(attempts.len(), 2);
+        assert!(matches!(
+            LlmError::from_backend_error(&error),
+            LlmError::ConnectionRefused(_)
+        ));
+
+        // Errors that would fail everywhere don't fall back.
+        let backend = FallbackBackend::new(
+            vec![
+                metered(MockBackend::failing("maximum context length exceeded")),
+                metered(MockBackend::new("never asked")),
+            ],
+            Arc::new(Mutex::new(None)),
+            Duration::from_secs(DEFAULT_FALLBACK_TIMEOUT_SECS),
+            false,
+        );
+        let (_cancel, cancel_receiver) = watch::channel(false);
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let result = generate(&backend, LlmRequest::default(), ai_answer, cancel_receiver).await;
+        assert!(matches!(
+            result.map_err(|e| LlmError::from_backend_error(&e)),
+            Err(LlmError::ContextTooLong(_))
+        ));
+    }
+}
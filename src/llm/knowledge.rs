@@ -0,0 +1,265 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Paragraphs are joined into snippets of about this many characters.
+const SNIPPET_CHARS: usize = 800;
+const EXTENSIONS: [&str; 3] = ["txt", "md", "markdown"];
+// Usual BM25 parameters.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Part of a note, the unit that is found and added to the prompt.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snippet {
+    /// Path of the note relative to the knowledge folder.
+    pub source: String,
+    pub text: String,
+}
+
+/// Notes and snippets the user keeps in `~/.plugovr/knowledge` (boilerplate,
+/// product facts, signatures), searched offline with BM25. The index is kept
+/// in memory and rebuilt when a file of the folder changes.
+#[derive(Clone)]
+pub struct KnowledgeBase {
+    dir: PathBuf,
+    /// Path, modification time and size of every indexed file.
+    files: Vec<(PathBuf, Option<SystemTime>, u64)>,
+    snippets: Vec<Snippet>,
+    term_frequencies: Vec<HashMap<String, u32>>,
+    lengths: Vec<usize>,
+    document_frequencies: HashMap<String, usize>,
+    average_length: f32,
+}
+
+impl KnowledgeBase {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            files: vec![],
+            snippets: vec![],
+            term_frequencies: vec![],
+            lengths: vec![],
+            document_frequencies: HashMap::new(),
+            average_length: 0.0,
+        }
+    }
+
+    pub fn default_dir() -> PathBuf {
+        crate::settings::config_path("knowledge")
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn snippet_count(&self) -> usize {
+        self.snippets.len()
+    }
+
+    /// Rebuilds the index if files were added, changed or removed.
+    pub fn refresh(&mut self) {
+        let mut files = vec![];
+        collect_files(&self.dir, &mut files);
+        files.sort();
+        let files = files
+            .into_iter()
+            .map(|path| {
+                let metadata = std::fs::metadata(&path).ok();
+                let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+                let size = metadata.map(|m| m.len()).unwrap_or(0);
+                (path, modified, size)
+            })
+            .collect::<Vec<_>>();
+        if files == self.files {
+            return;
+        }
+        let mut snippets = vec![];
+        for (path, _, _) in &files {
+            let text = match std::fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("Failed to read note {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let source = path
+                .strip_prefix(&self.dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .to_string();
+            snippets.extend(split_into_snippets(&text).into_iter().map(|text| Snippet {
+                source: source.clone(),
+                text,
+            }));
+        }
+        self.files = files;
+        self.index(snippets);
+    }
+
+    fn index(&mut self, snippets: Vec<Snippet>) {
+        self.term_frequencies = snippets
+            .iter()
+            .map(|snippet| {
+                let mut frequencies = HashMap::new();
+                for term in tokenize(&format!("{} {}", snippet.source, snippet.text)) {
+                    *frequencies.entry(term).or_insert(0) += 1;
+                }
+                frequencies
+            })
+            .collect();
+        self.lengths = self
+            .term_frequencies
+            .iter()
+            .map(|frequencies| frequencies.values().sum::<u32>() as usize)
+            .collect();
+        self.document_frequencies = HashMap::new();
+        for frequencies in &self.term_frequencies {
+            for term in frequencies.keys() {
+                *self.document_frequencies.entry(term.clone()).or_insert(0) += 1;
+            }
+        }
+        self.average_length = if snippets.is_empty() {
+            0.0
+        } else {
+            self.lengths.iter().sum::<usize>() as f32 / snippets.len() as f32
+        };
+        self.snippets = snippets;
+    }
+
+    /// The `count` snippets that match `query` best, snippets without a
+    /// common term are never returned.
+    pub fn search(&self, query: &str, count: usize) -> Vec<Snippet> {
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+        let documents = self.snippets.len() as f32;
+        let mut scores = self
+            .term_frequencies
+            .iter()
+            .zip(&self.lengths)
+            .enumerate()
+            .map(|(index, (frequencies, length))| {
+                let score = terms
+                    .iter()
+                    .filter_map(|term| {
+                        let frequency = *frequencies.get(term)? as f32;
+                        let document_frequency = self.document_frequencies[term] as f32;
+                        let idf = ((documents - document_frequency + 0.5)
+                            / (document_frequency + 0.5)
+                            + 1.0)
+                            .ln();
+                        let normalized_length = *length as f32 / self.average_length.max(1.0);
+                        Some(
+                            idf * frequency * (K1 + 1.0)
+                                / (frequency + K1 * (1.0 - B + B * normalized_length)),
+                        )
+                    })
+                    .sum::<f32>();
+                (index, score)
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores
+            .into_iter()
+            .take(count)
+            .map(|(index, _)| self.snippets[index].clone())
+            .collect()
+    }
+}
+
+/// The notes the snippets come from, each once.
+pub fn sources(snippets: &[Snippet]) -> Vec<String> {
+    let mut sources: Vec<String> = vec![];
+    for snippet in snippets {
+        if !sources.contains(&snippet.source) {
+            sources.push(snippet.source.clone());
+        }
+    }
+    sources
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// Joins paragraphs until a snippet reaches `SNIPPET_CHARS`, so short notes
+/// like a signature stay in one piece.
+fn split_into_snippets(text: &str) -> Vec<String> {
+    let mut snippets = vec![];
+    let mut current = String::new();
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        if !current.is_empty() && current.len() + paragraph.len() > SNIPPET_CHARS {
+            snippets.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current += "\n\n";
+        }
+        current += paragraph;
+    }
+    if !current.is_empty() {
+        snippets.push(current);
+    }
+    snippets
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() > 1)
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_ranks_matching_snippets() {
+        let dir = std::env::temp_dir().join(format!("plugovr_knowledge_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("company")).unwrap();
+        std::fs::write(
+            dir.join("signature.md"),
+            "Best regards\nJane Doe\nPlugOvr GmbH",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("company").join("pricing.txt"),
+            "The pro plan costs 10 EUR per month.\n\nThe team plan costs 8 EUR per user and month.",
+        )
+        .unwrap();
+        std::fs::write(dir.join("image.png"), "not a note").unwrap();
+
+        let mut knowledge = KnowledgeBase::new(dir.clone());
+        knowledge.refresh();
+        assert_eq!(knowledge.file_count(), 2);
+        assert_eq!(knowledge.snippet_count(), 2);
+
+        let found = knowledge.search("What does the team plan cost per user?", 3);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].source.ends_with("pricing.txt"));
+        assert!(knowledge.search("weather tomorrow", 3).is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use crate::llm::backend::{
+    AbortOnDrop, BackendError, FinishReason, LlmBackend, LlmChunk, LlmRequest, TokenStream,
+    backend_error, estimate_tokens,
+};
+use crate::llm::error::LlmError;
+use crate::llm::options::GenerationSettings;
+use crate::llm::prompt::{build_messages, flatten_messages};
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::{Stream, StreamExt};
+use kalosm::language::{
+    ArcParser, FloatParser, GenerationParameters, IndexParser, IntegerParser, LiteralParser, Llama,
+    ParserExt, StringParser,
+};
+use serde_json::Value;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+/// Longest string an answer constrained by a JSON schema may contain.
+const MAX_SCHEMA_STRING_CHARS: usize = 10_000;
+/// Most items of an array an answer constrained by a JSON schema may contain.
+const MAX_SCHEMA_ITEMS: usize = 1_000;
+
+pub struct LocalBackend {
+    model: Arc<Mutex<Option<Llama>>>,
+    description: String,
+    context_window: usize,
+}
+
+impl LocalBackend {
+    pub fn new(
+        model: Arc<Mutex<Option<Llama>>>,
+        description: String,
+        context_window: usize,
+    ) -> Self {
+        Self {
+            model,
+            description,
+            context_window,
+        }
+    }
+
+    fn loaded_model(&self) -> Result<Llama, BackendError> {
+        let guard = self
+            .model
+            .try_lock()
+            .map_err(|_| backend_error("Local model is busy"))?;
+        guard.as_ref().cloned().ok_or_else(|| -> BackendError {
+            Box::new(LlmError::ModelMissing(
+                "Local model is not loaded yet".to_string(),
+            ))
+        })
+    }
+}
+
+/// Kalosm only supports a single stop sequence, so the first one is used.
+fn sampler(options: &GenerationSettings) -> GenerationParameters {
+    let mut sampler = GenerationParameters::default();
+    if let Some(temperature) = options.temperature {
+        sampler = sampler.with_temperature(temperature.into());
+    }
+    if let Some(top_p) = options.top_p {
+        sampler = sampler.with_top_p(top_p.into());
+    }
+    if let Some(max_tokens) = options.max_tokens {
+        sampler = sampler.with_max_length(max_tokens);
+    }
+    if let Some(stop) = options.stop.first() {
+        sampler = sampler.with_stop_on(stop.clone());
+    }
+    if let Some(seed) = options.seed {
+        sampler = sampler.with_seed(seed);
+    }
+    sampler
+}
+
+fn literal(text: &str) -> ArcParser {
+    LiteralParser::new(text.to_string()).boxed()
+}
+
+/// A parser that only accepts JSON matching `schema`, so the model can't
+/// sample anything else. Covers what `structured::validate` checks plus
+/// `anyOf`, the properties are written in order and without whitespace.
+fn schema_parser(schema: &Value) -> Result<ArcParser, String> {
+    if let Some(variants) = schema.get("anyOf").and_then(Value::as_array) {
+        let parsers = variants
+            .iter()
+            .map(schema_parser)
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(IndexParser::new(parsers).map_output(|_| ()).boxed());
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        let parsers = allowed
+            .iter()
+            .map(|value| literal(&value.to_string()))
+            .collect::<Vec<_>>();
+        return Ok(IndexParser::new(parsers).map_output(|_| ()).boxed());
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some("object") => object_parser(schema),
+        Some("array") => {
+            let item = schema_parser(
+                schema
+                    .get("items")
+                    .ok_or("an array of the JSON schema has no items")?,
+            )?;
+            let items = item
+                .clone()
+                .then(
+                    literal(",")
+                        .then(item)
+                        .map_output(|_| ())
+                        .repeat(0..=MAX_SCHEMA_ITEMS),
+                )
+                .map_output(|_| ());
+            Ok(literal("[]")
+                .otherwise(literal("[").then(items).then(literal("]")))
+                .map_output(|_| ())
+                .boxed())
+        }
+        Some("string") => Ok(StringParser::new(0..=MAX_SCHEMA_STRING_CHARS)
+            .map_output(|_| ())
+            .boxed()),
+        Some("integer") => Ok(IntegerParser::new(i64::MIN.into()..=i64::MAX.into())
+            .map_output(|_| ())
+            .boxed()),
+        Some("number") => Ok(FloatParser::new(f64::MIN..=f64::MAX)
+            .map_output(|_| ())
+            .boxed()),
+        Some("boolean") => Ok(literal("true")
+            .otherwise(literal("false"))
+            .map_output(|_| ())
+            .boxed()),
+        Some("null") => Ok(literal("null")),
+        Some(other) => Err(format!("the JSON schema type {} is not supported", other)),
+        None => Err("every value of the JSON schema needs a type".to_string()),
+    }
+}
+
+fn object_parser(schema: &Value) -> Result<ArcParser, String> {
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .filter(|properties| !properties.is_empty())
+        .ok_or("an object of the JSON schema has no properties")?;
+    let mut parser = literal("{");
+    for (index, (key, property)) in properties.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        let key = format!("{}{}:", separator, Value::from(key.as_str()));
+        parser = parser
+            .then(literal(&key))
+            .then(schema_parser(property)?)
+            .map_output(|_| ())
+            .boxed();
+    }
+    Ok(parser.then(literal("}")).map_output(|_| ()).boxed())
+}
+
+impl LlmBackend for LocalBackend {
+    fn name(&self) -> String {
+        format!("{} - Local", self.description)
+    }
+
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>> {
+        let model = self.loaded_model();
+        Box::pin(async move {
+            let model = model?;
+            let constraints = request
+                .options
+                .json_schema
+                .as_ref()
+                .map(schema_parser)
+                .transpose()
+                .map_err(|e| {
+                    backend_error(format!("Local models can't enforce the JSON schema: {}", e))
+                })?;
+            let prompt = flatten_messages(&build_messages(&request));
+            let sampler = sampler(&request.options);
+            let max_tokens = request.options.max_tokens;
+            let tokenizer = model.tokenizer();
+            let (tx, rx) = mpsc::unbounded();
+            let producer = tokio::spawn(async move {
+                let mut stream: Pin<Box<dyn Stream<Item = String> + Send>> = match constraints {
+                    Some(constraints) => Box::pin(
+                        model(&prompt)
+                            .with_constraints(constraints)
+                            .with_sampler(sampler),
+                    ),
+                    None => Box::pin(model(&prompt).with_sampler(sampler)),
+                };
+                let mut answer = String::new();
+                while let Some(token) = stream.next().await {
+                    answer.push_str(&token);
+                    if tx.unbounded_send(Ok(LlmChunk::Token(token))).is_err() {
+                        return;
+                    }
+                }
+                // Kalosm ends silently at max_length and its stream items are
+                // pieces of text, so the answer is counted with the tokenizer.
+                let generated_tokens = tokenizer
+                    .encode(answer.as_str(), false)
+                    .map(|encoding| encoding.len())
+                    .unwrap_or_else(|_| estimate_tokens(&answer));
+                let finish_reason = if max_tokens.is_some_and(|max| generated_tokens >= max) {
+                    FinishReason::MaxTokens
+                } else {
+                    FinishReason::Stop
+                };
+                let _ = tx.unbounded_send(Ok(LlmChunk::Finished(finish_reason)));
+            });
+            // Dropping the stream (e.g. on cancel) stops the generation right away
+            // instead of running the model until the answer is complete.
+            let producer = AbortOnDrop(producer);
+            Ok(rx
+                .map(move |chunk| {
+                    let _ = &producer;
+                    chunk
+                })
+                .boxed())
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>> {
+        let description = self.description.clone();
+        Box::pin(async move { Ok(vec![description]) })
+    }
+
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>> {
+        let model = self.loaded_model().map(|_| ());
+        Box::pin(async move { model })
+    }
+
+    fn context_window(&self) -> usize {
+        self.context_window
+    }
+
+    /// Uses the tokenizer of the loaded model, estimates while it is loading.
+    fn count_tokens(&self, text: &str) -> usize {
+        self.loaded_model()
+            .ok()
+            .and_then(|model| model.tokenizer().encode(text, false).ok())
+            .map(|encoding| encoding.len())
+            .unwrap_or_else(|| estimate_tokens(text))
+    }
+}
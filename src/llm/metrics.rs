@@ -0,0 +1,261 @@
+use crate::llm::backend::{BackendError, LlmBackend, LlmChunk, LlmRequest, TokenStream};
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The file is cut to its newer half when it grows beyond this.
+const MAX_METRICS_BYTES: u64 = 4 * 1024 * 1024;
+
+/// One attempt of a request at one backend, appended as a json line to
+/// `~/.plugovr/metrics.jsonl`. A request that fell back has a row per backend.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestMetrics {
+    pub timestamp: u64,
+    pub backend: String,
+    pub model: String,
+    pub template: Option<String>,
+    /// Estimated from the prompt length, backends don't report it uniformly.
+    pub prompt_tokens: usize,
+    /// Counted from the answer text by the backend's tokenizer or estimate.
+    pub answer_tokens: usize,
+    pub time_to_first_token_ms: Option<u64>,
+    pub total_ms: u64,
+    pub error: Option<String>,
+    /// The user stopped the answer, it is neither a success nor an error.
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+impl RequestMetrics {
+    pub fn tokens_per_second(&self) -> Option<f64> {
+        let first_token_ms = self.time_to_first_token_ms?;
+        let generation_ms = self.total_ms.saturating_sub(first_token_ms);
+        if generation_ms == 0 || self.answer_tokens < 2 {
+            return None;
+        }
+        Some(self.answer_tokens as f64 * 1000.0 / generation_ms as f64)
+    }
+}
+
+/// What one backend streamed. Chunked requests call the backend several
+/// times, the stats add up over all calls.
+#[derive(Default)]
+struct StreamStats {
+    started: Option<Instant>,
+    first_token: Option<Duration>,
+    finished: Option<Duration>,
+    answer: String,
+    error: Option<String>,
+}
+
+/// Wraps a single backend, beneath the fallback, and measures its time to
+/// first token and its answer.
+pub struct MeteredBackend {
+    inner: Arc<dyn LlmBackend>,
+    model: String,
+    stats: Arc<Mutex<StreamStats>>,
+}
+
+impl MeteredBackend {
+    pub fn new(inner: Arc<dyn LlmBackend>, model: String) -> Self {
+        Self {
+            inner,
+            model,
+            stats: Arc::new(Mutex::new(StreamStats::default())),
+        }
+    }
+
+    /// The metrics of this backend, None if it wasn't tried. An error of the
+    /// backend itself wins over `error`, e.g. an invalid answer.
+    pub fn take_metrics(
+        &self,
+        request: &LlmRequest,
+        error: Option<String>,
+        cancelled: bool,
+    ) -> Option<RequestMetrics> {
+        let stats = std::mem::take(&mut *self.stats.lock().unwrap());
+        let started = stats.started?;
+        let error = stats.error.or(error);
+        let prompt = std::iter::once(&request.context)
+            .chain(std::iter::once(&request.instruction))
+            .chain(request.history.iter().map(|message| &message.content))
+            .map(|text| self.inner.count_tokens(text))
+            .sum();
+        Some(RequestMetrics {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            backend: self.inner.name(),
+            model: self.model.clone(),
+            template: request.template.clone(),
+            prompt_tokens: prompt,
+            answer_tokens: self.inner.count_tokens(&stats.answer),
+            time_to_first_token_ms: stats.first_token.map(|d| d.as_millis() as u64),
+            total_ms: stats
+                .finished
+                .unwrap_or_else(|| started.elapsed())
+                .as_millis() as u64,
+            cancelled: cancelled && error.is_none(),
+            error,
+        })
+    }
+}
+
+impl LlmBackend for MeteredBackend {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>> {
+        self.stats
+            .lock()
+            .unwrap()
+            .started
+            .get_or_insert_with(Instant::now);
+        let stream = self.inner.stream(request);
+        let stats = self.stats.clone();
+        Box::pin(async move {
+            let stream = match stream.await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    stats.lock().unwrap().error = Some(e.to_string());
+                    return Err(e);
+                }
+            };
+            Ok(stream
+                .inspect(move |chunk| {
+                    let mut stats = stats.lock().unwrap();
+                    let elapsed = stats.started.map(|started| started.elapsed());
+                    match chunk {
+                        Ok(LlmChunk::Token(token)) => {
+                            if stats.first_token.is_none() {
+                                stats.first_token = elapsed;
+                            }
+                            stats.answer.push_str(token);
+                            stats.finished = elapsed;
+                        }
+                        Ok(LlmChunk::Finished(_)) => stats.finished = elapsed,
+                        Err(e) => stats.error = Some(e.to_string()),
+                    }
+                })
+                .boxed())
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>> {
+        self.inner.list_models()
+    }
+
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>> {
+        self.inner.health_check()
+    }
+
+    fn context_window(&self) -> usize {
+        self.inner.context_window()
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.inner.count_tokens(text)
+    }
+}
+
+fn metrics_path() -> PathBuf {
+    crate::settings::config_path("metrics.jsonl")
+}
+
+pub fn save_metrics(metrics: &RequestMetrics) -> std::io::Result<()> {
+    let path = metrics_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(metrics)?)?;
+    if file.metadata()?.len() > MAX_METRICS_BYTES {
+        let contents = std::fs::read_to_string(&path)?;
+        let lines = contents.lines().collect::<Vec<_>>();
+        let newer_half = lines[lines.len() / 2..].join("\n");
+        std::fs::write(&path, newer_half + "\n")?;
+    }
+    Ok(())
+}
+
+pub fn load_metrics() -> std::io::Result<Vec<RequestMetrics>> {
+    let contents = std::fs::read_to_string(metrics_path())?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+pub fn clear_metrics() -> std::io::Result<()> {
+    std::fs::remove_file(metrics_path())
+}
+
+/// Aggregated metrics of one model and template.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSummary {
+    pub model: String,
+    pub template: String,
+    pub requests: usize,
+    pub errors: usize,
+    pub cancelled: usize,
+    pub avg_prompt_tokens: f64,
+    pub avg_answer_tokens: f64,
+    pub avg_time_to_first_token_ms: Option<f64>,
+    pub avg_tokens_per_second: Option<f64>,
+}
+
+pub fn summarize_metrics(metrics: &[RequestMetrics]) -> Vec<MetricsSummary> {
+    let mut groups: BTreeMap<(String, String), Vec<&RequestMetrics>> = BTreeMap::new();
+    for metric in metrics {
+        let template = metric.template.clone().unwrap_or_else(|| "-".to_string());
+        groups
+            .entry((metric.model.clone(), template))
+            .or_default()
+            .push(metric);
+    }
+    groups
+        .into_iter()
+        .map(|((model, template), metrics)| {
+            let successful: Vec<_> = metrics
+                .iter()
+                .filter(|m| m.error.is_none() && !m.cancelled)
+                .collect();
+            MetricsSummary {
+                model,
+                template,
+                requests: metrics.len(),
+                errors: metrics.iter().filter(|m| m.error.is_some()).count(),
+                cancelled: metrics.iter().filter(|m| m.cancelled).count(),
+                avg_prompt_tokens: average(successful.iter().map(|m| m.prompt_tokens as f64))
+                    .unwrap_or(0.0),
+                avg_answer_tokens: average(successful.iter().map(|m| m.answer_tokens as f64))
+                    .unwrap_or(0.0),
+                avg_time_to_first_token_ms: average(
+                    successful
+                        .iter()
+                        .filter_map(|m| m.time_to_first_token_ms.map(|ms| ms as f64)),
+                ),
+                avg_tokens_per_second: average(
+                    successful.iter().filter_map(|m| m.tokens_per_second()),
+                ),
+            }
+        })
+        .collect()
+}
+
+fn average(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    if count == 0 {
+        None
+    } else {
+        Some(sum / count as f64)
+    }
+}
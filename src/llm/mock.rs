@@ -0,0 +1,163 @@
+use crate::llm::backend::{
+    BackendError, FinishReason, LlmBackend, LlmChunk, LlmRequest, TokenStream,
+};
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use std::io;
+
+/// Deterministic backend without any model. It streams a canned answer word by
+/// word, or echoes the instruction back if no answer was given. A failing mock
+/// stands in for an unreachable server.
+pub struct MockBackend {
+    response: Option<String>,
+    finish_reason: FinishReason,
+    error: Option<(io::ErrorKind, String)>,
+}
+
+impl MockBackend {
+    pub fn new(response: impl Into<String>) -> Self {
+        Self {
+            response: Some(response.into()),
+            finish_reason: FinishReason::Stop,
+            error: None,
+        }
+    }
+
+    pub fn echo() -> Self {
+        Self {
+            response: None,
+            finish_reason: FinishReason::Stop,
+            error: None,
+        }
+    }
+
+    pub fn failing(error: impl Into<String>) -> Self {
+        Self {
+            response: None,
+            finish_reason: FinishReason::Stop,
+            error: Some((io::ErrorKind::Other, error.into())),
+        }
+    }
+
+    /// Fails like a server that isn't running, with a typed error.
+    pub fn refusing() -> Self {
+        Self {
+            response: None,
+            finish_reason: FinishReason::Stop,
+            error: Some((
+                io::ErrorKind::ConnectionRefused,
+                "connect failed".to_string(),
+            )),
+        }
+    }
+
+    pub fn with_finish_reason(mut self, finish_reason: FinishReason) -> Self {
+        self.finish_reason = finish_reason;
+        self
+    }
+}
+
+impl LlmBackend for MockBackend {
+    fn name(&self) -> String {
+        "Mock".to_string()
+    }
+
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>> {
+        let response = self.response.clone().unwrap_or(request.instruction);
+        let finish_reason = self.finish_reason;
+        let error = self.error.clone();
+        Box::pin(async move {
+            if let Some((kind, error)) = error {
+                return Err(Box::new(io::Error::new(kind, error)));
+            }
+            let mut chunks: Vec<Result<LlmChunk, BackendError>> = response
+                .split_inclusive(' ')
+                .map(|token| Ok(LlmChunk::Token(token.to_string())))
+                .collect();
+            chunks.push(Ok(LlmChunk::Finished(finish_reason)));
+            Ok(futures::stream::iter(chunks).boxed())
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>> {
+        Box::pin(async move { Ok(vec!["mock".to_string()]) })
+    }
+
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>> {
+        Box::pin(async move { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::backend::generate;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::watch;
+
+    fn request(instruction: &str) -> LlmRequest {
+        LlmRequest {
+            context: "some context".to_string(),
+            instruction: instruction.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_streams_answer() {
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let backend = MockBackend::new("Hello from the mock backend");
+        let (_cancel, cancel_receiver) = watch::channel(false);
+        let (answer, finish_reason) = generate(
+            &backend,
+            request("ignored"),
+            ai_answer.clone(),
+            cancel_receiver,
+        )
+        .await
+        .unwrap();
+        assert_eq!(answer, "Hello from the mock backend");
+        assert_eq!(*ai_answer.lock().unwrap(), answer);
+        assert_eq!(finish_reason, FinishReason::Stop);
+    }
+
+    #[tokio::test]
+    async fn test_generate_reports_finish_reason() {
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let backend = MockBackend::echo().with_finish_reason(FinishReason::MaxTokens);
+        let (_cancel, cancel_receiver) = watch::channel(false);
+        let (answer, finish_reason) =
+            generate(&backend, request("shorter"), ai_answer, cancel_receiver)
+                .await
+                .unwrap();
+        assert_eq!(answer, "shorter");
+        assert_eq!(finish_reason, FinishReason::MaxTokens);
+    }
+
+    #[tokio::test]
+    async fn test_generate_cancelled() {
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let backend = MockBackend::new("never streamed");
+        let (_cancel, cancel_receiver) = watch::channel(true);
+        let (answer, finish_reason) =
+            generate(&backend, request("ignored"), ai_answer, cancel_receiver)
+                .await
+                .unwrap();
+        assert_eq!(answer, "");
+        assert_eq!(finish_reason, FinishReason::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_generate_continues_after_prefix() {
+        let ai_answer = Arc::new(Mutex::new(String::new()));
+        let backend = MockBackend::new("upon a time");
+        let (_cancel, cancel_receiver) = watch::channel(false);
+        let mut request = request("ignored");
+        request.answer_prefix = "Once ".to_string();
+        let (answer, _) = generate(&backend, request, ai_answer.clone(), cancel_receiver)
+            .await
+            .unwrap();
+        assert_eq!(answer, "Once upon a time");
+        assert_eq!(*ai_answer.lock().unwrap(), answer);
+    }
+}
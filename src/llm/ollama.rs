@@ -0,0 +1,344 @@
+use crate::llm::backend::{
+    BackendError, FinishReason, LlmBackend, LlmChunk, LlmRequest, Role, TokenStream, backend_error,
+};
+use crate::llm::error::LlmError;
+use crate::llm::options::MAX_SEED;
+use crate::llm::prompt::build_messages;
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use ollama_rs::{
+    Ollama,
+    generation::chat::{ChatMessage, MessageRole},
+    generation::images::Image,
+    generation::options::GenerationOptions,
+};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+
+/// Ollama loads models with a small default context, we ask for this one
+/// explicitly so chunking knows what fits.
+const OLLAMA_CONTEXT_WINDOW: usize = 8192;
+
+pub struct OllamaBackend {
+    ollama: Ollama,
+    url: String,
+    model: String,
+}
+
+impl OllamaBackend {
+    pub fn new(ollama: Ollama, url: String, model: String) -> Self {
+        Self { ollama, url, model }
+    }
+}
+
+impl LlmBackend for OllamaBackend {
+    fn name(&self) -> String {
+        format!("Ollama - {}", self.model)
+    }
+
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>> {
+        let url = self.url.clone();
+        let model = self.model.clone();
+        Box::pin(async move {
+            use base64::{Engine as _, engine::general_purpose};
+
+            let mut images = vec![];
+            for img in request.screenshots.iter() {
+                let mut buf = vec![];
+                img.0.write_to(
+                    &mut std::io::Cursor::new(&mut buf),
+                    image_24::ImageOutputFormat::Png,
+                )?;
+                images.push(Image::from_base64(&general_purpose::STANDARD.encode(&buf)));
+            }
+
+            let mut options = GenerationOptions::default()
+                .repeat_penalty(1.1)
+                .top_k(40)
+                .num_ctx(OLLAMA_CONTEXT_WINDOW as u64);
+            if let Some(temperature) = request.options.temperature {
+                options = options.temperature(temperature);
+            }
+            if let Some(top_p) = request.options.top_p {
+                options = options.top_p(top_p);
+            }
+            if let Some(max_tokens) = request.options.max_tokens {
+                options = options.num_predict(max_tokens as i32);
+            }
+            if !request.options.stop.is_empty() {
+                options = options.stop(request.options.stop.clone());
+            }
+            if let Some(seed) = request.options.seed {
+                options = options.seed(seed.min(MAX_SEED) as i32);
+            }
+
+            let mut messages = build_messages(&request)
+                .into_iter()
+                .map(|message| {
+                    let role = match message.role {
+                        Role::System => MessageRole::System,
+                        Role::User => MessageRole::User,
+                        Role::Assistant => MessageRole::Assistant,
+                    };
+                    ChatMessage::new(role, message.content)
+                })
+                .collect::<Vec<_>>();
+            // The screenshots belong to the latest user message.
+            if let Some(last) = messages.pop() {
+                messages.push(last.with_images(images));
+            }
+
+            chat(
+                &url,
+                model,
+                messages,
+                options,
+                request.options.json_schema.clone(),
+            )
+            .await
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>> {
+        let ollama = self.ollama.clone();
+        Box::pin(async move {
+            let models = ollama.list_local_models().await?;
+            Ok(models.into_iter().map(|model| model.name).collect())
+        })
+    }
+
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>> {
+        let ollama = self.ollama.clone();
+        Box::pin(async move {
+            ollama.list_local_models().await?;
+            Ok(())
+        })
+    }
+
+    fn context_window(&self) -> usize {
+        OLLAMA_CONTEXT_WINDOW
+    }
+}
+
+/// ollama-rs neither tells why Ollama stopped nor knows formats other than
+/// plain `json`, so the chat goes to `/api/chat` directly. Ollama streams one
+/// JSON object per line and constrains sampling to the schema if given.
+async fn chat(
+    url: &str,
+    model: String,
+    messages: Vec<ChatMessage>,
+    options: GenerationOptions,
+    schema: Option<serde_json::Value>,
+) -> Result<TokenStream, BackendError> {
+    let mut body = serde_json::json!({
+        "model": model,
+        "messages": messages,
+        "options": options,
+        "stream": true,
+    });
+    if let Some(schema) = schema {
+        body["format"] = schema;
+    }
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/chat", url.trim().trim_end_matches('/')))
+        .json(&body)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        // Ollama explains the status in the error field of the body.
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|body| Some(body.get("error")?.as_str()?.to_string()))
+            .unwrap_or(body);
+        return Err(Box::new(LlmError::from_status(status.as_u16(), message)));
+    }
+    let mut buffer = vec![];
+    let stream = response.bytes_stream().flat_map(move |bytes| {
+        let mut chunks: Vec<Result<LlmChunk, BackendError>> = vec![];
+        match bytes {
+            Err(_) => chunks.push(Err(backend_error("Ollama stream interrupted"))),
+            Ok(bytes) => {
+                buffer.extend_from_slice(&bytes);
+                while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                    let line = buffer.drain(..=end).collect::<Vec<_>>();
+                    if !line.trim_ascii().is_empty() {
+                        chunks.extend(chat_chunks(&line));
+                    }
+                }
+            }
+        }
+        futures::stream::iter(chunks)
+    });
+    Ok(stream.boxed())
+}
+
+/// The tokens of one line of `/api/chat`. The last line has `done_reason`,
+/// `length` means the answer used up num_predict and was cut off.
+fn chat_chunks(line: &[u8]) -> Vec<Result<LlmChunk, BackendError>> {
+    #[derive(Deserialize)]
+    struct ChatResponse {
+        #[serde(default)]
+        message: Option<ChatResponseMessage>,
+        #[serde(default)]
+        done_reason: Option<String>,
+        #[serde(default)]
+        error: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct ChatResponseMessage {
+        content: String,
+    }
+
+    let response = match serde_json::from_slice::<ChatResponse>(line) {
+        Ok(response) => response,
+        Err(e) => return vec![Err(e.into())],
+    };
+    if let Some(error) = response.error {
+        return vec![Err(backend_error(error))];
+    }
+    let mut chunks = vec![];
+    if let Some(message) = response.message {
+        chunks.push(Ok(LlmChunk::Token(message.content)));
+    }
+    match response.done_reason.as_deref() {
+        Some("length") => chunks.push(Ok(LlmChunk::Finished(FinishReason::MaxTokens))),
+        Some(_) => chunks.push(Ok(LlmChunk::Finished(FinishReason::Stop))),
+        None => {}
+    }
+    chunks
+}
+
+pub const DEFAULT_OLLAMA_URL: &str = "http://127.0.0.1:11434";
+
+/// Creates a client for an endpoint like `http://192.168.1.10:11434`.
+pub fn ollama_from_url(url: &str) -> Result<Ollama, BackendError> {
+    let url = reqwest::Url::parse(url.trim())?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| backend_error("The Ollama URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(11434);
+    Ok(Ollama::new(format!("{}://{}", url.scheme(), host), port))
+}
+
+/// A model installed in Ollama together with what `/api/tags` and `/api/show`
+/// tell about it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OllamaModelInfo {
+    pub name: String,
+    pub size: u64,
+    pub family: String,
+    pub parameter_size: String,
+    pub quantization: String,
+    pub vision: bool,
+}
+
+impl OllamaModelInfo {
+    pub fn details(&self) -> String {
+        format!(
+            "{} {} {} {:.1} GB",
+            self.family,
+            self.parameter_size,
+            self.quantization,
+            self.size as f64 / 1e9
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct TagsResponse {
+    models: Vec<TagsModel>,
+}
+
+#[derive(Deserialize)]
+struct TagsModel {
+    name: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    details: TagsModelDetails,
+}
+
+#[derive(Default, Deserialize)]
+struct TagsModelDetails {
+    #[serde(default)]
+    family: String,
+    #[serde(default)]
+    families: Option<Vec<String>>,
+    #[serde(default)]
+    parameter_size: String,
+    #[serde(default)]
+    quantization_level: String,
+}
+
+#[derive(Deserialize)]
+struct ShowResponse {
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+/// Lists the installed models with their metadata. ollama-rs only knows name
+/// and size, so the REST API is queried directly.
+pub async fn list_ollama_models(url: &str) -> Result<Vec<OllamaModelInfo>, BackendError> {
+    let url = url.trim().trim_end_matches('/');
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?;
+    let tags: TagsResponse = client
+        .get(format!("{}/api/tags", url))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let mut models = vec![];
+    for model in tags.models {
+        // Older Ollama versions have no capabilities, the clip projector of
+        // llava-like models shows up in the families instead.
+        let mut vision = model
+            .details
+            .families
+            .iter()
+            .flatten()
+            .any(|family| family == "clip" || family == "mllama");
+        if let Ok(response) = client
+            .post(format!("{}/api/show", url))
+            .json(&serde_json::json!({ "model": model.name }))
+            .send()
+            .await
+        {
+            if let Ok(show) = response.json::<ShowResponse>().await {
+                vision |= show.capabilities.iter().any(|c| c == "vision");
+            }
+        }
+        models.push(OllamaModelInfo {
+            name: model.name,
+            size: model.size,
+            family: model.details.family,
+            parameter_size: model.details.parameter_size,
+            quantization: model.details.quantization_level,
+            vision,
+        });
+    }
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(models)
+}
+
+/// Pulls a model and reports the download progress as (status, fraction).
+pub async fn pull_ollama_model(
+    ollama: Ollama,
+    model: String,
+    progress: Arc<Mutex<Option<(String, f32)>>>,
+) -> Result<(), BackendError> {
+    let mut stream = ollama.pull_model_stream(model, false).await?;
+    while let Some(status) = stream.next().await {
+        let status = status?;
+        let fraction = match (status.completed, status.total) {
+            (Some(completed), Some(total)) if total > 0 => completed as f32 / total as f32,
+            _ => 0.0,
+        };
+        *progress.lock().unwrap() = Some((status.message, fraction));
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+use crate::llm::backend::{
+    BackendError, ConversationMessage, FinishReason, LlmBackend, LlmChunk, LlmRequest, Role,
+    TokenStream,
+};
+use crate::llm::options::MAX_SEED;
+use crate::llm::prompt::build_messages;
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use openai_dive::v1::api::Client;
+use openai_dive::v1::resources::chat::{
+    ChatCompletionParametersBuilder, ChatCompletionResponseFormat, ChatMessage, ChatMessageContent,
+    ChatMessageContentPart, ChatMessageImageContentPart, ChatMessageTextContentPart,
+    DeltaChatMessage, ImageUrlType,
+};
+use openai_dive::v1::resources::shared::{FinishReason as OpenAiFinishReason, StopToken};
+
+pub struct OpenAiCompatibleBackend {
+    base_url: String,
+    model: String,
+    api_key: String,
+}
+
+impl OpenAiCompatibleBackend {
+    pub fn new(base_url: String, model: String, api_key: String) -> Self {
+        Self {
+            base_url,
+            model,
+            api_key,
+        }
+    }
+
+    fn client(&self) -> Client {
+        let mut client = Client::new(self.api_key.clone());
+        client.set_base_url(&self.base_url);
+        client
+    }
+}
+
+/// Builds the chat message through serde so we don't depend on every optional
+/// field of the assistant variant.
+fn history_message(message: &ConversationMessage) -> Result<ChatMessage, serde_json::Error> {
+    let role = match message.role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+    };
+    serde_json::from_value(serde_json::json!({
+        "role": role,
+        "content": message.content,
+    }))
+}
+
+/// `response_format` asking the server to answer with JSON that matches `schema`.
+pub fn json_schema_response_format(
+    schema: &serde_json::Value,
+) -> Result<ChatCompletionResponseFormat, serde_json::Error> {
+    serde_json::from_value(serde_json::json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "response",
+            "schema": strict_schema(schema),
+            "strict": true,
+        },
+    }))
+}
+
+/// Strict mode rejects objects that allow other properties or leave some of
+/// them optional. Requiring all of them still gives answers that match the
+/// original schema.
+fn strict_schema(schema: &serde_json::Value) -> serde_json::Value {
+    let mut schema = schema.clone();
+    make_strict(&mut schema);
+    schema
+}
+
+fn make_strict(schema: &mut serde_json::Value) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+    if let Some(properties) = object
+        .get_mut("properties")
+        .and_then(serde_json::Value::as_object_mut)
+    {
+        properties.values_mut().for_each(make_strict);
+        let required = properties.keys().cloned().collect::<Vec<_>>();
+        object.insert("required".to_string(), required.into());
+        object.insert("additionalProperties".to_string(), false.into());
+    }
+    if let Some(items) = object.get_mut("items") {
+        make_strict(items);
+    }
+    if let Some(variants) = object
+        .get_mut("anyOf")
+        .and_then(serde_json::Value::as_array_mut)
+    {
+        variants.iter_mut().for_each(make_strict);
+    }
+}
+
+impl LlmBackend for OpenAiCompatibleBackend {
+    fn name(&self) -> String {
+        format!("{} - {}", self.model, self.base_url)
+    }
+
+    fn stream(&self, request: LlmRequest) -> BoxFuture<'static, Result<TokenStream, BackendError>> {
+        let client = self.client();
+        let model = self.model.clone();
+        Box::pin(async move {
+            use base64::{Engine as _, engine::general_purpose};
+
+            let mut prompt_messages = build_messages(&request);
+            // The last message is the user turn, the screenshots are attached to it.
+            let prompt = prompt_messages
+                .pop()
+                .map(|message| message.content)
+                .unwrap_or_default();
+            let content = if request.screenshots.is_empty() {
+                ChatMessageContent::Text(prompt)
+            } else {
+                let mut parts = vec![ChatMessageContentPart::Text(ChatMessageTextContentPart {
+                    r#type: "text".to_string(),
+                    text: prompt,
+                })];
+                for img in request.screenshots.iter() {
+                    let mut buf = vec![];
+                    img.0.write_to(
+                        &mut std::io::Cursor::new(&mut buf),
+                        image_24::ImageOutputFormat::Png,
+                    )?;
+                    parts.push(ChatMessageContentPart::Image(ChatMessageImageContentPart {
+                        r#type: "image_url".to_string(),
+                        image_url: ImageUrlType {
+                            url: format!(
+                                "data:image/png;base64,{}",
+                                general_purpose::STANDARD.encode(&buf)
+                            ),
+                            detail: None,
+                        },
+                    }));
+                }
+                ChatMessageContent::ContentPart(parts)
+            };
+
+            let mut messages = prompt_messages
+                .iter()
+                .map(history_message)
+                .collect::<Result<Vec<_>, _>>()?;
+            messages.push(ChatMessage::User {
+                content,
+                name: None,
+            });
+
+            let options = request.options;
+            let mut builder = ChatCompletionParametersBuilder::default();
+            builder.model(model).messages(messages);
+            if let Some(temperature) = options.temperature {
+                builder.temperature(temperature);
+            }
+            if let Some(top_p) = options.top_p {
+                builder.top_p(top_p);
+            }
+            if let Some(max_tokens) = options.max_tokens {
+                builder.max_completion_tokens(max_tokens);
+            }
+            if !options.stop.is_empty() {
+                builder.stop(StopToken::Array(options.stop));
+            }
+            if let Some(seed) = options.seed {
+                builder.seed(seed.min(MAX_SEED) as u32);
+            }
+            if let Some(schema) = &options.json_schema {
+                builder.response_format(json_schema_response_format(schema)?);
+            }
+            let parameters = builder.build()?;
+
+            let stream = client.chat().create_stream(parameters).await?;
+            let stream = stream.flat_map(|chunk| {
+                let mut chunks: Vec<Result<LlmChunk, BackendError>> = vec![];
+                match chunk {
+                    Err(e) => chunks.push(Err(e.into())),
+                    Ok(chunk) => {
+                        for choice in chunk.choices {
+                            match &choice.delta {
+                                DeltaChatMessage::Assistant {
+                                    content: Some(content),
+                                    ..
+                                }
+                                | DeltaChatMessage::Untagged {
+                                    content: Some(content),
+                                    ..
+                                } => chunks.push(Ok(LlmChunk::Token(content.to_string()))),
+                                _ => {}
+                            }
+                            match choice.finish_reason {
+                                Some(OpenAiFinishReason::TokenLimitReached) => {
+                                    chunks.push(Ok(LlmChunk::Finished(FinishReason::MaxTokens)))
+                                }
+                                Some(_) => chunks.push(Ok(LlmChunk::Finished(FinishReason::Stop))),
+                                None => {}
+                            }
+                        }
+                    }
+                }
+                futures::stream::iter(chunks)
+            });
+            Ok(stream.boxed())
+        })
+    }
+
+    fn list_models(&self) -> BoxFuture<'static, Result<Vec<String>, BackendError>> {
+        let client = self.client();
+        Box::pin(async move {
+            let models = client.models().list().await?;
+            Ok(models.data.into_iter().map(|model| model.id).collect())
+        })
+    }
+
+    fn health_check(&self) -> BoxFuture<'static, Result<(), BackendError>> {
+        let client = self.client();
+        Box::pin(async move {
+            client.models().list().await?;
+            Ok(())
+        })
+    }
+}
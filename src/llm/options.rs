@@ -0,0 +1,115 @@
+use crate::llm::chunking::ChunkMode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Largest seed every backend accepts, Ollama takes a signed 32 bit seed.
+pub const MAX_SEED: u64 = i32::MAX as u64;
+
+/// Sampling options of a request. Unset values fall back to the next level:
+/// template, then model, then the global defaults.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationSettings {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Vec<String>,
+    pub seed: Option<u64>,
+    /// JSON schema the answer has to match. Set by templates that fill forms
+    /// or produce plans.
+    pub json_schema: Option<serde_json::Value>,
+    /// How many snippets of the knowledge folder are added to the prompt.
+    /// Templates opt in, unset means no snippets.
+    pub knowledge_snippets: Option<usize>,
+    /// How a selection that doesn't fit the context window is split up.
+    /// Unset sends it in one piece.
+    pub chunking: Option<ChunkMode>,
+}
+
+impl GenerationSettings {
+    /// Returns `self` with every value set in `overrides` replaced.
+    pub fn merged_with(&self, overrides: &GenerationSettings) -> GenerationSettings {
+        GenerationSettings {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            stop: if overrides.stop.is_empty() {
+                self.stop.clone()
+            } else {
+                overrides.stop.clone()
+            },
+            seed: overrides.seed.or(self.seed),
+            json_schema: overrides
+                .json_schema
+                .clone()
+                .or_else(|| self.json_schema.clone()),
+            knowledge_snippets: overrides.knowledge_snippets.or(self.knowledge_snippets),
+            chunking: overrides.chunking.or(self.chunking),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == GenerationSettings::default()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationConfig {
+    pub global: GenerationSettings,
+    /// Keyed by `LLMType::description()`. Templates keep their own options.
+    pub per_model: HashMap<String, GenerationSettings>,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            global: GenerationSettings {
+                temperature: Some(0.2),
+                top_p: Some(0.5),
+                max_tokens: Some(500),
+                stop: vec![],
+                seed: None,
+                json_schema: None,
+                knowledge_snippets: None,
+                chunking: None,
+            },
+            per_model: HashMap::new(),
+        }
+    }
+}
+
+impl GenerationConfig {
+    pub fn for_model(&self, model_key: &str) -> GenerationSettings {
+        match self.per_model.get(model_key) {
+            Some(model_settings) => self.global.merged_with(model_settings),
+            None => self.global.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::create_prompt_templates;
+
+    #[test]
+    fn test_template_overrides_model_overrides_global() {
+        let mut config = GenerationConfig::default();
+        config.per_model.insert(
+            "Ollama - llama3.2".to_string(),
+            GenerationSettings {
+                max_tokens: Some(1000),
+                temperature: Some(0.4),
+                ..Default::default()
+            },
+        );
+        let settings = config
+            .for_model("Ollama - llama3.2")
+            .merged_with(&create_prompt_templates()["@correct"].options);
+        assert_eq!(settings.temperature, Some(0.0));
+        assert_eq!(settings.max_tokens, Some(1000));
+        assert_eq!(settings.top_p, Some(0.5));
+        assert_eq!(settings.seed, Some(42));
+    }
+}
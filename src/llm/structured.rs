@@ -0,0 +1,137 @@
+use serde_json::Value;
+
+/// Appended to the prompt so models without constrained decoding (cloud) know
+/// the expected shape. Local models, Ollama and OpenAI compatible servers
+/// additionally enforce the schema while sampling.
+pub fn schema_instruction(schema: &Value) -> String {
+    format!(
+        "Answer only with JSON that matches this JSON schema, without explanation:\n{}",
+        schema
+    )
+}
+
+/// Finds the JSON in an answer, with or without markdown code fences.
+pub fn extract_json(answer: &str) -> Option<Value> {
+    let answer = answer.trim();
+    if let Ok(value) = serde_json::from_str(answer) {
+        return Some(value);
+    }
+    let unfenced = answer
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    if let Ok(value) = serde_json::from_str(unfenced) {
+        return Some(value);
+    }
+    let start = answer.find(['{', '['])?;
+    let end = answer.rfind(['}', ']'])?;
+    if end <= start {
+        return None;
+    }
+    serde_json::from_str(&answer[start..=end]).ok()
+}
+
+/// Extracts the JSON of an answer, checks it against `schema` and returns it
+/// pretty printed.
+pub fn validated_json(answer: &str, schema: &Value) -> Result<String, String> {
+    let value = extract_json(answer).ok_or("The answer contains no valid JSON")?;
+    validate(&value, schema)
+        .map_err(|e| format!("The answer does not match the JSON schema: {}", e))?;
+    serde_json::to_string_pretty(&value).map_err(|e| e.to_string())
+}
+
+/// Checks the subset of JSON schema used by our templates: `type`,
+/// `properties`, `required`, `items` and `enum`.
+pub fn validate(value: &Value, schema: &Value) -> Result<(), String> {
+    validate_at(value, schema, "$")
+}
+
+fn validate_at(value: &Value, schema: &Value, path: &str) -> Result<(), String> {
+    if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+        let matches = match expected {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!("{} should be of type {}", path, expected));
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            return Err(format!(
+                "{} has a value that is not allowed: {}",
+                path, value
+            ));
+        }
+    }
+    if let Some(object) = value.as_object() {
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    return Err(format!("{} is missing {}", path, key));
+                }
+            }
+        }
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (key, property_schema) in properties {
+                if let Some(property) = object.get(key) {
+                    validate_at(property, property_schema, &format!("{}.{}", path, key))?;
+                }
+            }
+        }
+    }
+    if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            validate_at(item, item_schema, &format!("{}[{}]", path, index))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn form_fields_schema() -> Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "fields": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "caption": { "type": "string" },
+                        "content": { "type": "string" },
+                        "coordinates": { "type": "string" }
+                    },
+                    "required": ["caption", "content", "coordinates"]
+                }
+            }
+        },
+        "required": ["fields"]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_and_validate_form_fields() {
+        let answer = "Here you go:\n```json\n{\"fields\": [{\"caption\": \"Name\", \"content\": \"Ada\", \"coordinates\": \"[1, 2, 3, 4]\"}]}\n```";
+        let value = extract_json(answer).unwrap();
+        assert_eq!(validate(&value, &form_fields_schema()), Ok(()));
+
+        let missing_content = serde_json::json!({
+            "fields": [{ "caption": "Name", "coordinates": "[1, 2, 3, 4]" }]
+        });
+        assert_eq!(
+            validate(&missing_content, &form_fields_schema()),
+            Err("$.fields[0] is missing content".to_string())
+        );
+    }
+}
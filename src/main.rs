@@ -8,7 +8,6 @@
 use clap::Parser;
 #[cfg(feature = "computeruse_remote")]
 use rand::{Rng, distributions::Alphanumeric};
-use std::fs;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -17,7 +16,11 @@ use std::thread;
 #[macro_use]
 extern crate objc;
 
+mod assist_api;
+mod cli;
 mod llm;
+mod settings;
+mod templates;
 mod ui;
 #[cfg(feature = "computeruse_editor")]
 mod usecase_editor;
@@ -92,30 +95,9 @@ fn send_cmd_v() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn get_config_file(filename: &str) -> PathBuf {
-    let config_dir = dirs::home_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join(".plugovr");
-
-    // Create config directory if it doesn't exist
-    if let Err(e) = fs::create_dir_all(&config_dir) {
-        eprintln!("Failed to create config directory: {:?}", e);
-    }
-
-    config_dir.join(filename)
-}
-
-fn load_bool_config(filename: &str, default: bool) -> bool {
-    let config_file = get_config_file(filename);
-    fs::read_to_string(config_file)
-        .map(|s| s.trim() == "true")
-        .unwrap_or(default)
-}
-
-fn save_bool_config(filename: &str, value: bool) {
-    let config_file = get_config_file(filename);
-    if let Err(e) = fs::write(config_file, value.to_string()) {
-        eprintln!("Failed to save config {}: {:?}", filename, e);
+fn save_hide_ui(hide_ui: bool) {
+    if let Err(e) = settings::update(|settings| settings.hide_ui = hide_ui) {
+        eprintln!("Failed to save hide_ui: {:?}", e);
     }
 }
 
@@ -174,6 +156,23 @@ struct Args {
     /// Disable password protection for the webserver
     #[arg(long)]
     no_password: bool,
+
+    /// Serve the assistant API for other applications on localhost
+    #[arg(long)]
+    api: bool,
+
+    /// Port of the assistant API
+    #[arg(long, default_value_t = assist_api::DEFAULT_API_PORT)]
+    api_port: u16,
+
+    /// Directory of the settings, models and cache instead of ~/.plugovr
+    /// (also PLUGOVR_CONFIG_DIR)
+    #[arg(long, global = true)]
+    config_dir: Option<PathBuf>,
+
+    /// Run a command without starting the overlay
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 #[cfg(feature = "computeruse_remote")]
@@ -187,17 +186,14 @@ fn generate_random_password(length: usize) -> String {
 
 #[cfg(feature = "computeruse_remote")]
 fn load_password() -> Option<String> {
-    let password_file = get_config_file("webserver_password.txt");
-    match fs::read_to_string(password_file) {
-        Ok(password) if !password.trim().is_empty() => Some(password.trim().to_string()),
-        _ => None,
-    }
+    settings::load().webserver_password
 }
 
 #[cfg(feature = "computeruse_remote")]
 fn save_password(password: &str) {
-    let password_file = get_config_file("webserver_password.txt");
-    if let Err(e) = fs::write(password_file, password) {
+    if let Err(e) =
+        settings::update(|settings| settings.webserver_password = Some(password.to_string()))
+    {
         eprintln!("Failed to save password: {:?}", e);
     }
 }
@@ -205,13 +201,18 @@ fn save_password(password: &str) {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     // Parse command line arguments
-    #[cfg(feature = "computeruse_remote")]
     let args = Args::parse();
-    #[cfg(not(feature = "computeruse_remote"))]
-    let _args = Args::parse(); // Unused if computeruse_remote is not enabled
+    if let Some(config_dir) = args.config_dir.clone() {
+        settings::set_config_dir(config_dir);
+    }
+    if let Some(command) = args.command {
+        return cli::run(command).await;
+    }
 
     let text_entry = Arc::new(Mutex::new(false));
     let shortcut_window = Arc::new(Mutex::new(false));
+    let generating = Arc::new(Mutex::new(false));
+    let cancel_generation = Arc::new(Mutex::new(false));
     let control_pressed = Arc::new(Mutex::new(false));
     let alt_pressed = Arc::new(Mutex::new(false));
     let text_entryfield_position = Arc::new(Mutex::new((0, 0)));
@@ -221,7 +222,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     #[cfg(feature = "computeruse_editor")]
     let usecase_editor = Arc::new(Mutex::new(UsecaseEditor::new()));
     let mouse_position = Arc::new(Mutex::new((0, 0)));
-    let hide_ui = Arc::new(Mutex::new(load_bool_config("hide_ui.txt", false)));
+    let hide_ui = Arc::new(Mutex::new(settings::load().hide_ui));
     #[cfg(target_os = "linux")]
     let active_window = Arc::new(Mutex::new(ActiveWindow(0)));
     #[cfg(target_os = "windows")]
@@ -250,6 +251,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
         let ai_context = ai_context.clone();
         let active_window = active_window.clone();
         let alt_pressed = alt_pressed.clone();
+        let generating = generating.clone();
+        let cancel_generation = cancel_generation.clone();
         #[cfg(feature = "computeruse_record")]
         let usecase_recorder = usecase_recorder.clone();
         #[cfg(feature = "computeruse_replay")]
@@ -398,13 +401,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 {
                                     let mut hide_ui_guard = hide_ui.lock().unwrap();
                                     *hide_ui_guard = !*hide_ui_guard;
-                                    save_bool_config("hide_ui.txt", *hide_ui_guard);
+                                    save_hide_ui(*hide_ui_guard);
                                 }
                                 #[cfg(target_os = "macos")]
                                 if key == rdev::Key::KeyP && *control_pressed.lock().unwrap() {
                                     let mut hide_ui_guard = hide_ui.lock().unwrap();
                                     *hide_ui_guard = !*hide_ui_guard;
-                                    save_bool_config("hide_ui.txt", *hide_ui_guard);
+                                    save_hide_ui(*hide_ui_guard);
                                 }
                                 #[cfg(any(target_os = "linux", target_os = "windows"))]
                                 #[cfg(feature = "computeruse_record")]
@@ -420,6 +423,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                     usecase_recorder.lock().unwrap().show = true;
                                 }
                                 if key == rdev::Key::Escape
+                                    && *text_entry.lock().unwrap()
+                                    && *generating.lock().unwrap()
+                                {
+                                    // Stop the running generation but keep the window open
+                                    *cancel_generation.lock().unwrap() = true;
+                                } else if key == rdev::Key::Escape
                                     && (*text_entry.lock().unwrap()
                                         || *shortcut_window.lock().unwrap())
                                 {
@@ -530,6 +539,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
             ai_context,
             active_window,
             shortcut_window,
+            generating,
+            cancel_generation,
+            args.api.then_some(args.api_port),
             #[cfg(feature = "computeruse_record")]
             usecase_recorder,
             #[cfg(feature = "computeruse_replay")]
@@ -0,0 +1,511 @@
+//! Everything PlugOvr stores, in one schema-versioned `settings.json`.
+//!
+//! The config directory is `~/.plugovr` unless `--config-dir` or the
+//! `PLUGOVR_CONFIG_DIR` environment variable point elsewhere, so tests and
+//! separate setups never touch the real one. The files of older versions, one
+//! per value, are migrated on the first start and moved to `legacy/`.
+//!
+//! The model, the templates and the replay servers belong to a profile, e.g.
+//! one per client, the rest is shared by all profiles.
+
+use crate::llm::options::{GenerationConfig, GenerationSettings, MAX_SEED};
+use crate::llm::{
+    CacheSettings, CloudModel, CustomModel, DEFAULT_FALLBACK_TIMEOUT_SECS, DEFAULT_OLLAMA_URL,
+    LLMType,
+};
+use crate::templates::{PromptTemplate, Templates};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Increase when a value is renamed or changes its meaning, and convert the
+/// older files in `parse`.
+pub const SETTINGS_VERSION: u32 = 1;
+pub const CONFIG_DIR_ENV: &str = "PLUGOVR_CONFIG_DIR";
+pub const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:8000/v1";
+pub const DEFAULT_PROFILE: &str = "Default";
+const SETTINGS_FILE: &str = "settings.json";
+const INVALID_SETTINGS_FILE: &str = "settings.invalid.json";
+const LEGACY_DIR: &str = "legacy";
+const LEGACY_FILES: [&str; 6] = [
+    "llm_type.json",
+    "templates.json",
+    "server_url_planning.json",
+    "server_url_execution.json",
+    "hide_ui.txt",
+    "webserver_password.txt",
+];
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub llm_type: LLMType,
+    /// None until the user edits the templates, the built-in ones are used.
+    pub templates: Option<Templates>,
+    pub server_url_planning: String,
+    pub server_url_execution: String,
+    /// Only models that run on this machine may answer, no cloud models and
+    /// no remote servers.
+    pub local_only: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            llm_type: LLMType::Cloud(CloudModel::AnthropicHaiku),
+            templates: None,
+            server_url_planning: DEFAULT_SERVER_URL.to_string(),
+            server_url_execution: DEFAULT_SERVER_URL.to_string(),
+            local_only: false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub active_profile: String,
+    pub profiles: BTreeMap<String, Profile>,
+    pub hide_ui: bool,
+    pub webserver_password: Option<String>,
+    /// Token of the assistant API.
+    pub api_token: Option<String>,
+    pub ollama_url: String,
+    /// API keys of OpenAI compatible servers by base URL.
+    pub openai_api_keys: BTreeMap<String, String>,
+    pub custom_models: Vec<CustomModel>,
+    /// Tried in this order when the selected model fails before answering.
+    pub fallback_llms: Vec<LLMType>,
+    /// Seconds a model may take to accept a request before the next fallback
+    /// model is tried.
+    pub fallback_timeout_secs: u64,
+    pub cache: CacheSettings,
+    pub generation: GenerationConfig,
+    /// Shared template packs, loaded read-only beneath the templates of the
+    /// active profile.
+    pub team_templates_dir: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), Profile::default())]),
+            hide_ui: false,
+            webserver_password: None,
+            api_token: None,
+            ollama_url: DEFAULT_OLLAMA_URL.to_string(),
+            openai_api_keys: BTreeMap::new(),
+            custom_models: vec![],
+            fallback_llms: vec![],
+            fallback_timeout_secs: DEFAULT_FALLBACK_TIMEOUT_SECS,
+            cache: CacheSettings::default(),
+            generation: GenerationConfig::default(),
+            team_templates_dir: None,
+        }
+    }
+}
+
+impl Settings {
+    /// The active profile, the defaults if it doesn't exist.
+    pub fn profile(&self) -> Profile {
+        self.profiles
+            .get(&self.active_profile)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        self.profiles
+            .entry(self.active_profile.clone())
+            .or_default()
+    }
+
+    /// Values PlugOvr can't use, shown in the main window.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.version > SETTINGS_VERSION {
+            problems.push(format!(
+                "The settings were written by a newer PlugOvr (version {}), \
+                 values it added are lost when saving",
+                self.version
+            ));
+        }
+        if !self.profiles.contains_key(&self.active_profile) {
+            problems.push(format!(
+                "The active profile {} does not exist",
+                self.active_profile
+            ));
+        }
+        if !is_http_url(&self.ollama_url) {
+            problems.push(format!(
+                "Ollama URL \"{}\" is not a http(s) URL",
+                self.ollama_url
+            ));
+        }
+        for (profile_name, profile) in &self.profiles {
+            for (name, url) in [
+                ("planning server URL", &profile.server_url_planning),
+                ("execution server URL", &profile.server_url_execution),
+            ] {
+                if !is_http_url(url) {
+                    problems.push(format!(
+                        "Profile {}: {} \"{}\" is not a http(s) URL",
+                        profile_name, name, url
+                    ));
+                }
+            }
+            for (name, template) in profile.templates.iter().flatten() {
+                if template.is_newer_version() {
+                    problems.push(format!(
+                        "Profile {}: template {} was written by a newer PlugOvr (version {}), \
+                         values it added are lost when saving",
+                        profile_name, name, template.version
+                    ));
+                }
+                if !name.starts_with('@') {
+                    problems.push(format!(
+                        "Profile {}: template \"{}\" has to start with @",
+                        profile_name, name
+                    ));
+                }
+                if template.instruction.trim().is_empty() {
+                    problems.push(format!(
+                        "Profile {}: template {} has no instruction",
+                        profile_name, name
+                    ));
+                }
+                for problem in crate::templates::check_template(name, &template.instruction)
+                    .into_iter()
+                    .chain(validate_generation_settings(&template.options))
+                {
+                    problems.push(format!(
+                        "Profile {}: template {}: {}",
+                        profile_name, name, problem
+                    ));
+                }
+            }
+        }
+        for custom_model in &self.custom_models {
+            for path in [&custom_model.model_path, &custom_model.tokenizer_path] {
+                if !path.exists() {
+                    problems.push(format!(
+                        "File {} of model {} does not exist",
+                        path.display(),
+                        custom_model.name
+                    ));
+                }
+            }
+        }
+        if let Some(dir) = &self.team_templates_dir {
+            if !dir.is_dir() {
+                problems.push(format!(
+                    "The team template directory {} does not exist",
+                    dir.display()
+                ));
+            }
+        }
+        if self.cache.max_size_mb == 0 {
+            problems.push("The response cache has a size of 0 MB".to_string());
+        }
+        let generation = std::iter::once(("defaults", &self.generation.global)).chain(
+            self.generation
+                .per_model
+                .iter()
+                .map(|(k, v)| (k.as_str(), v)),
+        );
+        for (name, settings) in generation {
+            problems.extend(
+                validate_generation_settings(settings)
+                    .into_iter()
+                    .map(|problem| format!("Generation options of {}: {}", name, problem)),
+            );
+        }
+        problems
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+fn validate_generation_settings(settings: &GenerationSettings) -> Vec<String> {
+    let mut problems = vec![];
+    if settings
+        .temperature
+        .is_some_and(|temperature| !(0.0..=2.0).contains(&temperature))
+    {
+        problems.push("temperature has to be between 0 and 2".to_string());
+    }
+    if settings
+        .top_p
+        .is_some_and(|top_p| !(0.0..=1.0).contains(&top_p))
+    {
+        problems.push("top-p has to be between 0 and 1".to_string());
+    }
+    if settings.max_tokens == Some(0) {
+        problems.push("max tokens has to be at least 1".to_string());
+    }
+    if settings.seed.is_some_and(|seed| seed > MAX_SEED) {
+        problems.push(format!("the seed can be at most {}", MAX_SEED));
+    }
+    problems
+}
+
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+/// Counts the changes of the settings, so windows only read them again after
+/// one.
+static REVISION: AtomicU64 = AtomicU64::new(0);
+
+struct Store {
+    settings: Settings,
+    /// Errors of reading and migrating, reported beside the validation errors.
+    problems: Vec<String>,
+}
+
+/// Uses `dir` instead of `~/.plugovr`. Has to be called before anything is
+/// loaded.
+pub fn set_config_dir(dir: PathBuf) {
+    if CONFIG_DIR.set(dir).is_err() {
+        eprintln!("The config directory is already in use and can't be changed");
+    }
+}
+
+pub fn config_dir() -> PathBuf {
+    CONFIG_DIR
+        .get_or_init(|| match std::env::var_os(CONFIG_DIR_ENV) {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => dirs::home_dir()
+                .expect("Unable to get home directory")
+                .join(".plugovr"),
+        })
+        .clone()
+}
+
+/// Path of a file in the config directory, e.g. the cache or the models.
+pub fn config_path(name: &str) -> PathBuf {
+    config_dir().join(name)
+}
+
+pub fn load() -> Settings {
+    let mut store = STORE.lock().unwrap();
+    store
+        .get_or_insert_with(|| read_from(&config_dir()))
+        .settings
+        .clone()
+}
+
+/// Changes the settings and saves them.
+pub fn update(change: impl FnOnce(&mut Settings)) -> std::io::Result<()> {
+    let mut store = STORE.lock().unwrap();
+    let store = store.get_or_insert_with(|| read_from(&config_dir()));
+    change(&mut store.settings);
+    store.settings.version = SETTINGS_VERSION;
+    REVISION.fetch_add(1, Ordering::Relaxed);
+    write_to(&config_dir(), &store.settings)
+}
+
+/// Changes whenever the settings change, cheap enough to compare every frame.
+pub fn revision() -> u64 {
+    REVISION.load(Ordering::Relaxed)
+}
+
+/// Makes `name` the active profile. The running windows pick it up on their
+/// next frame.
+pub fn switch_profile(name: &str) -> std::io::Result<()> {
+    if !load().profiles.contains_key(name) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("There is no profile {}", name),
+        ));
+    }
+    update(|settings| settings.active_profile = name.to_string())
+}
+
+/// Reads `settings.json` again, e.g. after it was fixed by hand.
+pub fn reload() {
+    *STORE.lock().unwrap() = Some(read_from(&config_dir()));
+    REVISION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Everything that went wrong reading the settings and every invalid value.
+pub fn problems() -> Vec<String> {
+    let mut store = STORE.lock().unwrap();
+    let store = store.get_or_insert_with(|| read_from(&config_dir()));
+    let mut problems = store.problems.clone();
+    problems.extend(store.settings.validate());
+    problems
+}
+
+fn read_from(dir: &Path) -> Store {
+    let path = dir.join(SETTINGS_FILE);
+    let mut problems = vec![];
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => match parse(&contents) {
+            Ok(settings) => return Store { settings, problems },
+            Err(e) => {
+                // Saving would overwrite it, keep it for the user instead.
+                let invalid = dir.join(INVALID_SETTINGS_FILE);
+                match std::fs::rename(&path, &invalid) {
+                    Ok(()) => problems.push(format!(
+                        "{} could not be read and was moved to {}, the defaults are used: {}",
+                        path.display(),
+                        invalid.display(),
+                        e
+                    )),
+                    Err(rename_error) => problems.push(format!(
+                        "{} could not be read: {} (moving it failed: {})",
+                        path.display(),
+                        e,
+                        rename_error
+                    )),
+                }
+                return Store {
+                    settings: Settings::default(),
+                    problems,
+                };
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            problems.push(format!("{} could not be read: {}", path.display(), e));
+            return Store {
+                settings: Settings::default(),
+                problems,
+            };
+        }
+    }
+    let settings = migrate_legacy_files(dir, &mut problems);
+    match write_to(dir, &settings) {
+        Ok(()) => retire_legacy_files(dir, &mut problems),
+        Err(e) => problems.push(format!("Failed to save {}: {}", path.display(), e)),
+    }
+    Store { settings, problems }
+}
+
+fn parse(contents: &str) -> Result<Settings, String> {
+    serde_json::from_str(contents).map_err(|e| e.to_string())
+}
+
+/// Templates of `templates.json`, `[instruction, model, shortcut]`.
+type LegacyTemplates = HashMap<String, (String, Option<LLMType>, bool)>;
+
+fn from_legacy_templates(templates: LegacyTemplates) -> Templates {
+    templates
+        .into_iter()
+        .map(|(name, template)| (name, PromptTemplate::from(template)))
+        .collect()
+}
+
+fn write_to(dir: &Path, settings: &Settings) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let serialized = serde_json::to_string_pretty(settings)?;
+    // Written next to it first, so a crash never leaves half a file.
+    let temporary = dir.join(format!("{}.tmp", SETTINGS_FILE));
+    std::fs::write(&temporary, serialized)?;
+    std::fs::rename(temporary, dir.join(SETTINGS_FILE))
+}
+
+fn read_legacy_json<T: DeserializeOwned>(
+    dir: &Path,
+    name: &str,
+    problems: &mut Vec<String>,
+) -> Option<T> {
+    let contents = std::fs::read_to_string(dir.join(name)).ok()?;
+    serde_json::from_str(&contents)
+        .map_err(|e| problems.push(format!("{} could not be migrated: {}", name, e)))
+        .ok()
+}
+
+fn read_legacy_text(dir: &Path, name: &str) -> Option<String> {
+    let text = std::fs::read_to_string(dir.join(name)).ok()?;
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// Collects the values of the files older versions wrote, missing or broken
+/// files keep the default.
+fn migrate_legacy_files(dir: &Path, problems: &mut Vec<String>) -> Settings {
+    let mut settings = Settings::default();
+    let profile = settings.profile_mut();
+    if let Some(llm_type) = read_legacy_json(dir, "llm_type.json", problems) {
+        profile.llm_type = llm_type;
+    }
+    profile.templates =
+        read_legacy_json(dir, "templates.json", problems).map(from_legacy_templates);
+    if let Some(url) = read_legacy_json(dir, "server_url_planning.json", problems) {
+        profile.server_url_planning = url;
+    }
+    if let Some(url) = read_legacy_json(dir, "server_url_execution.json", problems) {
+        profile.server_url_execution = url;
+    }
+    settings.hide_ui = read_legacy_text(dir, "hide_ui.txt").is_some_and(|text| text == "true");
+    settings.webserver_password = read_legacy_text(dir, "webserver_password.txt");
+    settings
+}
+
+/// Moves the migrated files out of the way, they are not read anymore.
+fn retire_legacy_files(dir: &Path, problems: &mut Vec<String>) {
+    let legacy_dir = dir.join(LEGACY_DIR);
+    for name in LEGACY_FILES {
+        let path = dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        if let Err(e) = std::fs::create_dir_all(&legacy_dir)
+            .and_then(|()| std::fs::rename(&path, legacy_dir.join(name)))
+        {
+            problems.push(format!("Failed to move {}: {}", path.display(), e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::LocalModel;
+
+    #[test]
+    fn test_legacy_files_are_migrated_once() {
+        let dir = std::env::temp_dir().join(format!("plugovr_settings_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("llm_type.json"), r#"{"Local":"Llama32S1bChat"}"#).unwrap();
+        std::fs::write(
+            dir.join("templates.json"),
+            r#"{"@shout": ["Make it uppercase", null, true]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("server_url_planning.json"),
+            r#""http://10.0.0.2:8000/v1""#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("hide_ui.txt"), "true").unwrap();
+        std::fs::write(dir.join("webserver_password.txt"), "secret\n").unwrap();
+        std::fs::write(dir.join("server_url_execution.json"), "not json").unwrap();
+
+        let store = read_from(&dir);
+        let settings = &store.settings;
+        let profile = settings.profile();
+        assert!(profile.llm_type == LLMType::Local(LocalModel::Llama32S1bChat));
+        assert!(profile.templates.as_ref().unwrap().contains_key("@shout"));
+        assert_eq!(profile.server_url_planning, "http://10.0.0.2:8000/v1");
+        assert_eq!(profile.server_url_execution, DEFAULT_SERVER_URL);
+        assert!(settings.hide_ui);
+        assert_eq!(settings.webserver_password.as_deref(), Some("secret"));
+        assert_eq!(store.problems.len(), 1);
+        assert!(!dir.join("llm_type.json").exists());
+        assert!(dir.join(LEGACY_DIR).join("llm_type.json").exists());
+
+        let reread = read_from(&dir);
+        assert!(reread.settings == store.settings);
+        assert!(reread.problems.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
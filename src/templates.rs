@@ -0,0 +1,659 @@
+//! Prompt templates, their parameters and built-in variables.
+//!
+//! A template named `@translate(lang)` is used as `@translate(german)` and its
+//! instruction refers to the argument as `{{lang}}`. A parameter can list the
+//! values it accepts, `@translate(lang=english|german|spanish)`, those are
+//! validated and suggested in the text entry. `{{selection}}`, `{{clipboard}}`,
+//! `{{date}}`, `{{active_app}}` and `{{user_name}}` work in every template and
+//! in typed instructions.
+
+use crate::llm::chunking::ChunkMode;
+use crate::llm::options::GenerationSettings;
+use crate::llm::structured::form_fields_schema;
+use crate::llm::{CloudModel, LLMType};
+use crate::ui::assistance_window::AiResponseAction;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::LazyLock;
+
+pub mod packs;
+
+/// Increase when a field is renamed or changes its meaning. New fields only
+/// need a default.
+pub const TEMPLATE_VERSION: u32 = 1;
+pub const BUILTIN_VARIABLES: [&str; 5] =
+    ["selection", "clipboard", "date", "active_app", "user_name"];
+/// Calls suggested for one template, several parameters with choices multiply.
+const MAX_CALLS: usize = 50;
+
+static VARIABLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*([A-Za-z0-9_]+)\s*\}\}").unwrap());
+
+pub type Templates = HashMap<String, PromptTemplate>;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromptTemplate {
+    pub version: u32,
+    pub instruction: String,
+    /// None uses the model selected in the GUI.
+    pub llm_type: Option<LLMType>,
+    /// Shown as a button in the assistance and shortcut windows.
+    pub shortcut: bool,
+    pub description: String,
+    /// What happens with the answer, None keeps the choice of the window.
+    pub response_action: Option<AiResponseAction>,
+    /// Override the options of the model, see `GenerationSettings`.
+    pub options: GenerationSettings,
+    pub tags: Vec<String>,
+    /// Shown in front of the name on the shortcut buttons, e.g. an emoji.
+    pub icon: String,
+    /// Applications the template is meant for, listed first while one of them
+    /// is focused: names or window classes like `firefox`, or `title:` and a
+    /// part of the window title like `title:github`. Empty for all.
+    pub apps: Vec<String>,
+    /// File name of the team pack the template comes from, those are read-only
+    /// and not saved with the personal templates.
+    #[serde(skip)]
+    pub team_pack: Option<String>,
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        Self {
+            version: TEMPLATE_VERSION,
+            instruction: String::new(),
+            llm_type: None,
+            shortcut: false,
+            description: String::new(),
+            response_action: None,
+            options: GenerationSettings::default(),
+            tags: vec![],
+            icon: String::new(),
+            apps: vec![],
+            team_pack: None,
+        }
+    }
+}
+
+/// Templates were stored as `[instruction, model, shortcut]` before.
+impl From<(String, Option<LLMType>, bool)> for PromptTemplate {
+    fn from((instruction, llm_type, shortcut): (String, Option<LLMType>, bool)) -> Self {
+        Self {
+            instruction,
+            llm_type,
+            shortcut,
+            ..Default::default()
+        }
+    }
+}
+
+impl PromptTemplate {
+    fn new(instruction: &str, shortcut: bool) -> Self {
+        Self {
+            instruction: instruction.to_string(),
+            shortcut,
+            ..Default::default()
+        }
+    }
+
+    /// Written by a newer PlugOvr, fields it added are lost when it is saved.
+    pub fn is_newer_version(&self) -> bool {
+        self.version > TEMPLATE_VERSION
+    }
+
+    /// 0 if the template is scoped to the focused application, 1 if it has no
+    /// scope and 2 if it is meant for other applications.
+    pub fn rank(&self, app: &FocusedApp) -> u8 {
+        if self.apps.is_empty() {
+            return 1;
+        }
+        let matches = self.apps.iter().any(|scope| {
+            let scope = scope.trim().to_lowercase();
+            match scope.strip_prefix("title:") {
+                Some(part) => app
+                    .title
+                    .as_ref()
+                    .is_some_and(|title| title.to_lowercase().contains(part.trim())),
+                None => app
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| name.to_lowercase() == scope),
+            }
+        });
+        if matches { 0 } else { 2 }
+    }
+
+    /// The label of the shortcut button for `call`.
+    pub fn button_text(&self, call: &str) -> String {
+        if self.icon.is_empty() {
+            call.to_string()
+        } else {
+            format!("{} {}", self.icon, call)
+        }
+    }
+}
+
+pub fn create_prompt_templates() -> Templates {
+    let mut templates = Templates::new();
+    // Rewrites work on every part of a long selection on their own.
+    let rewrite = GenerationSettings {
+        chunking: Some(ChunkMode::Concatenate),
+        ..Default::default()
+    };
+    templates.insert(
+        "@correct".to_string(),
+        PromptTemplate {
+            description: "Fixes spelling and grammar".to_string(),
+            options: GenerationSettings {
+                temperature: Some(0.0),
+                seed: Some(42),
+                chunking: Some(ChunkMode::Concatenate),
+                ..Default::default()
+            },
+            ..PromptTemplate::new("Correct the text without explanation", true)
+        },
+    );
+    templates.insert(
+        "@translate(lang=english|german|spanish|french)".to_string(),
+        PromptTemplate {
+            options: rewrite.clone(),
+            ..PromptTemplate::new("Translate the text to {{lang}} without explanation", true)
+        },
+    );
+    templates.insert(
+        "@summarize".to_string(),
+        PromptTemplate {
+            options: GenerationSettings {
+                chunking: Some(ChunkMode::Reduce),
+                ..Default::default()
+            },
+            ..PromptTemplate::new("Provide a short summary of the text", true)
+        },
+    );
+    templates.insert(
+        "@improve".to_string(),
+        PromptTemplate {
+            options: rewrite.clone(),
+            ..PromptTemplate::new(
+                "Suggest improvements or enhancements for the given text without explanation",
+                false,
+            )
+        },
+    );
+    templates.insert(
+        "@format".to_string(),
+        PromptTemplate {
+            options: rewrite.clone(),
+            ..PromptTemplate::new(
+                "Format the text for better readability without explanation",
+                true,
+            )
+        },
+    );
+    templates.insert(
+        "@simplify".to_string(),
+        PromptTemplate {
+            options: rewrite,
+            ..PromptTemplate::new("Simplify complex text or concepts", false)
+        },
+    );
+    templates.insert(
+        "@extend".to_string(),
+        PromptTemplate {
+            response_action: Some(AiResponseAction::Extend),
+            options: GenerationSettings {
+                temperature: Some(0.8),
+                top_p: Some(0.9),
+                ..Default::default()
+            },
+            ..PromptTemplate::new("continue the text without explanation", true)
+        },
+    );
+    templates.insert(
+        "@filename".to_string(),
+        PromptTemplate::new("propose filename for document: structure:date(year_month_day )_topic_company. Output only filename", false),
+    );
+    templates.insert(
+        "@fillform".to_string(),
+        PromptTemplate {
+            llm_type: Some(LLMType::Cloud(CloudModel::AnthropicSonnet3_5)),
+            description: "Fills the fields of the form in the screenshot".to_string(),
+            options: GenerationSettings {
+                temperature: Some(0.0),
+                json_schema: Some(form_fields_schema()),
+                ..Default::default()
+            },
+            ..PromptTemplate::new("#computeruse Output the coordinates for each input field / textbox in json format from image 1 (screenshot) and fill with information from images starting from image 2. The original textbox should be empty before we fill it. Json format: {\"fields\": [{ \"caption\": \"<caption>\", \"content\": \"<content>\", \"coordinates\": \"[x1, y1, x2, y2]\" }]}", false)
+        },
+    );
+    templates
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    /// Accepted values, any value if empty.
+    pub choices: Vec<String>,
+}
+
+/// A template used in an instruction, with its arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invocation {
+    /// Name of the template in the templates.
+    pub key: String,
+    /// The text that calls it, e.g. `@translate(german)`.
+    pub call: String,
+    pub arguments: Vec<(String, String)>,
+}
+
+/// The application the text was selected in.
+#[derive(Clone, Debug, Default)]
+pub struct FocusedApp {
+    pub name: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Values of the built-in variables that depend on where the template runs.
+#[derive(Default)]
+pub struct Variables<'a> {
+    pub selection: &'a str,
+    pub active_app: Option<String>,
+}
+
+/// Name and parameters of a template with parameters. Every parameter has to
+/// be used in the instruction, so `@translate(english)` of older versions
+/// stays a template without parameters.
+pub fn parameters<'a>(key: &'a str, instruction: &str) -> Option<(&'a str, Vec<Parameter>)> {
+    let (name, rest) = key.split_once('(')?;
+    let list = rest.strip_suffix(')')?;
+    let parameters = list
+        .split(',')
+        .map(|parameter| match parameter.split_once('=') {
+            Some((name, choices)) => Parameter {
+                name: name.trim().to_string(),
+                choices: choices
+                    .split('|')
+                    .map(|choice| choice.trim().to_string())
+                    .filter(|choice| !choice.is_empty())
+                    .collect(),
+            },
+            None => Parameter {
+                name: parameter.trim().to_string(),
+                choices: vec![],
+            },
+        })
+        .collect::<Vec<_>>();
+    let used = used_variables(instruction);
+    parameters
+        .iter()
+        .all(|parameter| used.contains(&parameter.name))
+        .then_some((name, parameters))
+}
+
+fn used_variables(text: &str) -> BTreeSet<String> {
+    VARIABLE
+        .captures_iter(text)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+/// Finds the template used in `text`. The longest match wins, so
+/// `@translate(german)` calls `@translate(lang)` and not a `@translate`
+/// template. An error tells what is wrong with the arguments.
+pub fn find_template(text: &str, templates: &Templates) -> Option<Result<Invocation, String>> {
+    let mut keys = templates.keys().collect::<Vec<_>>();
+    keys.sort();
+    let literal = keys
+        .iter()
+        .filter(|key| {
+            parameters(key, &templates[key.as_str()].instruction).is_none()
+                && text.contains(key.as_str())
+        })
+        .max_by_key(|key| key.len());
+    let mut found: Option<(usize, Result<Invocation, String>)> = None;
+    for key in &keys {
+        let Some((name, parameters)) = parameters(key, &templates[key.as_str()].instruction) else {
+            continue;
+        };
+        let Some(start) = text.find(&format!("{}(", name)) else {
+            continue;
+        };
+        let arguments_start = start + name.len() + 1;
+        let result = match text[arguments_start..].find(')') {
+            Some(end) => {
+                let call = &text[start..arguments_start + end + 1];
+                bind_arguments(
+                    name,
+                    &parameters,
+                    &text[arguments_start..arguments_start + end],
+                )
+                .map(|arguments| Invocation {
+                    key: key.to_string(),
+                    call: call.to_string(),
+                    arguments,
+                })
+            }
+            None => Err(format!("Close the arguments of {} with )", name)),
+        };
+        let length = match &result {
+            Ok(invocation) => invocation.call.len(),
+            Err(_) => name.len() + 1,
+        };
+        if found.as_ref().is_none_or(|(best, _)| length > *best) {
+            found = Some((length, result));
+        }
+    }
+    match (literal, found) {
+        (Some(key), Some((length, _))) if key.len() >= length => Some(Ok(literal_call(key))),
+        (_, Some((_, result))) => Some(result),
+        (Some(key), None) => Some(Ok(literal_call(key))),
+        (None, None) => None,
+    }
+}
+
+fn literal_call(key: &str) -> Invocation {
+    Invocation {
+        key: key.to_string(),
+        call: key.to_string(),
+        arguments: vec![],
+    }
+}
+
+fn bind_arguments(
+    name: &str,
+    parameters: &[Parameter],
+    arguments: &str,
+) -> Result<Vec<(String, String)>, String> {
+    let arguments = arguments.split(',').map(str::trim).collect::<Vec<_>>();
+    if arguments.len() != parameters.len() {
+        return Err(format!(
+            "{} takes {} argument(s): {}",
+            name,
+            parameters.len(),
+            parameters
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    parameters
+        .iter()
+        .zip(arguments)
+        .map(|(parameter, argument)| {
+            if argument.is_empty() {
+                return Err(format!("{} needs a value for {}", name, parameter.name));
+            }
+            if parameter.choices.is_empty() {
+                return Ok((parameter.name.clone(), argument.to_string()));
+            }
+            match parameter
+                .choices
+                .iter()
+                .find(|choice| choice.eq_ignore_ascii_case(argument))
+            {
+                Some(choice) => Ok((parameter.name.clone(), choice.clone())),
+                None => Err(format!(
+                    "{} is not a valid {}, use one of {}",
+                    argument,
+                    parameter.name,
+                    parameter.choices.join(", ")
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Replaces the call in `text` with the instruction of the template and fills
+/// in the arguments and the built-in variables.
+pub fn expand(
+    text: &str,
+    invocation: &Invocation,
+    instruction: &str,
+    variables: &Variables,
+) -> String {
+    let instruction = VARIABLE.replace_all(instruction, |captures: &regex::Captures| {
+        invocation
+            .arguments
+            .iter()
+            .find(|(name, _)| *name == captures[1])
+            .map_or_else(|| captures[0].to_string(), |(_, value)| value.clone())
+    });
+    fill_variables(&text.replace(&invocation.call, &instruction), variables)
+}
+
+/// Fills in the built-in variables, unknown ones are left as they are. The
+/// clipboard is only read when it is used.
+pub fn fill_variables(text: &str, variables: &Variables) -> String {
+    VARIABLE
+        .replace_all(text, |captures: &regex::Captures| {
+            match &captures[1] {
+                "selection" => Some(variables.selection.to_string()),
+                "clipboard" => Some(
+                    arboard::Clipboard::new()
+                        .and_then(|mut clipboard| clipboard.get_text())
+                        .unwrap_or_default(),
+                ),
+                "date" => Some(chrono::Local::now().format("%Y-%m-%d").to_string()),
+                "active_app" => Some(variables.active_app.clone().unwrap_or_default()),
+                "user_name" => Some(user_name()),
+                _ => None,
+            }
+            .unwrap_or_else(|| captures[0].to_string())
+        })
+        .to_string()
+}
+
+fn user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+/// `{{...}}` in the instruction that is neither a parameter nor a built-in
+/// variable.
+pub fn check_template(key: &str, instruction: &str) -> Vec<String> {
+    let parameters = parameters(key, instruction)
+        .map(|(_, parameters)| parameters)
+        .unwrap_or_default();
+    used_variables(instruction)
+        .into_iter()
+        .filter(|variable| {
+            !BUILTIN_VARIABLES.contains(&variable.as_str())
+                && !parameters
+                    .iter()
+                    .any(|parameter| parameter.name == *variable)
+        })
+        .map(|variable| format!("{{{{{}}}}} is neither a parameter nor a variable", variable))
+        .collect()
+}
+
+/// The complete calls of a template: its name or one call per combination of
+/// choices. Empty if a parameter takes any value.
+pub fn calls(key: &str, instruction: &str) -> Vec<String> {
+    let Some((name, parameters)) = parameters(key, instruction) else {
+        return vec![key.to_string()];
+    };
+    let mut combinations = vec![vec![]];
+    for parameter in &parameters {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination: Vec<&str>| {
+                parameter.choices.iter().map(move |choice| {
+                    let mut combination = combination.clone();
+                    combination.push(choice.as_str());
+                    combination
+                })
+            })
+            .take(MAX_CALLS)
+            .collect();
+    }
+    combinations
+        .into_iter()
+        .map(|combination| format!("{}({})", name, combination.join(", ")))
+        .collect()
+}
+
+/// The calls of the shortcut templates, those scoped to `app` first.
+pub fn shortcut_calls<'a>(
+    templates: &'a Templates,
+    app: &FocusedApp,
+) -> Vec<(String, &'a PromptTemplate)> {
+    let mut calls = templates
+        .iter()
+        .filter(|(_, template)| template.shortcut)
+        .flat_map(|(key, template)| {
+            calls(key, &template.instruction)
+                .into_iter()
+                .map(move |call| (call, template))
+        })
+        .collect::<Vec<_>>();
+    calls.sort_by_key(|(call, template)| (template.rank(app), call.clone()));
+    calls
+}
+
+/// What the text entry suggests, those scoped to `app` first. Templates with
+/// a free parameter are suggested up to the parenthesis, e.g. `@reply(`.
+pub fn suggestions(templates: &Templates, app: &FocusedApp) -> Vec<String> {
+    let mut suggestions = templates
+        .iter()
+        .flat_map(|(key, template)| {
+            let calls = calls(key, &template.instruction);
+            let calls = if calls.is_empty() {
+                let name = key.split_once('(').map_or(key.as_str(), |(name, _)| name);
+                vec![format!("{}(", name)]
+            } else {
+                calls
+            };
+            calls.into_iter().map(|call| (template.rank(app), call))
+        })
+        .collect::<Vec<_>>();
+    suggestions.sort();
+    let mut seen = BTreeSet::new();
+    suggestions
+        .into_iter()
+        .filter(|(_, suggestion)| seen.insert(suggestion.clone()))
+        .map(|(_, suggestion)| suggestion)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameters_are_validated_and_filled_in() {
+        let mut templates = Templates::new();
+        templates.insert(
+            "@translate(lang=english|german)".to_string(),
+            PromptTemplate::new("Translate the text to {{lang}}", true),
+        );
+        templates.insert(
+            "@translate(french)".to_string(),
+            PromptTemplate::new("Traduire en français", true),
+        );
+        templates.insert(
+            "@reply(tone)".to_string(),
+            PromptTemplate::new("Reply {{tone}} to {{user_name}}", false),
+        );
+
+        let invocation = find_template("@translate(german) please", &templates)
+            .unwrap()
+            .unwrap();
+        assert_eq!(invocation.key, "@translate(lang=english|german)");
+        let instruction = &templates[&invocation.key].instruction;
+        assert_eq!(
+            expand(
+                "@translate(german) please",
+                &invocation,
+                instruction,
+                &Variables::default()
+            ),
+            "Translate the text to german please"
+        );
+        assert_eq!(
+            find_template("@translate(french)", &templates)
+                .unwrap()
+                .unwrap()
+                .key,
+            "@translate(french)"
+        );
+        assert!(
+            find_template("@translate(klingon)", &templates)
+                .unwrap()
+                .is_err()
+        );
+        assert!(find_template("@reply(a, b)", &templates).unwrap().is_err());
+        assert!(find_template("hello", &templates).is_none());
+
+        assert_eq!(
+            suggestions(&templates, &FocusedApp::default()),
+            [
+                "@reply(",
+                "@translate(english)",
+                "@translate(french)",
+                "@translate(german)"
+            ]
+        );
+        assert_eq!(
+            check_template("@reply", "Reply {{tone}}"),
+            ["{{tone}} is neither a parameter nor a variable"]
+        );
+    }
+
+    #[test]
+    fn test_scoped_templates_come_first() {
+        let mut templates = Templates::new();
+        templates.insert(
+            "@summarize".to_string(),
+            PromptTemplate::new("Summarize the text", true),
+        );
+        templates.insert(
+            "@fillform".to_string(),
+            PromptTemplate {
+                apps: vec!["Firefox".to_string(), "title:github".to_string()],
+                ..PromptTemplate::new("Fill the form", true)
+            },
+        );
+        templates.insert(
+            "@shell".to_string(),
+            PromptTemplate {
+                apps: vec!["gnome-terminal-server".to_string()],
+                ..PromptTemplate::new("Write a shell command", true)
+            },
+        );
+        let order = |app: FocusedApp| {
+            shortcut_calls(&templates, &app)
+                .into_iter()
+                .map(|(call, _)| call)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            order(FocusedApp {
+                name: Some("firefox".to_string()),
+                title: None,
+            }),
+            ["@fillform", "@summarize", "@shell"]
+        );
+        assert_eq!(
+            order(FocusedApp {
+                name: Some("code".to_string()),
+                title: Some("Pull requests · GitHub".to_string()),
+            }),
+            ["@fillform", "@summarize", "@shell"]
+        );
+        assert_eq!(
+            suggestions(
+                &templates,
+                &FocusedApp {
+                    name: Some("gnome-terminal-server".to_string()),
+                    title: None,
+                }
+            ),
+            ["@shell", "@summarize", "@fillform"]
+        );
+    }
+}
@@ -1,9 +1,14 @@
 pub mod answer_analyser;
 pub mod assistance_window;
 pub mod diff_view;
+pub mod generation_options;
+pub mod llm_selection_window;
 pub mod main_window;
+pub mod profiles_window;
 pub mod screen_dimensions;
 pub mod shortcut_window;
 pub mod show_form_fields;
+pub mod statistics_window;
 pub mod template_editor;
+pub mod template_packs;
 pub mod user_interface;
@@ -1,5 +1,10 @@
-use crate::llm::LLMSelector;
 use crate::llm::LLMType;
+use crate::llm::backend::{ConversationMessage, FinishReason, LlmRequest, Role};
+use crate::llm::error::LlmError;
+use crate::llm::options::GenerationSettings;
+use crate::llm::prompt::build_messages;
+use crate::llm::{Generation, LLMSelector};
+use crate::templates::{self, FocusedApp, Variables};
 use crate::ui::answer_analyser::analyse_answer;
 use crate::ui::template_editor::TemplateMap;
 use plugovr_types::Screenshots;
@@ -13,12 +18,11 @@ use arboard::Clipboard;
 use egui::Layout;
 use egui::ScrollArea;
 use egui::scroll_area::ScrollBarVisibility;
-use itertools::Itertools;
 use screenshots::Screen;
-use std::collections::BTreeSet;
 
+use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 
 pub enum AiResponseAction {
     Replace,
@@ -42,12 +46,23 @@ pub struct AssistanceWindow {
     pub small_window: bool,
     prompt_templates: TemplateMap,
     spinner: Arc<Mutex<bool>>,
+    cancel_generation: Arc<Mutex<bool>>,
     ai_answer: Arc<Mutex<String>>,
-    max_tokens_reached: Arc<Mutex<bool>>,
+    finish_reason: Arc<Mutex<Option<FinishReason>>>,
+    /// Shown next to the answer with retry options, never written into it.
+    llm_error: Arc<Mutex<Option<LlmError>>>,
     llm_selector: Arc<Mutex<LLMSelector>>,
+    /// The request of this window, the API and the CLI have their own.
+    generation: Option<Generation>,
     pub ai_response_action: AiResponseAction,
     display_mode: DisplayMode,
     last_analyzed_answer: String,
+    follow_up_mode: bool,
+    conversation: Vec<ConversationMessage>,
+    /// Last request sent to the model, kept for follow-ups and to continue a
+    /// truncated answer.
+    last_request: Option<(LlmRequest, Option<LLMType>)>,
+    bypass_cache: bool,
 
     form_fields_overlay: FormFieldsOverlay,
 
@@ -62,6 +77,8 @@ pub struct AssistanceWindow {
     pub text: String,
     pub clipboard: Clipboard,
     pub active_window: Arc<Mutex<ActiveWindow>>,
+    /// Application of the active window, looked up once per selection.
+    focused_app: Option<(ActiveWindow, FocusedApp)>,
 }
 
 impl AssistanceWindow {
@@ -76,6 +93,8 @@ impl AssistanceWindow {
         screen_width: u16,
         screen_height: u16,
         llm_selector: Arc<Mutex<LLMSelector>>,
+        generating: Arc<Mutex<bool>>,
+        cancel_generation: Arc<Mutex<bool>>,
     ) -> Self {
         Self {
             show: false,
@@ -87,14 +106,21 @@ impl AssistanceWindow {
             shortcut_clicked: false,
             small_window: false,
             prompt_templates,
-            spinner: Arc::new(Mutex::new(false)),
+            spinner: generating,
+            cancel_generation,
 
             ai_answer,
-            max_tokens_reached: Arc::new(Mutex::new(false)),
+            finish_reason: Arc::new(Mutex::new(None)),
+            llm_error: Arc::new(Mutex::new(None)),
             llm_selector,
+            generation: None,
             ai_response_action: AiResponseAction::Replace,
             display_mode: DisplayMode::Normal,
             last_analyzed_answer: String::new(),
+            follow_up_mode: false,
+            conversation: Vec::new(),
+            last_request: None,
+            bypass_cache: false,
             form_fields_overlay: FormFieldsOverlay::new(mouse_position.clone()),
             screenshot_mode: false,
 
@@ -106,14 +132,52 @@ impl AssistanceWindow {
             text: String::new(),
             clipboard: Clipboard::new().unwrap(),
             active_window,
+            focused_app: None,
         }
     }
+
+    /// The application the text was selected in, its templates are listed
+    /// first.
+    pub fn focused_app(&mut self) -> FocusedApp {
+        let active_window = *self
+            .active_window
+            .lock()
+            .expect("Failed to lock active_window POISON");
+        match &self.focused_app {
+            Some((window, app)) if *window == active_window => app.clone(),
+            _ => {
+                let app = FocusedApp {
+                    name: window_handling::active_app_name(&active_window),
+                    title: window_handling::active_window_title(&active_window),
+                };
+                self.focused_app = Some((active_window, app.clone()));
+                app
+            }
+        }
+    }
+
+    /// The title of the window may have changed until the next selection.
+    pub fn forget_focused_app(&mut self) {
+        self.focused_app = None;
+    }
     pub fn show(&mut self, egui_context: &egui::Context, scale: f32) {
         self.show = *self
             .text_entry
             .lock()
             .expect("Failed to lock text_entry POISON");
 
+        // Escape while generating is caught by the key event thread.
+        if std::mem::take(
+            &mut *self
+                .cancel_generation
+                .lock()
+                .expect("Failed to lock cancel_generation POISON"),
+        ) {
+            if let Some(generation) = &self.generation {
+                generation.cancel();
+            }
+        }
+
         let text_entryfield_position = *self
             .text_entryfield_position
             .lock()
@@ -138,6 +202,16 @@ impl AssistanceWindow {
                 .lock()
                 .expect("Failed to lock ai_answer POISON")
                 .clear();
+            self.conversation.clear();
+            self.last_request = None;
+            *self
+                .finish_reason
+                .lock()
+                .expect("Failed to lock finish_reason POISON") = None;
+            *self
+                .llm_error
+                .lock()
+                .expect("Failed to lock llm_error POISON") = None;
         }
         let mut run_llm = false;
         window.show(egui_context, |ui| {
@@ -154,6 +228,8 @@ impl AssistanceWindow {
                                 if ui.button("Clear").clicked() {
                                     self.ai_context.lock().expect("Failed to lock ai_context POISON").clear();
                                     self.screenshots = Vec::new();
+                                    self.conversation.clear();
+                                    self.last_request = None;
                                 }
 
                             });
@@ -234,30 +310,43 @@ impl AssistanceWindow {
                     let mut shortcut_clicked = false;
                     ui.horizontal(|ui| {    // Add buttons for each template shortcut
                         ui.label("Shortcut:");
+                        let focused_app = self.focused_app();
                         let templates = self.prompt_templates.lock().expect("Failed to lock prompt_templates POISON");
 
-                        for (key, _) in templates.iter().filter(|(_, (_, _, is_shortcut))| *is_shortcut).sorted_by(|a, b| a.0.cmp(b.0)) {
-                            if ui.button(key).clicked() {
-                                self.text = key.clone();
+                        for (call, template) in templates::shortcut_calls(&templates, &focused_app) {
+                            let mut response = ui.button(template.button_text(&call));
+                            if !template.description.is_empty() {
+                                response = response.on_hover_text(&template.description);
+                            }
+                            if response.clicked() {
+                                self.text = call;
                                 shortcut_clicked=true;
                             }
                         }
                     });
 
+                    let follow_up_active = self.follow_up_mode && self.last_request.is_some();
+                    let template_error = templates::find_template(
+                        &self.text,
+                        &self.prompt_templates.lock().expect("Failed to lock prompt_templates POISON"),
+                    )
+                    .and_then(|found| found.err());
                     ui.horizontal(|ui| {
-                        let inputs = self
-                            .prompt_templates
-                            .lock()
-                            .expect("Failed to lock prompt_templates POISON")
-                            .keys()
-                            .cloned()
-                            .collect::<BTreeSet<_>>();
+                        let focused_app = self.focused_app();
+                        let inputs = templates::suggestions(
+                            &self.prompt_templates.lock().expect("Failed to lock prompt_templates POISON"),
+                            &focused_app,
+                        );
 
                         let resp = ui.add(
                             egui_autocomplete::AutoCompleteTextEdit::new(&mut self.text, inputs)
                                 .set_text_edit_properties(|text_edit: egui::TextEdit<'_>| {
                                     text_edit
-                                        .hint_text("enter your instructions to the AI")
+                                        .hint_text(if follow_up_active {
+                                            "refine the answer, e.g. shorter or more formal"
+                                        } else {
+                                            "enter your instructions to the AI"
+                                        })
                                         .frame(true)
                                 }),
                         );
@@ -266,65 +355,239 @@ impl AssistanceWindow {
                             resp.request_focus();
                             self.text_entry_changed = false;
                         }
-                        let resp_submit = ui.add(egui::Button::new("Submit"));
+                        let resp_submit = ui
+                            .add_enabled(template_error.is_none(), egui::Button::new("Submit"))
+                            .on_hover_text("Hold Shift to bypass the response cache");
                         if resp.lost_focus() {
                             resp_submit.request_focus();
                         }
                         if resp_submit.clicked() || shortcut_clicked {
+                            self.bypass_cache = ui.input(|i| i.modifiers.shift);
+                            record_last_turn(
+                                self.follow_up_mode,
+                                &mut self.conversation,
+                                &mut self.last_request,
+                                &self.ai_answer,
+                            );
                             self.ai_answer.lock().expect("Failed to lock ai_answer POISON").clear();
                             run_llm=true;
                         }
-
+                        if ui
+                            .checkbox(&mut self.follow_up_mode, "Follow-up")
+                            .on_hover_text("Keep the conversation to refine the answer step by step")
+                            .changed()
+                            && !self.follow_up_mode
+                        {
+                            self.conversation.clear();
+                            self.last_request = None;
+                        }
 
 
                         ui.add(egui::Label::new(" "))
                     });
+                    if let Some(template_error) = &template_error {
+                        ui.colored_label(egui::Color32::RED, template_error);
+                    }
                 } else if self.shortcut_clicked {
                     run_llm=true;
                 }
 
                 if run_llm && !*self.spinner.lock().expect("Failed to lock spinner POISON") {
                     self.shortcut_clicked = false;
-                    let mut ai_instruction = self.text.clone();
+                    let context = self.ai_context.lock().expect("Failed to lock ai_context POISON").clone();
+                    let variables = Variables {
+                        selection: &context,
+                        active_app: self.focused_app().name,
+                    };
+                    let mut ai_instruction = templates::fill_variables(&self.text, &variables);
                     let mut llm_from_template: Option<LLMType> = None;
-
-                    for (template, replacement) in self.prompt_templates.lock().expect("Failed to lock prompt_templates POISON").iter() {
-                        if ai_instruction.contains(template) {
-                            ai_instruction = ai_instruction.replace(template, replacement.0.as_str());
-                            llm_from_template = replacement.1.clone();
-                            break;
+                    let mut template_options = GenerationSettings::default();
+                    let mut template_name = None;
+
+                    let prompt_templates = self.prompt_templates.lock().expect("Failed to lock prompt_templates POISON");
+                    if let Some(Ok(invocation)) = templates::find_template(&self.text, &prompt_templates) {
+                        let prompt_template = &prompt_templates[&invocation.key];
+                        ai_instruction = templates::expand(&self.text, &invocation, &prompt_template.instruction, &variables);
+                        llm_from_template = prompt_template.llm_type.clone();
+                        template_options = prompt_template.options.clone();
+                        if let Some(response_action) = prompt_template.response_action {
+                            self.ai_response_action = response_action;
                         }
+                        template_name = Some(invocation.key);
                     }
-
-                    let prompt = format!(
-                        "context: {} instruction: {}",
-                        self.ai_context.lock().expect("Failed to lock ai_context POISON"),
-                        ai_instruction
-                    );
+                    drop(prompt_templates);
 
                     let spinner_clone = self.spinner.clone();
                     let ai_answer_clone = self.ai_answer.clone();
-                    let max_tokens_reached_clone = self.max_tokens_reached.clone();
-
-                    let _ = self.llm_selector.lock().expect("Failed to lock llm_selector POISON").process_input(
-                        prompt,
-                        self.ai_context.lock().expect("Failed to lock ai_context POISON").clone(),
-                        self.screenshots.clone(),
-                        ai_instruction,
+                    let finish_reason_clone = self.finish_reason.clone();
+
+                    let mut request = LlmRequest {
+                        context,
+                        instruction: ai_instruction,
+                        screenshots: self.screenshots.clone(),
+                        history: self.conversation.clone(),
+                        options: template_options,
+                        answer_prefix: String::new(),
+                        bypass_cache: std::mem::take(&mut self.bypass_cache),
+                        template: template_name,
+                        knowledge: vec![],
+                    };
+                    self.last_request = Some((request.clone(), llm_from_template.clone()));
+                    self.generation = self.llm_selector.lock().expect("Failed to lock llm_selector POISON").process_input(
+                        request,
                         ai_answer_clone,
-                        max_tokens_reached_clone,
+                        finish_reason_clone,
+                        self.llm_error.clone(),
                         spinner_clone,
                         llm_from_template,
-                    );
+                    ).ok();
+                    // Kept with the request, so follow-ups see the same notes.
+                    if let (Some((request, _)), Some(generation)) =
+                        (&mut self.last_request, &self.generation)
+                    {
+                        request.knowledge = generation.knowledge().to_vec();
+                    }
                 }
                 ui.vertical(|ui| {
-                    if *self.max_tokens_reached.lock().expect("Failed to lock max_tokens_reached POISON") {
-                        let colored_label = egui::RichText::new(
-                            "Warning: Max tokens reached. Your answer may be incomplete.",
-                        )
-                        .color(egui::Color32::from_rgb(255, 0, 0)); // RGB for red color
+                    let mut continue_clicked = false;
+                    match *self.finish_reason.lock().expect("Failed to lock finish_reason POISON") {
+                        Some(FinishReason::MaxTokens) => {
+                            let colored_label = egui::RichText::new(
+                                "Warning: Max tokens reached. Your answer may be incomplete.",
+                            )
+                            .color(egui::Color32::from_rgb(255, 0, 0)); // RGB for red color
+
+                            ui.horizontal(|ui| {
+                                ui.label(colored_label);
+                                if self.last_request.is_some()
+                                    && !*self.spinner.lock().expect("Failed to lock spinner POISON")
+                                    && ui
+                                        .button("Continue")
+                                        .on_hover_text("Ask the model to resume where it stopped")
+                                        .clicked()
+                                {
+                                    continue_clicked = true;
+                                }
+                            });
+                        }
+                        Some(FinishReason::Cancelled) => {
+                            let colored_label = egui::RichText::new(
+                                "Generation cancelled. Your answer is incomplete.",
+                            )
+                            .color(egui::Color32::from_rgb(255, 0, 0));
 
-                        ui.label(colored_label);
+                            ui.label(colored_label);
+                        }
+                        _ => {}
+                    }
+                    let answered_by = self
+                        .generation
+                        .as_ref()
+                        .and_then(|generation| generation.answered_by());
+                    match answered_by {
+                        Some((backend_name, true)) => {
+                            ui.colored_label(
+                                egui::Color32::from_rgb(255, 165, 0),
+                                format!("Answered by {} (fallback)", backend_name),
+                            );
+                        }
+                        Some((backend_name, false)) => {
+                            ui.weak(format!("Answered by {}", backend_name));
+                        }
+                        None => {}
+                    }
+                    let knowledge_sources = self
+                        .generation
+                        .as_ref()
+                        .map(|generation| generation.knowledge_sources())
+                        .unwrap_or_default();
+                    if !knowledge_sources.is_empty() {
+                        ui.weak(format!("Sources: {}", knowledge_sources.join(", ")));
+                    }
+                    let mut regenerate_clicked = false;
+                    if self
+                        .generation
+                        .as_ref()
+                        .is_some_and(|generation| generation.from_cache())
+                        && !*self.spinner.lock().expect("Failed to lock spinner POISON")
+                    {
+                        ui.horizontal(|ui| {
+                            ui.label("Answer from cache");
+                            regenerate_clicked = ui
+                                .button("Regenerate")
+                                .on_hover_text("Ask the model again instead of using the cache")
+                                .clicked();
+                        });
+                    }
+                    if continue_clicked || regenerate_clicked {
+                        if let Some((request, llm_from_template)) = &self.last_request {
+                            let request = if continue_clicked {
+                                let partial_answer = self
+                                    .ai_answer
+                                    .lock()
+                                    .expect("Failed to lock ai_answer POISON")
+                                    .clone();
+                                continuation_request(request, partial_answer)
+                            } else {
+                                LlmRequest {
+                                    bypass_cache: true,
+                                    ..request.clone()
+                                }
+                            };
+                            self.generation = self
+                                .llm_selector
+                                .lock()
+                                .expect("Failed to lock llm_selector POISON")
+                                .process_input(
+                                    request,
+                                    self.ai_answer.clone(),
+                                    self.finish_reason.clone(),
+                                    self.llm_error.clone(),
+                                    self.spinner.clone(),
+                                    llm_from_template.clone(),
+                                )
+                                .ok();
+                        }
+                    }
+                    let llm_error = self
+                        .llm_error
+                        .lock()
+                        .expect("Failed to lock llm_error POISON")
+                        .clone();
+                    if let Some(llm_error) = &llm_error {
+                        let available_llm_types = self
+                            .llm_selector
+                            .lock()
+                            .expect("Failed to lock llm_selector POISON")
+                            .available_llm_types();
+                        if let Some(llm_type) = error_panel(
+                            ui,
+                            llm_error,
+                            &available_llm_types,
+                            self.last_request.is_some()
+                                && !*self.spinner.lock().expect("Failed to lock spinner POISON"),
+                        ) {
+                            if let Some((request, llm_from_template)) = &self.last_request {
+                                let llm_type = llm_type.or_else(|| llm_from_template.clone());
+                                self.ai_answer.lock().expect("Failed to lock ai_answer POISON").clear();
+                                self.generation = self
+                                    .llm_selector
+                                    .lock()
+                                    .expect("Failed to lock llm_selector POISON")
+                                    .process_input(
+                                        LlmRequest {
+                                            bypass_cache: true,
+                                            ..request.clone()
+                                        },
+                                        self.ai_answer.clone(),
+                                        self.finish_reason.clone(),
+                                        self.llm_error.clone(),
+                                        self.spinner.clone(),
+                                        llm_type,
+                                    )
+                                    .ok();
+                            }
+                        }
                     }
                     ui.horizontal(|ui| {
                         ui.label("Action:");
@@ -354,7 +617,11 @@ impl AssistanceWindow {
                     ui.horizontal(|ui| {
                         ui.label("AI Answer:");
 
-                        if ui.add(egui::Button::new("Accept")).clicked() {
+                        if ui
+                            .add_enabled(llm_error.is_none(), egui::Button::new("Accept"))
+                            .on_disabled_hover_text("The request failed, there is no answer to insert")
+                            .clicked()
+                        {
                             *self.text_entry.lock().expect("Failed to lock text_entry POISON") = false;
 
                             let active_window = self.active_window.clone();
@@ -385,6 +652,26 @@ impl AssistanceWindow {
                         ui.add(egui::Label::new(" "))
                     });
 
+                    if !self.conversation.is_empty() {
+                        egui::CollapsingHeader::new(format!(
+                            "Conversation ({} turns)",
+                            self.conversation
+                                .iter()
+                                .filter(|message| message.role == Role::Assistant)
+                                .count()
+                        ))
+                        .show(ui, |ui| {
+                            for message in &self.conversation {
+                                let text = match message.role {
+                                    Role::System => continue,
+                                    Role::User => egui::RichText::new(format!("You: {}", message.content)).strong(),
+                                    Role::Assistant => egui::RichText::new(message.content.as_str()),
+                                };
+                                ui.label(text);
+                            }
+                        });
+                    }
+
                     ui.separator();
                     ui.horizontal(|ui| {
                         ui.label("Display mode:");
@@ -407,7 +694,26 @@ impl AssistanceWindow {
                                 }
                             }
                         if *self.spinner.lock().expect("Failed to lock spinner POISON") {
-                            ui.add(egui::Spinner::new());
+                            ui.horizontal(|ui| {
+                                ui.add(egui::Spinner::new());
+                                if ui.button("Stop").on_hover_text("Stop the generation (Esc)").clicked() {
+                                    if let Some(generation) = &self.generation {
+                                        generation.cancel();
+                                    }
+                                }
+                            });
+                            let chunk_progress = self.generation.as_ref().and_then(|generation| generation.chunk_progress());
+                            if let Some(progress) = chunk_progress {
+                                let text = if progress.reducing {
+                                    format!("Combining the answers of {} parts", progress.total)
+                                } else {
+                                    format!("Part {} of {}", progress.done + 1, progress.total)
+                                };
+                                ui.add(
+                                    egui::ProgressBar::new(progress.done as f32 / progress.total.max(1) as f32)
+                                        .text(text),
+                                );
+                            }
                         }
                             // Only analyze if the answer has changed
                             if ai_answer.as_str() != self.last_analyzed_answer.as_str() {
@@ -556,3 +862,84 @@ impl AssistanceWindow {
         *self.text_entry.lock().unwrap() = true;
     }
 }
+
+/// Asks the model to resume a truncated answer. The partial answer is sent back
+/// as the assistant turn and the new tokens are appended to it.
+fn continuation_request(request: &LlmRequest, partial_answer: String) -> LlmRequest {
+    let instruction = "Continue your answer exactly where it stopped. Do not repeat any text.";
+    let mut history = build_messages(request);
+    history.push(ConversationMessage::new(
+        Role::Assistant,
+        partial_answer.clone(),
+    ));
+    LlmRequest {
+        context: request.context.clone(),
+        instruction: instruction.to_string(),
+        screenshots: request.screenshots.clone(),
+        history,
+        options: request.options.clone(),
+        answer_prefix: partial_answer,
+        bypass_cache: request.bypass_cache,
+        template: request.template.clone(),
+        knowledge: request.knowledge.clone(),
+    }
+}
+
+/// Moves the last request and its answer into the conversation history.
+fn record_last_turn(
+    follow_up_mode: bool,
+    conversation: &mut Vec<ConversationMessage>,
+    last_request: &mut Option<(LlmRequest, Option<LLMType>)>,
+    ai_answer: &Arc<Mutex<String>>,
+) {
+    if let Some((last_request, _)) = last_request.take() {
+        let last_answer = ai_answer
+            .lock()
+            .expect("Failed to lock ai_answer POISON")
+            .clone();
+        if follow_up_mode && !last_answer.is_empty() {
+            // The messages of the last request already contain the earlier turns.
+            *conversation = build_messages(&last_request);
+            conversation.push(ConversationMessage::new(Role::Assistant, last_answer));
+        }
+    }
+}
+
+/// Shows a failed request with what to do about it. Returns `Some(None)` to
+/// retry with the same model and `Some(Some(model))` to retry with another one.
+fn error_panel(
+    ui: &mut egui::Ui,
+    llm_error: &LlmError,
+    available_llm_types: &[LLMType],
+    can_retry: bool,
+) -> Option<Option<LLMType>> {
+    let mut retry = None;
+    egui::Frame::group(ui.style())
+        .stroke(egui::Stroke::new(1.0, egui::Color32::from_rgb(255, 0, 0)))
+        .show(ui, |ui| {
+            ui.colored_label(
+                egui::Color32::from_rgb(255, 0, 0),
+                egui::RichText::new(llm_error.title()).strong(),
+            );
+            ui.label(llm_error.hint());
+            ui.collapsing("Details", |ui| {
+                ui.weak(llm_error.details());
+            });
+            ui.add_enabled_ui(can_retry, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Retry").clicked() {
+                        retry = Some(None);
+                    }
+                    ui.menu_button("Switch model", |ui| {
+                        for llm_type in available_llm_types {
+                            if ui.button(llm_type.description()).clicked() {
+                                retry = Some(Some(llm_type.clone()));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                });
+            });
+        });
+    retry
+}
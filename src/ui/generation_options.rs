@@ -0,0 +1,142 @@
+use crate::llm::chunking::ChunkMode;
+use crate::llm::options::{GenerationSettings, MAX_SEED};
+
+/// Editor for optional generation settings, unchecked values are inherited.
+/// Returns true if anything changed.
+pub fn generation_settings_ui(
+    ui: &mut egui::Ui,
+    id: &str,
+    settings: &mut GenerationSettings,
+) -> bool {
+    let mut changed = false;
+    egui::Grid::new(id).num_columns(2).show(ui, |ui| {
+        changed |= optional_value(
+            ui,
+            "Temperature",
+            &mut settings.temperature,
+            0.2,
+            |ui, v| ui.add(egui::Slider::new(v, 0.0..=2.0)),
+        );
+        changed |= optional_value(ui, "Top-p", &mut settings.top_p, 0.5, |ui, v| {
+            ui.add(egui::Slider::new(v, 0.0..=1.0))
+        });
+        changed |= optional_value(ui, "Max tokens", &mut settings.max_tokens, 500, |ui, v| {
+            ui.add(egui::DragValue::new(v).range(1..=32768))
+        });
+        changed |= optional_value(ui, "Seed", &mut settings.seed, 42, |ui, v| {
+            ui.add(egui::DragValue::new(v).range(0..=MAX_SEED))
+        });
+        changed |= optional_value(
+            ui,
+            "Knowledge snippets",
+            &mut settings.knowledge_snippets,
+            3,
+            |ui, v| {
+                ui.add(egui::DragValue::new(v).range(1..=10))
+                    .on_hover_text("Relevant notes of the knowledge folder added to the prompt")
+            },
+        );
+        changed |= optional_value(
+            ui,
+            "Split long text",
+            &mut settings.chunking,
+            ChunkMode::Concatenate,
+            |ui, v| {
+                ui.horizontal(|ui| {
+                    ui.radio_value(v, ChunkMode::Concatenate, "Join the answers")
+                        | ui.radio_value(v, ChunkMode::Reduce, "Combine the answers")
+                })
+                .inner
+                .on_hover_text(
+                    "Text too long for the model is split into parts. Rewrites join the \
+                     answers of the parts, summaries run the template on them again.",
+                )
+            },
+        );
+
+        ui.label("Stop sequences");
+        let mut stop = settings.stop.join(",");
+        if ui
+            .add(egui::TextEdit::singleline(&mut stop).hint_text("comma separated"))
+            .changed()
+        {
+            settings.stop = stop
+                .split(',')
+                .map(|s| s.to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            changed = true;
+        }
+        ui.end_row();
+    });
+    changed |= json_schema_ui(ui, id, &mut settings.json_schema);
+    changed
+}
+
+/// The schema text is kept in egui memory so it can be edited while it is not
+/// valid JSON yet. Only valid schemas are written to the settings.
+fn json_schema_ui(
+    ui: &mut egui::Ui,
+    id: &str,
+    json_schema: &mut Option<serde_json::Value>,
+) -> bool {
+    let text_id = egui::Id::new(id).with("json_schema");
+    let mut text = ui
+        .data_mut(|data| data.get_temp::<String>(text_id))
+        .unwrap_or_else(|| {
+            json_schema
+                .as_ref()
+                .and_then(|schema| serde_json::to_string_pretty(schema).ok())
+                .unwrap_or_default()
+        });
+    let mut changed = false;
+    ui.collapsing("JSON schema", |ui| {
+        ui.label("The answer is constrained to and validated against this schema. Leave empty to inherit.");
+        if ui
+            .add(
+                egui::TextEdit::multiline(&mut text)
+                    .code_editor()
+                    .desired_rows(6)
+                    .hint_text("{\"type\": \"object\", ...}"),
+            )
+            .changed()
+        {
+            if text.trim().is_empty() {
+                *json_schema = None;
+                changed = true;
+            } else if let Ok(schema) = serde_json::from_str(&text) {
+                *json_schema = Some(schema);
+                changed = true;
+            }
+        }
+        if !text.trim().is_empty() {
+            if let Err(e) = serde_json::from_str::<serde_json::Value>(&text) {
+                ui.colored_label(egui::Color32::RED, format!("Invalid JSON: {}", e));
+            }
+        }
+    });
+    ui.data_mut(|data| data.insert_temp(text_id, text));
+    changed
+}
+
+fn optional_value<T: Copy>(
+    ui: &mut egui::Ui,
+    label: &str,
+    value: &mut Option<T>,
+    default: T,
+    add_editor: impl FnOnce(&mut egui::Ui, &mut T) -> egui::Response,
+) -> bool {
+    let mut changed = false;
+    let mut enabled = value.is_some();
+    if ui.checkbox(&mut enabled, label).changed() {
+        *value = if enabled { Some(default) } else { None };
+        changed = true;
+    }
+    if let Some(v) = value.as_mut() {
+        changed |= add_editor(ui, v).changed();
+    } else {
+        ui.label("inherited");
+    }
+    ui.end_row();
+    changed
+}
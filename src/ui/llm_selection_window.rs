@@ -0,0 +1,617 @@
+use crate::llm::downloads::DownloadState;
+use crate::llm::{
+    CloudModel, CustomModel, DEFAULT_OLLAMA_URL, LLMSelector, LLMType, LocalModel, Quantization,
+};
+use crate::settings;
+use crate::ui::generation_options::generation_settings_ui;
+use strum::IntoEnumIterator;
+
+/// Picks the model and edits the model settings, the settings themselves
+/// live in the `LLMSelector`.
+#[derive(Default)]
+pub struct LlmSelectionWindow {
+    pub show: bool,
+    import_dir: String,
+    import_status: Option<String>,
+    local_delete_confirmation: Option<LocalModel>,
+    ollama_url_edit: String,
+    ollama_pull_model: String,
+    ollama_delete_confirmation: Option<String>,
+    new_custom_model_name: String,
+    new_custom_model_path: String,
+    new_custom_tokenizer_path: String,
+    new_custom_chat_template: String,
+    custom_model_error: Option<String>,
+    fallback_candidate: Option<LLMType>,
+}
+
+impl LlmSelectionWindow {
+    pub fn new(llm_selector: &LLMSelector) -> Self {
+        Self {
+            ollama_url_edit: llm_selector.ollama_url(),
+            ..Self::default()
+        }
+    }
+
+    pub fn show_llm_selection_window(
+        &mut self,
+        egui_context: &egui::Context,
+        llm_selector: &mut LLMSelector,
+    ) {
+        let mut show_window = self.show;
+        egui::Window::new("LLM Selection")
+            .open(&mut show_window)
+            .collapsible(false)
+            .max_width(400.0)
+            .show(egui_context, |ui| {
+                profile_ui(ui, llm_selector);
+                cloud_models_ui(ui, llm_selector);
+                self.download_manager_ui(ui, llm_selector);
+                self.custom_gguf_ui(ui, llm_selector);
+                self.ollama_ui(ui, llm_selector);
+                openai_compatible_ui(ui, llm_selector);
+
+                if let Some(status) = &self.import_status {
+                    ui.label(status);
+                }
+                if let Some(error) = llm_selector.download_error() {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+
+                ui.collapsing("Fallback models", |ui| self.fallback_ui(ui, llm_selector));
+                ui.collapsing("Response cache", |ui| cache_ui(ui, llm_selector));
+                ui.collapsing("Knowledge", |ui| knowledge_ui(ui, llm_selector));
+                ui.collapsing("Generation options", |ui| {
+                    generation_options_ui(ui, llm_selector)
+                });
+            });
+        self.show = show_window;
+    }
+
+    /// The catalog models with their downloads, verification and import.
+    fn download_manager_ui(&mut self, ui: &mut egui::Ui, llm_selector: &mut LLMSelector) {
+        ui.heading("Local Models");
+        ui.horizontal(|ui| {
+            ui.label("Quantization");
+            egui::ComboBox::from_id_salt("local_quantization")
+                .selected_text(llm_selector.local_quantization.description())
+                .show_ui(ui, |ui| {
+                    for quantization in Quantization::iter() {
+                        ui.selectable_value(
+                            &mut llm_selector.local_quantization,
+                            quantization,
+                            quantization.description(),
+                        );
+                    }
+                });
+        });
+        let local_models = LocalModel::iter()
+            .map(|local_model| local_model.with_quantization(llm_selector.local_quantization))
+            .collect::<Vec<_>>();
+        let downloads = llm_selector.downloads().clone();
+        let disk_usage: u64 = local_models
+            .iter()
+            .map(|local_model| downloads.disk_usage(&local_model.files().to_vec()))
+            .sum();
+        ui.weak(format!(
+            "{:.1} GB in {}",
+            disk_usage as f64 / 1e9,
+            downloads.dir().display()
+        ));
+        for local_model in local_models {
+            let key = local_model.to_string();
+            let files = local_model.files().to_vec();
+            let downloaded = downloads.is_downloaded(&files);
+
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(
+                        downloaded,
+                        egui::RadioButton::new(
+                            llm_selector.get_llm_type() == LLMType::Local(local_model),
+                            LLMType::Local(local_model).description(),
+                        ),
+                    )
+                    .clicked()
+                {
+                    llm_selector.select_llm_type(LLMType::Local(local_model));
+                }
+                if downloaded {
+                    ui.weak(format!(
+                        "{:.1} GB",
+                        downloads.disk_usage(&files) as f64 / 1e9
+                    ));
+                    if downloads.is_unverified(&files) {
+                        ui.colored_label(ui.visuals().warn_fg_color, "unverified")
+                            .on_hover_text(
+                                "No sha256 was published for it or it was imported \
+                                 without one, only the file format was checked.",
+                            );
+                    }
+                    if downloads.state(&key) == Some(DownloadState::Verifying) {
+                        ui.spinner();
+                        ui.weak("Verifying");
+                    } else if ui.small_button("Verify").clicked() {
+                        downloads.start_verify(key.clone(), files.clone());
+                    }
+                    if self.local_delete_confirmation == Some(local_model) {
+                        if ui.small_button("Confirm delete").clicked() {
+                            self.local_delete_confirmation = None;
+                            llm_selector.delete_local_model(local_model);
+                        }
+                        if ui.small_button("Cancel").clicked() {
+                            self.local_delete_confirmation = None;
+                        }
+                    } else if ui.small_button("Delete").clicked() {
+                        self.local_delete_confirmation = Some(local_model);
+                    }
+                } else if downloads.is_downloading(&key) {
+                    let state = downloads.state(&key);
+                    let text = match &state {
+                        Some(DownloadState::Verifying) => "Verifying".to_string(),
+                        Some(DownloadState::Downloading {
+                            file, downloaded, ..
+                        }) => format!("{} {:.2} GB", file, *downloaded as f64 / 1e9),
+                        _ => "Starting".to_string(),
+                    };
+                    ui.add(
+                        egui::ProgressBar::new(state.map(|state| state.progress()).unwrap_or(0.0))
+                            .desired_width(200.0)
+                            .text(text),
+                    );
+                    if ui.small_button("Pause").clicked() {
+                        downloads.pause(&key);
+                    }
+                } else {
+                    let label = if downloads.has_partial(&files) {
+                        "Resume"
+                    } else {
+                        "Download"
+                    };
+                    if ui.button(label).clicked() {
+                        downloads.start(key.clone(), files.clone());
+                    }
+                    if downloads.has_partial(&files) && ui.small_button("Discard").clicked() {
+                        downloads
+                            .delete(&key, &files)
+                            .unwrap_or_else(|e| eprintln!("Failed to delete download: {}", e));
+                    }
+                }
+            });
+            match downloads.state(&key) {
+                Some(DownloadState::Failed(error)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                Some(DownloadState::Verified { checksum: true }) => {
+                    ui.label(format!("{} is intact", local_model.description()));
+                }
+                Some(DownloadState::Verified { checksum: false }) => {
+                    ui.label(format!(
+                        "{} is a valid GGUF file, there is no checksum to compare it with",
+                        local_model.description()
+                    ));
+                }
+                _ => {}
+            }
+        }
+        ui.collapsing("Import models from a directory", |ui| {
+            ui.label(
+                "For machines without internet access: copy the GGUF and tokenizer.json \
+                 files, e.g. from the models directory of another PlugOvr installation.",
+            );
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.import_dir).hint_text("/media/usb/models"),
+                );
+                if ui.button("Import").clicked() {
+                    let imported =
+                        llm_selector.import_models(std::path::Path::new(self.import_dir.trim()));
+                    self.import_status = Some(format!("Imported {} models", imported));
+                }
+            });
+        });
+    }
+
+    fn custom_gguf_ui(&mut self, ui: &mut egui::Ui, llm_selector: &mut LLMSelector) {
+        ui.heading("Custom GGUF Models");
+        let mut remove_custom_model = None;
+        for (index, custom_model) in llm_selector.custom_models().into_iter().enumerate() {
+            let custom_llm_type = LLMType::CustomGguf(custom_model.clone());
+            ui.horizontal(|ui| {
+                if ui
+                    .radio(
+                        llm_selector.get_llm_type() == custom_llm_type,
+                        custom_model.name.as_str(),
+                    )
+                    .on_hover_text(custom_model.model_path.display().to_string())
+                    .clicked()
+                {
+                    llm_selector.select_llm_type(custom_llm_type);
+                }
+                if ui.button("Remove").clicked() {
+                    remove_custom_model = Some(index);
+                }
+            });
+        }
+        if let Some(index) = remove_custom_model {
+            llm_selector.remove_custom_model(index);
+        }
+        ui.collapsing("Add GGUF model", |ui| {
+            egui::Grid::new("custom_model_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut self.new_custom_model_name);
+                    ui.end_row();
+                    ui.label("Model file");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.new_custom_model_path)
+                            .hint_text("/path/to/model.gguf"),
+                    );
+                    ui.end_row();
+                    ui.label("Tokenizer");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.new_custom_tokenizer_path)
+                            .hint_text("/path/to/tokenizer.json"),
+                    );
+                    ui.end_row();
+                    ui.label("Chat template");
+                    ui.add(
+                        egui::TextEdit::multiline(&mut self.new_custom_chat_template)
+                            .desired_rows(2)
+                            .hint_text("optional, taken from the GGUF file if empty"),
+                    );
+                    ui.end_row();
+                });
+            if ui.button("Add").clicked() {
+                let custom_model = CustomModel {
+                    name: self.new_custom_model_name.trim().to_string(),
+                    model_path: self.new_custom_model_path.trim().into(),
+                    tokenizer_path: self.new_custom_tokenizer_path.trim().into(),
+                    chat_template: Some(self.new_custom_chat_template.clone())
+                        .filter(|chat_template| !chat_template.trim().is_empty()),
+                };
+                match llm_selector.add_custom_model(custom_model) {
+                    Ok(()) => {
+                        self.new_custom_model_name.clear();
+                        self.new_custom_model_path.clear();
+                        self.new_custom_tokenizer_path.clear();
+                        self.new_custom_chat_template.clear();
+                        self.custom_model_error = None;
+                    }
+                    Err(e) => self.custom_model_error = Some(e),
+                }
+            }
+            if let Some(error) = &self.custom_model_error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+    }
+
+    /// The models of the Ollama server, pulling and deleting them.
+    fn ollama_ui(&mut self, ui: &mut egui::Ui, llm_selector: &mut LLMSelector) {
+        ui.heading("Ollama Models");
+        ui.horizontal(|ui| {
+            ui.label("Endpoint");
+            let url_edit = ui.add(
+                egui::TextEdit::singleline(&mut self.ollama_url_edit).hint_text(DEFAULT_OLLAMA_URL),
+            );
+            let url_changed =
+                url_edit.lost_focus() && llm_selector.ollama_url() != self.ollama_url_edit;
+            if url_changed || ui.button("Refresh").clicked() {
+                llm_selector.set_ollama_url(&self.ollama_url_edit);
+            }
+        });
+        let ollama_models = llm_selector.ollama_models.lock().unwrap().clone();
+        match &ollama_models {
+            None => {
+                ui.label(format!(
+                    "Ollama is not running at {}",
+                    llm_selector.ollama_url()
+                ));
+            }
+            Some(ollama_models) if ollama_models.is_empty() => {
+                ui.label(
+                    "No ollama models found, pull some models with e.g. ollama pull llama3.2:1b",
+                );
+            }
+            Some(ollama_models) => {
+                for ollama_model in ollama_models {
+                    let ollama_llm_type = LLMType::Ollama(ollama_model.name.clone());
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(
+                                llm_selector.is_allowed(&ollama_llm_type),
+                                egui::RadioButton::new(
+                                    llm_selector.get_llm_type() == ollama_llm_type,
+                                    ollama_llm_type.description(),
+                                ),
+                            )
+                            .clicked()
+                        {
+                            llm_selector.select_llm_type(ollama_llm_type.clone());
+                        }
+                        ui.weak(ollama_model.details());
+                        if ollama_model.vision {
+                            ui.label("👁").on_hover_text("Supports screenshots");
+                        }
+                        if self.ollama_delete_confirmation.as_ref() == Some(&ollama_model.name) {
+                            if ui.button("Confirm delete").clicked() {
+                                self.ollama_delete_confirmation = None;
+                                llm_selector.delete_ollama_model(ollama_model.name.clone());
+                            }
+                            if ui.button("Cancel").clicked() {
+                                self.ollama_delete_confirmation = None;
+                            }
+                        } else if ui.button("Delete").clicked() {
+                            self.ollama_delete_confirmation = Some(ollama_model.name.clone());
+                        }
+                    });
+                }
+            }
+        }
+        if ollama_models.is_some() {
+            let pull_progress = llm_selector.ollama_pull_progress();
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.ollama_pull_model)
+                        .hint_text("e.g. llama3.2:1b"),
+                );
+                if ui
+                    .add_enabled(
+                        pull_progress.is_none() && !self.ollama_pull_model.trim().is_empty(),
+                        egui::Button::new("Pull"),
+                    )
+                    .clicked()
+                {
+                    llm_selector.pull_ollama_model(self.ollama_pull_model.trim().to_string());
+                }
+            });
+            if let Some((status, fraction)) = pull_progress {
+                ui.add(egui::ProgressBar::new(fraction).text(format!(
+                    "{} {:.0}%",
+                    status,
+                    fraction * 100.0
+                )));
+            }
+        }
+        if let Some(error) = llm_selector.ollama_error() {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+
+    fn fallback_ui(&mut self, ui: &mut egui::Ui, llm_selector: &mut LLMSelector) {
+        ui.label(
+            "Tried in this order if the selected model cannot be reached, \
+             its model is missing or it doesn't respond in time.",
+        );
+        let mut changed = false;
+        let mut move_up = None;
+        let mut remove = None;
+        for (index, fallback_llm) in llm_selector.fallback_llms.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}. {}", index + 1, fallback_llm.description()));
+                if index > 0 && ui.small_button("⬆").clicked() {
+                    move_up = Some(index);
+                }
+                if ui.small_button("Remove").clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+        if let Some(index) = move_up {
+            llm_selector.fallback_llms.swap(index - 1, index);
+            changed = true;
+        }
+        if let Some(index) = remove {
+            llm_selector.fallback_llms.remove(index);
+            changed = true;
+        }
+        let available_llm_types = llm_selector.available_llm_types();
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("fallback_candidate")
+                .selected_text(
+                    self.fallback_candidate
+                        .as_ref()
+                        .map(|llm_type| llm_type.description())
+                        .unwrap_or_else(|| "select model".to_string()),
+                )
+                .show_ui(ui, |ui| {
+                    for llm_type in &available_llm_types {
+                        if !llm_selector.fallback_llms.contains(llm_type) {
+                            ui.selectable_value(
+                                &mut self.fallback_candidate,
+                                Some(llm_type.clone()),
+                                llm_type.description(),
+                            );
+                        }
+                    }
+                });
+            if ui.button("Add").clicked() {
+                if let Some(llm_type) = self.fallback_candidate.take() {
+                    llm_selector.fallback_llms.push(llm_type);
+                    changed = true;
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Wait for a connection (s)");
+            changed |= ui
+                .add(egui::DragValue::new(&mut llm_selector.fallback_timeout_secs).range(1..=600))
+                .changed();
+        });
+        if changed {
+            llm_selector.save_fallback_llms();
+        }
+    }
+}
+
+fn profile_ui(ui: &mut egui::Ui, llm_selector: &LLMSelector) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("Profile")
+            .selected_text(llm_selector.profile())
+            .show_ui(ui, |ui| {
+                for name in llm_selector.profile_names() {
+                    let active = name == llm_selector.profile();
+                    if ui.selectable_label(active, name).clicked() && !active {
+                        // Applied by the user interface, like a switch in the tray.
+                        if let Err(e) = settings::switch_profile(name) {
+                            eprintln!("Failed to switch profile: {}", e);
+                        }
+                    }
+                }
+            });
+        if llm_selector.local_only() {
+            ui.colored_label(egui::Color32::from_rgb(255, 165, 0), "Local models only");
+        }
+    });
+}
+
+fn cloud_models_ui(ui: &mut egui::Ui, llm_selector: &mut LLMSelector) {
+    ui.heading("Cloud Models");
+    ui.add_enabled_ui(!llm_selector.local_only(), |ui| {
+        for cloud_model in CloudModel::iter() {
+            let cloud_llm_type = LLMType::Cloud(cloud_model);
+            if ui
+                .radio(
+                    llm_selector.get_llm_type() == cloud_llm_type,
+                    cloud_llm_type.description(),
+                )
+                .clicked()
+            {
+                llm_selector.select_llm_type(cloud_llm_type);
+            }
+        }
+    });
+}
+
+fn openai_compatible_ui(ui: &mut egui::Ui, llm_selector: &mut LLMSelector) {
+    ui.heading("OpenAI compatible server");
+    ui.label("e.g. vLLM, llama.cpp server, LM Studio or LocalAI");
+    egui::Grid::new("openai_compatible_grid")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Base URL");
+            ui.text_edit_singleline(&mut llm_selector.openai_base_url);
+            ui.end_row();
+            ui.label("Model");
+            ui.text_edit_singleline(&mut llm_selector.openai_model);
+            ui.end_row();
+            ui.label("API key");
+            if ui
+                .add(egui::TextEdit::singleline(&mut llm_selector.openai_api_key).password(true))
+                .lost_focus()
+            {
+                llm_selector.save_openai_api_key();
+            }
+            ui.end_row();
+        });
+    let openai_llm_type = llm_selector.openai_compatible_llm_type();
+    if ui
+        .add_enabled(
+            !llm_selector.openai_base_url.is_empty()
+                && !llm_selector.openai_model.is_empty()
+                && llm_selector.is_allowed(&openai_llm_type),
+            egui::RadioButton::new(
+                llm_selector.get_llm_type() == openai_llm_type,
+                openai_llm_type.description(),
+            ),
+        )
+        .clicked()
+    {
+        llm_selector.select_llm_type(openai_llm_type);
+    }
+}
+
+fn cache_ui(ui: &mut egui::Ui, llm_selector: &mut LLMSelector) {
+    let cache_settings = &mut llm_selector.cache_settings;
+    let mut changed = ui
+        .checkbox(
+            &mut cache_settings.enabled,
+            "Reuse answers for the same model, options and text",
+        )
+        .changed();
+    ui.horizontal(|ui| {
+        ui.label("Max size (MB)");
+        changed |= ui
+            .add(egui::DragValue::new(&mut cache_settings.max_size_mb).range(1..=10_000))
+            .changed();
+        ui.label("Keep for (hours)");
+        changed |= ui
+            .add(egui::DragValue::new(&mut cache_settings.ttl_hours).range(1..=24 * 365))
+            .changed();
+    });
+    ui.horizontal(|ui| {
+        ui.label(format!(
+            "Cache size: {:.1} MB",
+            llm_selector.cache_size() as f64 / (1024.0 * 1024.0)
+        ));
+        if ui.button("Clear cache").clicked() {
+            llm_selector.clear_cache();
+        }
+    });
+    if changed {
+        llm_selector.save_cache_settings();
+    }
+}
+
+fn knowledge_ui(ui: &mut egui::Ui, llm_selector: &LLMSelector) {
+    ui.label(
+        "Notes and snippets (.txt, .md) in this folder are searched offline. \
+         Templates with knowledge snippets get the most relevant ones in their prompt.",
+    );
+    let (dir, file_count, snippet_count) = {
+        let knowledge = llm_selector.knowledge();
+        (
+            knowledge.dir().to_path_buf(),
+            knowledge.file_count(),
+            knowledge.snippet_count(),
+        )
+    };
+    ui.horizontal(|ui| {
+        ui.monospace(dir.display().to_string());
+        if ui.button("Create folder").clicked() {
+            std::fs::create_dir_all(&dir)
+                .unwrap_or_else(|e| eprintln!("Failed to create knowledge folder: {}", e));
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label(format!("{} notes, {} snippets", file_count, snippet_count));
+        if ui.button("Reload").clicked() {
+            llm_selector.reload_knowledge();
+        }
+    });
+}
+
+/// The defaults and the overrides of the selected model.
+fn generation_options_ui(ui: &mut egui::Ui, llm_selector: &mut LLMSelector) {
+    let mut changed = false;
+    ui.label("Defaults");
+    changed |= generation_settings_ui(
+        ui,
+        "generation_options_global",
+        &mut llm_selector.generation_config.global,
+    );
+    ui.separator();
+    let model_key = llm_selector.get_llm_type().description();
+    ui.label(format!("Overrides for {}", model_key));
+    let generation_config = &mut llm_selector.generation_config;
+    let mut model_settings = generation_config
+        .per_model
+        .get(&model_key)
+        .cloned()
+        .unwrap_or_default();
+    if generation_settings_ui(ui, "generation_options_model", &mut model_settings) {
+        if model_settings.is_empty() {
+            generation_config.per_model.remove(&model_key);
+        } else {
+            generation_config
+                .per_model
+                .insert(model_key, model_settings);
+        }
+        changed = true;
+    }
+    if changed {
+        llm_selector.save_generation_config();
+    }
+}
@@ -1,4 +1,8 @@
 use crate::llm::LLMSelector;
+use crate::settings;
+use crate::ui::llm_selection_window::LlmSelectionWindow;
+use crate::ui::profiles_window::ProfilesWindow;
+use crate::ui::statistics_window::StatisticsWindow;
 use crate::ui::template_editor::TemplateEditor;
 use crate::ui::template_editor::TemplateMap;
 
@@ -8,8 +12,12 @@ use plugovr_cs::login_window::LoginWindow;
 use plugovr_types::UserInfo;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use webbrowser;
 
+/// Validating checks files on disk, so it's not done every frame.
+const SETTINGS_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
 #[cfg(feature = "computeruse_editor")]
 use crate::usecase_editor::UsecaseEditor;
 
@@ -17,6 +25,9 @@ pub struct MainWindow {
     #[cfg(feature = "cs")]
     login_window: LoginWindow,
     template_editor: TemplateEditor,
+    statistics_window: StatisticsWindow,
+    llm_selection_window: LlmSelectionWindow,
+    profiles_window: ProfilesWindow,
     window_pos_initialized: bool,
     pub user_info: Arc<Mutex<Option<UserInfo>>>,
     pub is_loading_user_info: Arc<Mutex<bool>>,
@@ -25,12 +36,17 @@ pub struct MainWindow {
     pub llm_selector: Arc<Mutex<LLMSelector>>,
     show_template_editor: Arc<Mutex<bool>>,
     show_llm_selector: Arc<Mutex<bool>>,
+    show_statistics: Arc<Mutex<bool>>,
+    show_profiles: Arc<Mutex<bool>>,
     show_login_window: Arc<Mutex<bool>>,
     pub menu_map: Arc<Mutex<Option<HashMap<String, String>>>>,
     #[cfg(feature = "computeruse_editor")]
     show_usecase_editor: Arc<Mutex<bool>>,
     #[cfg(feature = "computeruse_editor")]
     usecase_editor: Arc<Mutex<UsecaseEditor>>,
+    settings_problems: Vec<String>,
+    settings_checked: Option<Instant>,
+    dismissed_settings_problems: Vec<String>,
 }
 impl MainWindow {
     pub fn new(
@@ -45,11 +61,13 @@ impl MainWindow {
         let show_login_window = Arc::new(Mutex::new(false));
         let show_template_editor = Arc::new(Mutex::new(false));
         let show_llm_selector = Arc::new(Mutex::new(false));
+        let show_statistics = Arc::new(Mutex::new(false));
+        let show_profiles = Arc::new(Mutex::new(false));
         #[cfg(feature = "computeruse_editor")]
         let show_usecase_editor = Arc::new(Mutex::new(false));
         #[cfg(feature = "cs")]
         let login_window = LoginWindow::new(user_info.clone(), is_loading_user_info.clone());
-        let template_editor = TemplateEditor::new(prompt_templates.clone());
+        let template_editor = TemplateEditor::new(prompt_templates.clone(), llm_selector.clone());
         let menu_map = Arc::new(Mutex::new(Option::<HashMap<String, String>>::None));
         let menu_channel = MenuEvent::receiver();
 
@@ -57,6 +75,8 @@ impl MainWindow {
             let show_login_window = show_login_window.clone();
             let show_template_editor = show_template_editor.clone();
             let show_llm_selector = show_llm_selector.clone();
+            let show_statistics = show_statistics.clone();
+            let show_profiles = show_profiles.clone();
             let user_info = user_info.clone();
             let menu_map = menu_map.clone();
             #[cfg(feature = "computeruse_editor")]
@@ -88,6 +108,21 @@ impl MainWindow {
                             println!("LLM Selector");
                             *show_llm_selector.lock().unwrap() = true;
                         }
+                        if id == *menu_map.get("Statistics").unwrap_or(&"".to_string()) {
+                            *show_statistics.lock().unwrap() = true;
+                        }
+                        if id == *menu_map.get("Profiles").unwrap_or(&"".to_string()) {
+                            *show_profiles.lock().unwrap() = true;
+                        }
+                        for (key, value) in &menu_map {
+                            if let Some(profile) = key.strip_prefix("Profile:") {
+                                if id == *value {
+                                    if let Err(e) = settings::switch_profile(profile) {
+                                        eprintln!("Failed to switch profile: {}", e);
+                                    }
+                                }
+                            }
+                        }
                         if id == *menu_map.get("Updater").unwrap_or(&"".to_string()) {
                             let _ = webbrowser::open("https://plugovr.ai/download").is_ok();
                         }
@@ -104,6 +139,9 @@ impl MainWindow {
             #[cfg(feature = "cs")]
             login_window,
             template_editor,
+            statistics_window: StatisticsWindow::new(),
+            llm_selection_window: LlmSelectionWindow::new(&llm_selector.lock().unwrap()),
+            profiles_window: ProfilesWindow::new(),
             window_pos_initialized: false,
             user_info: user_info.clone(),
             is_loading_user_info,
@@ -112,12 +150,17 @@ impl MainWindow {
             llm_selector,
             show_template_editor,
             show_llm_selector,
+            show_statistics,
+            show_profiles,
             show_login_window,
             menu_map,
             #[cfg(feature = "computeruse_editor")]
             show_usecase_editor,
             #[cfg(feature = "computeruse_editor")]
             usecase_editor,
+            settings_problems: vec![],
+            settings_checked: None,
+            dismissed_settings_problems: vec![],
         }
     }
     pub fn show(&mut self, egui_context: &egui::Context) {
@@ -138,6 +181,20 @@ impl MainWindow {
         if self.template_editor.show {
             self.template_editor.show_template_editor(egui_context);
         }
+        if *self.show_statistics.lock().unwrap() {
+            self.statistics_window.show = true;
+            *self.show_statistics.lock().unwrap() = false;
+        }
+        if self.statistics_window.show {
+            self.statistics_window.show_statistics_window(egui_context);
+        }
+        if *self.show_profiles.lock().unwrap() {
+            self.profiles_window.show = true;
+            *self.show_profiles.lock().unwrap() = false;
+        }
+        if self.profiles_window.show {
+            self.profiles_window.show_profiles_window(egui_context);
+        }
         #[cfg(feature = "computeruse_editor")]
         if *self.show_usecase_editor.lock().unwrap() {
             *self.show_usecase_editor.lock().unwrap() = self
@@ -147,13 +204,57 @@ impl MainWindow {
                 .show_editor(egui_context);
         }
         if *self.show_llm_selector.lock().unwrap() {
-            self.llm_selector.lock().unwrap().toggle_window();
+            self.llm_selection_window.show = !self.llm_selection_window.show;
             *self.show_llm_selector.lock().unwrap() = false;
         }
 
-        self.llm_selector
-            .lock()
-            .expect("Failed to lock llm_selector POISON")
-            .show_selection_window(egui_context);
+        if self.llm_selection_window.show {
+            self.llm_selection_window.show_llm_selection_window(
+                egui_context,
+                &mut self
+                    .llm_selector
+                    .lock()
+                    .expect("Failed to lock llm_selector POISON"),
+            );
+        }
+        self.show_settings_problems(egui_context);
+    }
+
+    /// Lists what went wrong reading or migrating the settings and the values
+    /// that are invalid, until they are fixed or dismissed.
+    fn show_settings_problems(&mut self, egui_context: &egui::Context) {
+        if self
+            .settings_checked
+            .is_none_or(|checked| checked.elapsed() > SETTINGS_CHECK_INTERVAL)
+        {
+            self.settings_problems = settings::problems();
+            self.settings_checked = Some(Instant::now());
+        }
+        if self.settings_problems.is_empty()
+            || self.settings_problems == self.dismissed_settings_problems
+        {
+            return;
+        }
+        egui::Window::new("Settings problems")
+            .collapsible(false)
+            .show(egui_context, |ui| {
+                ui.label(format!(
+                    "Problems in {}:",
+                    settings::config_path("settings.json").display()
+                ));
+                for problem in &self.settings_problems {
+                    ui.colored_label(egui::Color32::RED, problem);
+                }
+                ui.label("Fix the values in the settings or in the file and restart PlugOvr.");
+                ui.horizontal(|ui| {
+                    if ui.button("Check again").clicked() {
+                        settings::reload();
+                        self.settings_checked = None;
+                    }
+                    if ui.button("Dismiss").clicked() {
+                        self.dismissed_settings_problems = self.settings_problems.clone();
+                    }
+                });
+            });
     }
 }
@@ -0,0 +1,158 @@
+use crate::settings::{self, Settings};
+
+/// Lists the settings profiles (e.g. work, personal or one per client) and
+/// switches between them. A profile has its own model, templates, replay
+/// servers and can be restricted to local models.
+#[derive(Default)]
+pub struct ProfilesWindow {
+    pub show: bool,
+    new_name: String,
+    /// The profile being renamed and its new name.
+    renaming: Option<(String, String)>,
+    error: Option<String>,
+}
+
+enum ProfileAction {
+    Switch(String),
+    SetLocalOnly(String, bool),
+    Create(String),
+    Rename(String, String),
+    Delete(String),
+}
+
+impl ProfilesWindow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn show_profiles_window(&mut self, egui_context: &egui::Context) {
+        let settings = settings::load();
+        let mut show_window = self.show;
+        let mut action = None;
+        egui::Window::new("Profiles")
+            .resizable(false)
+            .collapsible(false)
+            .open(&mut show_window)
+            .show(egui_context, |ui| {
+                egui::Grid::new("profiles_grid")
+                    .num_columns(4)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (name, profile) in &settings.profiles {
+                            let active = *name == settings.active_profile;
+                            if ui.radio(active, name).clicked() && !active {
+                                action = Some(ProfileAction::Switch(name.clone()));
+                            }
+                            let mut local_only = profile.local_only;
+                            if ui.checkbox(&mut local_only, "Local models only").changed() {
+                                action =
+                                    Some(ProfileAction::SetLocalOnly(name.clone(), local_only));
+                            }
+                            ui.weak(profile.llm_type.description());
+                            ui.horizontal(|ui| match &mut self.renaming {
+                                Some((old_name, new_name)) if old_name == name => {
+                                    ui.add(
+                                        egui::TextEdit::singleline(new_name).desired_width(120.0),
+                                    );
+                                    if ui.button("OK").clicked() {
+                                        action = Some(ProfileAction::Rename(
+                                            old_name.clone(),
+                                            new_name.clone(),
+                                        ));
+                                    }
+                                    if ui.button("Cancel").clicked() {
+                                        self.renaming = None;
+                                    }
+                                }
+                                _ => {
+                                    if ui.button("Rename").clicked() {
+                                        self.renaming = Some((name.clone(), name.clone()));
+                                    }
+                                    if ui
+                                        .add_enabled(!active, egui::Button::new("Delete"))
+                                        .on_disabled_hover_text(
+                                            "The active profile can't be deleted",
+                                        )
+                                        .clicked()
+                                    {
+                                        action = Some(ProfileAction::Delete(name.clone()));
+                                    }
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.new_name)
+                            .hint_text("Name, e.g. Client A")
+                            .desired_width(160.0),
+                    );
+                    if ui.button("Create from active profile").clicked() {
+                        action = Some(ProfileAction::Create(self.new_name.clone()));
+                    }
+                });
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+        self.show = show_window;
+
+        if let Some(action) = action {
+            self.error = self.apply(action, &settings).err();
+        }
+    }
+
+    fn apply(&mut self, action: ProfileAction, settings: &Settings) -> Result<(), String> {
+        let result = match action {
+            ProfileAction::Switch(name) => settings::switch_profile(&name),
+            ProfileAction::SetLocalOnly(name, local_only) => settings::update(|settings| {
+                if let Some(profile) = settings.profiles.get_mut(&name) {
+                    profile.local_only = local_only;
+                }
+            }),
+            ProfileAction::Create(name) => {
+                let name = check_name(&name, settings)?;
+                self.new_name.clear();
+                settings::update(|settings| {
+                    let profile = settings.profile();
+                    settings.profiles.insert(name, profile);
+                })
+            }
+            ProfileAction::Rename(old_name, new_name) if old_name == new_name.trim() => {
+                self.renaming = None;
+                Ok(())
+            }
+            ProfileAction::Rename(old_name, new_name) => {
+                let new_name = check_name(&new_name, settings)?;
+                self.renaming = None;
+                settings::update(|settings| {
+                    if let Some(profile) = settings.profiles.remove(&old_name) {
+                        settings.profiles.insert(new_name.clone(), profile);
+                    }
+                    if settings.active_profile == old_name {
+                        settings.active_profile = new_name;
+                    }
+                })
+            }
+            ProfileAction::Delete(name) => settings::update(|settings| {
+                if settings.active_profile != name {
+                    settings.profiles.remove(&name);
+                }
+            }),
+        };
+        result.map_err(|e| format!("Failed to save the profiles: {}", e))
+    }
+}
+
+fn check_name(name: &str, settings: &Settings) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The profile needs a name".to_string());
+    }
+    if settings.profiles.contains_key(name) {
+        return Err(format!("The profile {} already exists", name));
+    }
+    Ok(name.to_string())
+}
@@ -1,5 +1,5 @@
 use super::user_interface::PlugOvr;
-use itertools::Itertools;
+use crate::templates;
 
 impl PlugOvr {
     pub fn show_shortcut_window(&mut self, egui_context: &egui::Context, scale: f32) {
@@ -16,16 +16,30 @@ impl PlugOvr {
         let x = text_entryfield_position.0 as f32 / scale;
         let y = text_entryfield_position.1 as f32 / scale;
         window = window.current_pos(egui::pos2(x, y));
-        let templates = self
-            .prompt_templates
-            .lock()
-            .expect("Failed to lock prompt_templates POISON");
+        let focused_app = self.assistance_window.focused_app();
+        let calls = templates::shortcut_calls(
+            &self
+                .prompt_templates
+                .lock()
+                .expect("Failed to lock prompt_templates POISON"),
+            &focused_app,
+        )
+        .into_iter()
+        .map(|(call, template)| {
+            (
+                template.button_text(&call),
+                call,
+                template.description.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
         window.show(egui_context, |ui| {
             // Calculate the maximum width needed for any button
-            let max_width = templates
+            let max_width = calls
                 .iter()
-                .filter(|(_, (_, _, is_shortcut))| *is_shortcut)
-                .map(|(key, _)| ui.text_style_height(&egui::TextStyle::Body) * key.len() as f32)
+                .map(|(text, _, _)| {
+                    ui.text_style_height(&egui::TextStyle::Body) * text.len() as f32
+                })
                 .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
                 .unwrap_or(100.0);
             ui.label("AI Context:");
@@ -36,16 +50,16 @@ impl PlugOvr {
                     .expect("Failed to lock ai_context POISON")
                     .clone(),
             );
-            for (key, _) in templates
-                .iter()
-                .filter(|(_, (_, _, is_shortcut))| *is_shortcut)
-                .sorted_by(|a, b| a.0.cmp(b.0))
-            {
-                let button = egui::Button::new(key)
+            for (text, call, description) in &calls {
+                let button = egui::Button::new(text)
                     .min_size(egui::vec2(max_width, ui.spacing().interact_size.y));
+                let mut response = ui.add(button);
+                if !description.is_empty() {
+                    response = response.on_hover_text(description);
+                }
 
-                if ui.add(button).clicked() {
-                    self.assistance_window.text = key.clone();
+                if response.clicked() {
+                    self.assistance_window.text = call.clone();
                     *self
                         .text_entry
                         .lock()
@@ -0,0 +1,90 @@
+use crate::llm::metrics::{MetricsSummary, clear_metrics, load_metrics, summarize_metrics};
+
+#[derive(Default)]
+pub struct StatisticsWindow {
+    pub show: bool,
+    summaries: Vec<MetricsSummary>,
+    loaded: bool,
+}
+
+impl StatisticsWindow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn reload(&mut self) {
+        self.summaries = summarize_metrics(&load_metrics().unwrap_or_default());
+        self.loaded = true;
+    }
+
+    pub fn show_statistics_window(&mut self, egui_context: &egui::Context) {
+        if !self.loaded {
+            self.reload();
+        }
+        let mut show_window = self.show;
+        let mut reload = false;
+        egui::Window::new("Statistics")
+            .resizable(true)
+            .collapsible(false)
+            .open(&mut show_window)
+            .show(egui_context, |ui| {
+                if self.summaries.is_empty() {
+                    ui.label("No requests recorded yet.");
+                } else {
+                    egui::Grid::new("statistics_grid")
+                        .num_columns(9)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.strong("Model");
+                            ui.strong("Template");
+                            ui.strong("Requests");
+                            ui.strong("Errors");
+                            ui.strong("Cancelled");
+                            ui.strong("Prompt tokens");
+                            ui.strong("Answer tokens");
+                            ui.strong("First token");
+                            ui.strong("Tokens/s");
+                            ui.end_row();
+                            for summary in &self.summaries {
+                                ui.label(&summary.model);
+                                ui.label(&summary.template);
+                                ui.label(summary.requests.to_string());
+                                ui.label(summary.errors.to_string());
+                                ui.label(summary.cancelled.to_string());
+                                ui.label(format!("~{:.0}", summary.avg_prompt_tokens));
+                                ui.label(format!("{:.0}", summary.avg_answer_tokens));
+                                ui.label(match summary.avg_time_to_first_token_ms {
+                                    Some(ms) => format!("{:.2} s", ms / 1000.0),
+                                    None => "-".to_string(),
+                                });
+                                ui.label(match summary.avg_tokens_per_second {
+                                    Some(tokens_per_second) => format!("{:.1}", tokens_per_second),
+                                    None => "-".to_string(),
+                                });
+                                ui.end_row();
+                            }
+                        });
+                    ui.label("Averages over successful requests, prompt tokens are estimated.");
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Refresh").clicked() {
+                        reload = true;
+                    }
+                    if ui.button("Clear statistics").clicked() {
+                        if let Err(e) = clear_metrics() {
+                            eprintln!("Failed to clear statistics: {}", e);
+                        }
+                        reload = true;
+                    }
+                });
+            });
+        if reload {
+            self.reload();
+        }
+        if !show_window {
+            // Load fresh numbers the next time the window opens.
+            self.loaded = false;
+        }
+        self.show = show_window;
+    }
+}
@@ -2,11 +2,18 @@ use crate::llm::CloudModel; // Add this line
 use crate::llm::LLMSelector;
 use crate::llm::LLMType;
 use crate::llm::LocalModel;
-use std::collections::HashMap;
+use crate::settings;
+use crate::templates::{
+    self, BUILTIN_VARIABLES, PromptTemplate, Templates, create_prompt_templates,
+    packs::with_team_templates,
+};
+use crate::ui::assistance_window::AiResponseAction;
+use crate::ui::generation_options::generation_settings_ui;
+use crate::ui::template_packs::TemplatePacksWindow;
 use std::sync::{Arc, Mutex};
 use strum::IntoEnumIterator;
 
-pub type TemplateMap = Arc<Mutex<HashMap<String, (String, Option<LLMType>, bool)>>>;
+pub type TemplateMap = Arc<Mutex<Templates>>;
 
 pub struct TemplateEditor {
     pub show: bool,
@@ -15,17 +22,22 @@ pub struct TemplateEditor {
     new_template_value: String,
     reset_templates_confirmation: String,
     llm_selector: Arc<Mutex<LLMSelector>>,
+    /// Template whose description, icon, tags and options are being edited.
+    details_template: Option<(String, PromptTemplate)>,
+    packs_window: TemplatePacksWindow,
 }
 
 impl TemplateEditor {
-    pub fn new(prompt_templates: TemplateMap) -> Self {
+    pub fn new(prompt_templates: TemplateMap, llm_selector: Arc<Mutex<LLMSelector>>) -> Self {
         Self {
             show: false,
-            prompt_templates,
+            prompt_templates: prompt_templates.clone(),
             new_template_key: String::new(),
             new_template_value: String::new(),
             reset_templates_confirmation: String::new(),
-            llm_selector: Arc::new(Mutex::new(LLMSelector::new(Arc::new(Mutex::new(None))))),
+            llm_selector,
+            details_template: None,
+            packs_window: TemplatePacksWindow::new(prompt_templates.clone()),
         }
     }
     pub fn show_template_editor(&mut self, egui_context: &egui::Context) {
@@ -61,11 +73,43 @@ impl TemplateEditor {
                             // Use templates_vec instead of templates_clone in the loop
                             for (key, value) in &templates_vec {
                                 let mut local_value = value.clone();
+                                if let Some(pack) = &value.team_pack {
+                                    ui.label(key).on_hover_text(format!(
+                                        "From the team pack {}, read-only",
+                                        pack
+                                    ));
+                                    ui.weak(&value.instruction);
+                                    ui.weak(match &value.llm_type {
+                                        Some(llm_type) => llm_type.description(),
+                                        None => "default".to_string(),
+                                    });
+                                    ui.add_enabled(false, egui::Checkbox::without_text(&mut local_value.shortcut));
+                                    if ui
+                                        .button("Copy")
+                                        .on_hover_text("Copies it to your templates to edit it")
+                                        .clicked()
+                                    {
+                                        local_value.team_pack = None;
+                                        templates_to_add.push((key.clone(), local_value));
+                                    }
+                                    ui.end_row();
+                                    continue;
+                                }
                                 ui.label(key);
-                                let value_edit = ui.text_edit_singleline(&mut local_value.0);
-                                let mut selected_llm = local_value.1.clone();
+                                let problems =
+                                    templates::check_template(key, &local_value.instruction);
+                                let mut value_edit = ui.add(
+                                    egui::TextEdit::singleline(&mut local_value.instruction)
+                                        .text_color_opt(
+                                        (!problems.is_empty()).then_some(egui::Color32::RED),
+                                    ),
+                                );
+                                if !problems.is_empty() {
+                                    value_edit = value_edit.on_hover_text(problems.join("\n"));
+                                }
+                                let mut selected_llm = local_value.llm_type.clone();
                                 egui::ComboBox::from_id_source(format!("llm_type_{}", key))
-                                    .selected_text(if let Some(llm_type) = &local_value.1 {
+                                    .selected_text(if let Some(llm_type) = &local_value.llm_type {
                                         format!("{:?}", llm_type.description())
                                     } else {
                                         "default".to_string()
@@ -86,6 +130,17 @@ impl TemplateEditor {
                                                 local_model.description(),
                                             );
                                         }
+                                        let custom_models =
+                                            self.llm_selector.lock().unwrap().custom_models();
+                                        for custom_model in custom_models {
+                                            let llm_type = LLMType::CustomGguf(custom_model);
+                                            let description = llm_type.description();
+                                            ui.selectable_value(
+                                                &mut selected_llm,
+                                                Some(llm_type),
+                                                description,
+                                            );
+                                        }
                                         let ollama_models = self
                                             .llm_selector
                                             .lock()
@@ -104,19 +159,45 @@ impl TemplateEditor {
                                                 );
                                             }
                                         }
+                                        let openai_llm_type = self
+                                            .llm_selector
+                                            .lock()
+                                            .unwrap()
+                                            .openai_compatible_llm_type();
+                                        if let LLMType::OpenAiCompatible { model, .. } =
+                                            &openai_llm_type
+                                        {
+                                            if !model.is_empty() {
+                                                let description = openai_llm_type.description();
+                                                ui.selectable_value(
+                                                    &mut selected_llm,
+                                                    Some(openai_llm_type),
+                                                    description,
+                                                );
+                                            }
+                                        }
 
-                                        if selected_llm != local_value.1.clone() {
-                                            local_value.1 = selected_llm;
+                                        if selected_llm != local_value.llm_type {
+                                            local_value.llm_type = selected_llm;
                                             templates_to_add
                                                 .push((key.clone(), local_value.clone()));
                                         }
                                     });
 
-                                if ui.checkbox(&mut local_value.2, "").changed() {
+                                if ui.checkbox(&mut local_value.shortcut, "").changed() {
                                     templates_to_add.push((key.clone(), local_value.clone()));
                                 }
 
                                 ui.horizontal(|ui| {
+                                    if ui
+                                        .button("Details")
+                                        .on_hover_text(
+                                            "Description, icon, tags, apps, answer action and generation options",
+                                        )
+                                        .clicked()
+                                    {
+                                        self.details_template = Some((key.clone(), value.clone()));
+                                    }
                                     if ui
                                         .button("Default")
                                         .on_hover_text("Resets to default value if existed.")
@@ -156,15 +237,29 @@ impl TemplateEditor {
                                         Some(egui::Color32::RED)
                                     }),
                             );
-                            ui.text_edit_singleline(&mut self.new_template_value);
+                            let problems = templates::check_template(
+                                &self.new_template_key,
+                                &self.new_template_value,
+                            );
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.new_template_value)
+                                    .text_color_opt(
+                                        (!problems.is_empty()).then_some(egui::Color32::RED),
+                                    ),
+                            )
+                            .on_hover_text(problems.join("\n"));
                             if ui.button("Add").clicked()
                                 && !self.new_template_key.is_empty()
                                 && !self.new_template_value.is_empty()
                                 && key.starts_with('@')
+                                && problems.is_empty()
                             {
                                 self.prompt_templates.lock().unwrap().insert(
                                     self.new_template_key.clone(),
-                                    (self.new_template_value.clone(), None, false),
+                                    PromptTemplate {
+                                        instruction: self.new_template_value.clone(),
+                                        ..Default::default()
+                                    },
                                 );
                                 self.save_templates();
                                 self.new_template_key.clear();
@@ -172,6 +267,14 @@ impl TemplateEditor {
                             }
                         });
 
+                    ui.weak(format!(
+                        "Parameters: @translate(lang) or @translate(lang=english|german) with {{{{lang}}}} in the instruction. Variables: {}",
+                        BUILTIN_VARIABLES
+                            .iter()
+                            .map(|variable| format!("{{{{{}}}}}", variable))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
                     ui.horizontal(|ui| {
                         ui.label("Reset all templates to default:");
                         ui.add(
@@ -185,6 +288,13 @@ impl TemplateEditor {
                             self.reset_templates_confirmation.clear();
                         }
                     });
+                    if ui
+                        .button("Template packs")
+                        .on_hover_text("Export, import and team templates")
+                        .clicked()
+                    {
+                        self.packs_window.show = true;
+                    }
                     // Handle additions and removals
                     let mut templates = self.prompt_templates.lock().unwrap();
                     for key in &templates_to_remove {
@@ -198,129 +308,152 @@ impl TemplateEditor {
                         drop(templates); // Release the lock before saving
                         self.save_templates();
                     }
+                    if !templates_to_remove.is_empty() {
+                        // Brings back team templates that were overridden.
+                        self.load_templates();
+                    }
 
                     ui.add_space(10.0);
                 });
             });
         self.show = show_window; // Update original value
+        self.show_details_window(egui_context);
+        if self.packs_window.show_template_packs_window(egui_context) {
+            self.save_templates();
+            self.load_templates();
+        }
     }
 
+    fn show_details_window(&mut self, egui_context: &egui::Context) {
+        let Some((key, template)) = self.details_template.as_mut() else {
+            return;
+        };
+        let mut open = true;
+        let mut changed = false;
+        egui::Window::new(format!("Template {}", key))
+            .collapsible(false)
+            .open(&mut open)
+            .show(egui_context, |ui| {
+                egui::Grid::new("template_details_grid")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Description");
+                        changed |= ui.text_edit_singleline(&mut template.description).changed();
+                        ui.end_row();
+                        ui.label("Icon");
+                        changed |= ui
+                            .add(
+                                egui::TextEdit::singleline(&mut template.icon)
+                                    .hint_text("e.g. an emoji")
+                                    .desired_width(40.0),
+                            )
+                            .changed();
+                        ui.end_row();
+                        ui.label("Tags");
+                        let mut tags = template.tags.join(", ");
+                        if ui
+                            .add(egui::TextEdit::singleline(&mut tags).hint_text("comma separated"))
+                            .changed()
+                        {
+                            template.tags = split_list(&tags);
+                            changed = true;
+                        }
+                        ui.end_row();
+                        ui.label("Apps").on_hover_text(
+                            "Listed first in these applications, by name or window class, \
+                             or title: and a part of the window title",
+                        );
+                        let mut apps = template.apps.join(", ");
+                        if ui
+                            .add(
+                                egui::TextEdit::singleline(&mut apps)
+                                    .hint_text("e.g. firefox, code, title:github"),
+                            )
+                            .changed()
+                        {
+                            template.apps = split_list(&apps);
+                            changed = true;
+                        }
+                        ui.end_row();
+                        ui.label("Answer");
+                        egui::ComboBox::from_id_salt("template_response_action")
+                            .selected_text(match template.response_action {
+                                Some(action) => format!("{:?}", action),
+                                None => "as selected in the window".to_string(),
+                            })
+                            .show_ui(ui, |ui| {
+                                for action in [
+                                    None,
+                                    Some(AiResponseAction::Replace),
+                                    Some(AiResponseAction::Extend),
+                                    Some(AiResponseAction::Ignore),
+                                ] {
+                                    let text = match action {
+                                        Some(action) => format!("{:?}", action),
+                                        None => "as selected in the window".to_string(),
+                                    };
+                                    changed |= ui
+                                        .selectable_value(
+                                            &mut template.response_action,
+                                            action,
+                                            text,
+                                        )
+                                        .changed();
+                                }
+                            });
+                        ui.end_row();
+                    });
+                ui.separator();
+                ui.label("Generation options, unchecked values use the settings of the model.");
+                changed |= generation_settings_ui(
+                    ui,
+                    "template_generation_options",
+                    &mut template.options,
+                );
+            });
+        if changed {
+            let (key, template) = (key.clone(), template.clone());
+            self.prompt_templates.lock().unwrap().insert(key, template);
+            self.save_templates();
+        }
+        if !open || !self.show {
+            self.details_template = None;
+        }
+    }
+
+    /// Saves the personal templates, team templates stay in their packs.
     pub fn save_templates(&self) {
-        let templates = self.prompt_templates.lock().unwrap();
-        let templates_json =
-            serde_json::to_string_pretty(&*templates).expect("Failed to serialize templates");
-        let home_dir = dirs::home_dir().expect("Unable to find home directory");
-        let config_dir = home_dir.join(".plugovr");
-        std::fs::create_dir_all(&config_dir).expect("Failed to create config directory");
-        let config_file = config_dir.join("templates.json");
-        std::fs::write(config_file, templates_json).expect("Failed to write templates to file");
+        let mut templates = self.prompt_templates.lock().unwrap().clone();
+        templates.retain(|_, template| template.team_pack.is_none());
+        settings::update(|settings| settings.profile_mut().templates = Some(templates))
+            .unwrap_or_else(|e| eprintln!("Failed to save templates: {}", e));
     }
 
     pub fn load_templates(&mut self) {
-        let home_dir = dirs::home_dir().expect("Unable to find home directory");
-        let config_file = home_dir.join(".plugovr").join("templates.json");
-        if let Ok(templates_json) = std::fs::read_to_string(config_file) {
-            if let Ok(loaded_templates) = serde_json::from_str(&templates_json) {
-                *self.prompt_templates.lock().unwrap() = loaded_templates;
-            }
-        }
+        *self.prompt_templates.lock().unwrap() = load_active_templates();
     }
     pub fn reset_templates(&mut self) {
-        let home_dir = dirs::home_dir().expect("Unable to find home directory");
-        let config_file = home_dir.join(".plugovr").join("templates.json");
-        match std::fs::remove_file(config_file) {
+        match settings::update(|settings| settings.profile_mut().templates = None) {
             Ok(_) => println!("Templates reset successfully"),
             Err(e) => println!("Failed to reset templates: {}", e),
         }
-        *self.prompt_templates.lock().unwrap() = create_prompt_templates();
+        *self.prompt_templates.lock().unwrap() = with_team_templates(create_prompt_templates());
     }
 }
-pub fn create_prompt_templates() -> HashMap<String, (String, Option<LLMType>, bool)> {
-    let mut templates: HashMap<String, (String, Option<LLMType>, bool)> = HashMap::new();
-    templates.insert(
-        "@correct".to_string(),
-        (
-            "Correct the text without explanation".to_string(),
-            None,
-            true,
-        ),
-    );
-    templates.insert(
-        "@translate(english)".to_string(),
-        (
-            "Translate the text to english without explanation".to_string(),
-            None,
-            true,
-        ),
-    );
-    templates.insert(
-        "@translate(german)".to_string(),
-        (
-            "Translate the text to german without explanation".to_string(),
-            None,
-            true,
-        ),
-    );
-    templates.insert(
-        "@translate(spanish)".to_string(),
-        (
-            "Translate the text to spanish without explanation".to_string(),
-            None,
-            true,
-        ),
-    );
-    templates.insert(
-        "@summarize".to_string(),
-        (
-            "Provide a short summary of the text".to_string(),
-            None,
-            true,
-        ),
-    );
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
 
-    templates.insert(
-        "@improve".to_string(),
-        (
-            "Suggest improvements or enhancements for the given text without explanation"
-                .to_string(),
-            None,
-            false,
-        ),
-    );
-    templates.insert(
-        "@format".to_string(),
-        (
-            "Format the text for better readability without explanation".to_string(),
-            None,
-            true,
-        ),
-    );
+/// The templates saved in the active profile, None if there are none yet.
+pub fn load_saved_templates() -> Option<Templates> {
+    settings::load().profile().templates
+}
 
-    templates.insert(
-        "@simplify".to_string(),
-        ("Simplify complex text or concepts".to_string(), None, false),
-    );
-    templates.insert(
-        "@extend".to_string(),
-        (
-            "continue the text without explanation".to_string(),
-            None,
-            true,
-        ),
-    );
-    templates.insert(
-        "@filename".to_string(),
-        ("propose filename for document: structure:date(year_month_day )_topic_company. Output only filename".to_string(), None, false),
-    );
-    // templates.insert(
-    //     "@fillfields".to_string(),
-    //     ("Extract caption and coordinates from all textboxes in image 2. For each textbox, find the corresponding content in image 1. Output the information in format of json. [{ \"content_image_1\": \"content\", \"caption_textbox_image_2\": \"caption\", \"coordinates_textbox_image_2\": \"[x1,y1,x2,y2]\" }]".to_string(),
-    //     None),
-    // );
-    templates.insert(
-             "@fillform".to_string(),
-             ("#computeruse Output the coordinates for each input field / textbox in json format from image 1 (screenshot) and fill with information from images starting from image 2. The original textbox should be empty before we fill it. Json format: [{ \"caption\": \"<caption>\", \"content\": \"<content>\", \"coordinates\": \"[x1, y1, x2, y2]\" }]".to_string(),
-        Some(LLMType::Cloud(CloudModel::AnthropicSonnet3_5)), false),
-    );
-    templates
+/// The saved or built-in templates of the active profile on top of the team
+/// templates.
+pub fn load_active_templates() -> Templates {
+    with_team_templates(load_saved_templates().unwrap_or_else(create_prompt_templates))
 }
@@ -0,0 +1,296 @@
+use crate::settings;
+use crate::templates::packs::{Conflict, TemplatePack};
+use crate::ui::template_editor::TemplateMap;
+use egui_file_dialog::FileDialog;
+use std::collections::{BTreeSet, HashMap};
+
+/// What the file dialog was opened for.
+enum PackDialog {
+    Export,
+    Import,
+    TeamDir,
+}
+
+/// Exports templates to a pack file, imports packs and sets the team
+/// directory whose packs are loaded read-only.
+pub struct TemplatePacksWindow {
+    pub show: bool,
+    prompt_templates: TemplateMap,
+    /// Templates ticked for the export.
+    selected: BTreeSet<String>,
+    pack_name: String,
+    file_dialog: FileDialog,
+    dialog: Option<PackDialog>,
+    /// A pack with conflicts or models on other servers, and the choice for
+    /// each conflict.
+    pending_import: Option<(TemplatePack, HashMap<String, Conflict>)>,
+    /// Whether the templates of the pending pack keep models that send the
+    /// text to another server.
+    keep_remote_models: bool,
+    /// Result of the last export or import.
+    message: Option<Result<String, String>>,
+}
+
+impl TemplatePacksWindow {
+    pub fn new(prompt_templates: TemplateMap) -> Self {
+        Self {
+            show: false,
+            prompt_templates,
+            selected: BTreeSet::new(),
+            pack_name: String::new(),
+            file_dialog: FileDialog::new(),
+            dialog: None,
+            pending_import: None,
+            keep_remote_models: false,
+            message: None,
+        }
+    }
+
+    /// Returns true if the templates or the team directory changed.
+    pub fn show_template_packs_window(&mut self, egui_context: &egui::Context) -> bool {
+        if !self.show {
+            return false;
+        }
+        let mut changed = false;
+        let mut keys = self
+            .prompt_templates
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        let mut show_window = self.show;
+        egui::Window::new("Template Packs")
+            .collapsible(false)
+            .open(&mut show_window)
+            .show(egui_context, |ui| {
+                let (mut import_clicked, mut cancel_clicked) = (false, false);
+                ui.label("Export the selected templates to share them:");
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        for key in &keys {
+                            let mut selected = self.selected.contains(key);
+                            if ui.checkbox(&mut selected, key).changed() {
+                                if selected {
+                                    self.selected.insert(key.clone());
+                                } else {
+                                    self.selected.remove(key);
+                                }
+                            }
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    if ui.button("All").clicked() {
+                        self.selected = keys.iter().cloned().collect();
+                    }
+                    if ui.button("None").clicked() {
+                        self.selected.clear();
+                    }
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.pack_name)
+                            .hint_text("Pack name")
+                            .desired_width(150.0),
+                    );
+                    if ui
+                        .add_enabled(!self.selected.is_empty(), egui::Button::new("Export…"))
+                        .clicked()
+                    {
+                        self.dialog = Some(PackDialog::Export);
+                        self.file_dialog.save_file();
+                    }
+                });
+                ui.separator();
+                match &mut self.pending_import {
+                    None => {
+                        if ui.button("Import pack…").clicked() {
+                            self.dialog = Some(PackDialog::Import);
+                            self.file_dialog.pick_file();
+                        }
+                    }
+                    Some((pack, resolutions)) => {
+                        if !resolutions.is_empty() {
+                            ui.label(format!(
+                                "These templates of {} differ from yours:",
+                                pack.name
+                            ));
+                            egui::Grid::new("pack_conflicts_grid")
+                                .num_columns(2)
+                                .striped(true)
+                                .show(ui, |ui| {
+                                    let mut conflicts =
+                                        resolutions.keys().cloned().collect::<Vec<_>>();
+                                    conflicts.sort();
+                                    for key in conflicts {
+                                        ui.label(&key);
+                                        let resolution = resolutions.get_mut(&key).unwrap();
+                                        ui.horizontal(|ui| {
+                                            ui.radio_value(resolution, Conflict::Keep, "Keep mine");
+                                            ui.radio_value(
+                                                resolution,
+                                                Conflict::Overwrite,
+                                                "Overwrite",
+                                            );
+                                            ui.radio_value(
+                                                resolution,
+                                                Conflict::Rename,
+                                                "Import renamed",
+                                            );
+                                        });
+                                        ui.end_row();
+                                    }
+                                });
+                        }
+                        let remote_models = pack.remote_models();
+                        if !remote_models.is_empty() {
+                            ui.label(format!(
+                                "These templates of {} send the text to another server:",
+                                pack.name
+                            ));
+                            egui::Grid::new("pack_remote_models_grid")
+                                .num_columns(2)
+                                .striped(true)
+                                .show(ui, |ui| {
+                                    for (key, server) in &remote_models {
+                                        ui.label(key);
+                                        ui.colored_label(
+                                            egui::Color32::from_rgb(255, 165, 0),
+                                            server,
+                                        );
+                                        ui.end_row();
+                                    }
+                                });
+                            ui.checkbox(
+                                &mut self.keep_remote_models,
+                                "Keep these servers, otherwise the selected model is used",
+                            );
+                        }
+                        ui.horizontal(|ui| {
+                            import_clicked = ui.button("Import").clicked();
+                            cancel_clicked = ui.button("Cancel").clicked();
+                        });
+                    }
+                }
+                if cancel_clicked {
+                    self.pending_import = None;
+                }
+                if import_clicked {
+                    if let Some((pack, resolutions)) = self.pending_import.take() {
+                        self.import(pack, &resolutions, self.keep_remote_models);
+                        changed = true;
+                    }
+                }
+                ui.separator();
+                let team_dir = settings::load().team_templates_dir;
+                ui.horizontal(|ui| {
+                    ui.label("Team directory:");
+                    match &team_dir {
+                        Some(dir) => ui.label(dir.display().to_string()),
+                        None => ui.weak("none"),
+                    };
+                    if ui.button("Choose…").clicked() {
+                        self.dialog = Some(PackDialog::TeamDir);
+                        self.file_dialog.select_directory();
+                    }
+                    if team_dir.is_some() && ui.button("Clear").clicked() {
+                        changed |= self.set_team_dir(None);
+                    }
+                });
+                ui.weak(
+                    "The packs in the team directory are read-only, \
+                     your templates of the same name are used instead.",
+                );
+                match &self.message {
+                    Some(Ok(message)) => {
+                        ui.label(message);
+                    }
+                    Some(Err(error)) => {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    None => {}
+                }
+            });
+        self.show = show_window;
+
+        self.file_dialog.update(egui_context);
+        if let Some(path) = self.file_dialog.take_picked() {
+            match self.dialog.take() {
+                Some(PackDialog::Export) => {
+                    let path = if path.extension().is_none() {
+                        path.with_extension("json")
+                    } else {
+                        path
+                    };
+                    let templates = self.prompt_templates.lock().unwrap().clone();
+                    let pack = TemplatePack::new(&self.pack_name, &templates, &self.selected);
+                    self.message = Some(
+                        pack.save(&path)
+                            .map(|_| {
+                                format!(
+                                    "Exported {} templates to {}",
+                                    pack.templates.len(),
+                                    path.display()
+                                )
+                            })
+                            .map_err(|e| format!("Failed to export the pack: {}", e)),
+                    );
+                }
+                Some(PackDialog::Import) => match TemplatePack::load(&path) {
+                    Ok(pack) => {
+                        let conflicts = pack.conflicts(&self.prompt_templates.lock().unwrap());
+                        if conflicts.is_empty() && pack.remote_models().is_empty() {
+                            self.import(pack, &HashMap::new(), false);
+                            changed = true;
+                        } else {
+                            let resolutions = conflicts
+                                .into_iter()
+                                .map(|key| (key, Conflict::Keep))
+                                .collect();
+                            self.pending_import = Some((pack, resolutions));
+                            self.keep_remote_models = false;
+                        }
+                    }
+                    Err(e) => {
+                        self.message = Some(Err(format!(
+                            "Failed to load the pack {}: {}",
+                            path.display(),
+                            e
+                        )));
+                    }
+                },
+                Some(PackDialog::TeamDir) => changed |= self.set_team_dir(Some(path)),
+                None => {}
+            }
+        }
+        changed
+    }
+
+    fn import(
+        &mut self,
+        mut pack: TemplatePack,
+        resolutions: &HashMap<String, Conflict>,
+        keep_remote_models: bool,
+    ) {
+        if !keep_remote_models {
+            pack.drop_remote_models();
+        }
+        let mut templates = self.prompt_templates.lock().unwrap();
+        let imported = pack.merge_into(&mut templates, resolutions);
+        self.message = Some(Ok(if imported.is_empty() {
+            "No new templates in the pack".to_string()
+        } else {
+            format!("Imported {}", imported.join(", "))
+        }));
+    }
+
+    fn set_team_dir(&mut self, dir: Option<std::path::PathBuf>) -> bool {
+        match settings::update(|settings| settings.team_templates_dir = dir) {
+            Ok(_) => true,
+            Err(e) => {
+                self.message = Some(Err(format!("Failed to save the team directory: {}", e)));
+                false
+            }
+        }
+    }
+}
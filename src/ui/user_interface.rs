@@ -1,11 +1,12 @@
 use crate::ActiveWindow;
 use crate::llm::LLMSelector;
+use crate::settings::{self, Settings};
+use crate::templates::{create_prompt_templates, packs::with_team_templates};
 use crate::ui::assistance_window::AssistanceWindow;
 use crate::ui::main_window::MainWindow;
 use crate::ui::screen_dimensions::get_screen_dimensions;
 use crate::ui::template_editor::TemplateEditor;
 use crate::ui::template_editor::TemplateMap;
-use crate::ui::template_editor::create_prompt_templates;
 #[cfg(feature = "computeruse_editor")]
 use crate::usecase_editor::UsecaseEditor;
 #[cfg(feature = "computeruse_record")]
@@ -17,6 +18,7 @@ use egui_overlay::EguiOverlay;
 use std::collections::HashMap;
 use tray_icon::{
     TrayIcon, TrayIconBuilder, menu::AboutMetadata, menu::MenuItem, menu::PredefinedMenuItem,
+    menu::Submenu,
 };
 
 #[cfg(feature = "three_d")]
@@ -44,6 +46,9 @@ pub async fn run(
 
     active_window: Arc<Mutex<ActiveWindow>>,
     shortcut_window: Arc<Mutex<bool>>,
+    generating: Arc<Mutex<bool>>,
+    cancel_generation: Arc<Mutex<bool>>,
+    api_port: Option<u16>,
     #[cfg(feature = "computeruse_record")] usecase_recorder: Arc<Mutex<UseCaseRecorder>>,
     #[cfg(feature = "computeruse_replay")] usecase_replay: Arc<Mutex<UseCaseReplay>>,
     #[cfg(feature = "computeruse_editor")] usecase_editor: Arc<Mutex<UsecaseEditor>>,
@@ -65,6 +70,9 @@ pub async fn run(
         ai_context,
         active_window,
         shortcut_window,
+        generating,
+        cancel_generation,
+        api_port,
         #[cfg(feature = "computeruse_record")]
         usecase_recorder,
         #[cfg(feature = "computeruse_replay")]
@@ -80,6 +88,7 @@ enum MenuUpdate {
     UpdateWelcome(String),
     UpdateLogin(String, bool),
     UpdateUpdater(String, bool),
+    UpdateProfiles(Vec<String>),
 }
 pub struct PlugOvr {
     pub text_entry: Arc<Mutex<bool>>,
@@ -106,6 +115,13 @@ pub struct PlugOvr {
     menu_update_sender: Arc<Mutex<Option<Sender<MenuUpdate>>>>,
     last_login_state: Option<bool>,
     last_loading_state: Option<bool>,
+    /// The settings profile the model, templates and servers are from.
+    applied_profile: String,
+    /// `settings::revision` the windows and the tray were updated to.
+    settings_revision: Option<u64>,
+    profile_names: Vec<String>,
+    #[cfg(not(target_os = "linux"))]
+    profile_menu: Submenu,
     #[cfg(feature = "computeruse_record")]
     pub usecase_recorder: Arc<Mutex<UseCaseRecorder>>,
     #[cfg(feature = "computeruse_replay")]
@@ -124,6 +140,9 @@ impl PlugOvr {
 
         active_window: Arc<Mutex<ActiveWindow>>,
         shortcut_window: Arc<Mutex<bool>>,
+        generating: Arc<Mutex<bool>>,
+        cancel_generation: Arc<Mutex<bool>>,
+        api_port: Option<u16>,
         #[cfg(feature = "computeruse_record")] usecase_recorder: Arc<Mutex<UseCaseRecorder>>,
         #[cfg(feature = "computeruse_replay")] usecase_replay: Arc<Mutex<UseCaseReplay>>,
         #[cfg(feature = "computeruse_editor")] usecase_editor: Arc<Mutex<UsecaseEditor>>,
@@ -201,6 +220,14 @@ impl PlugOvr {
             usecase_replay.lock().unwrap().llm_selector = Some(llm_selector.clone());
         }
 
+        if let Some(port) = api_port {
+            tokio::spawn(crate::assist_api::start_server(
+                llm_selector.clone(),
+                prompt_templates.clone(),
+                port,
+            ));
+        }
+
         let assistance_window = AssistanceWindow::new(
             active_window.clone(),
             text_entry.clone(),
@@ -212,6 +239,8 @@ impl PlugOvr {
             screen_width,
             screen_height,
             llm_selector.clone(),
+            generating,
+            cancel_generation,
         );
         let mut plug_ovr = Self {
             text_entry,
@@ -232,7 +261,7 @@ impl PlugOvr {
             llm_selector: llm_selector.clone(),
 
             // Add this field to the PlugOvr struct
-            template_editor: TemplateEditor::new(prompt_templates.clone()),
+            template_editor: TemplateEditor::new(prompt_templates.clone(), llm_selector.clone()),
             main_window: MainWindow::new(
                 user_info.clone(),
                 is_loading_user_info.clone(),
@@ -250,6 +279,11 @@ impl PlugOvr {
             menu_update_sender: Arc::new(Mutex::new(None)),
             last_login_state: None,
             last_loading_state: None,
+            applied_profile: settings::load().active_profile,
+            settings_revision: None,
+            profile_names: vec![],
+            #[cfg(not(target_os = "linux"))]
+            profile_menu: Submenu::new("Profile", true),
             #[cfg(feature = "computeruse_record")]
             usecase_recorder: usecase_recorder.clone(),
             #[cfg(feature = "computeruse_replay")]
@@ -264,6 +298,56 @@ impl PlugOvr {
 
         plug_ovr
     }
+
+    /// Takes over a profile that was switched to in the tray, the profiles
+    /// window or the model selection.
+    fn apply_profile(&mut self, settings: &Settings) {
+        let profile = settings.profile();
+        self.llm_selector
+            .lock()
+            .expect("Failed to lock llm_selector POISON")
+            .apply_profile(&settings.active_profile, &profile);
+        *self.prompt_templates.lock().unwrap() = with_team_templates(
+            profile
+                .templates
+                .clone()
+                .unwrap_or_else(create_prompt_templates),
+        );
+        #[cfg(feature = "computeruse_replay")]
+        {
+            let mut usecase_replay = self.usecase_replay.lock().unwrap();
+            usecase_replay.server_url_planning = profile.server_url_planning.clone();
+            usecase_replay.server_url_execution = profile.server_url_execution.clone();
+        }
+        self.applied_profile = settings.active_profile.clone();
+    }
+
+    /// Follows changed settings, a switched profile or a profile that was
+    /// added or deleted.
+    fn apply_settings(&mut self) {
+        let settings = settings::load();
+        if settings.active_profile != self.applied_profile {
+            self.apply_profile(&settings);
+        }
+        let profile_names = settings.profiles.keys().cloned().collect::<Vec<_>>();
+        if profile_names == self.profile_names {
+            return;
+        }
+        #[cfg(not(target_os = "linux"))]
+        set_profile_items(
+            &self.profile_menu,
+            &profile_names,
+            &self.main_window.menu_map,
+        );
+        if let Some(sender) = self.menu_update_sender.lock().unwrap().as_ref() {
+            _ = sender.send(MenuUpdate::UpdateProfiles(profile_names.clone()));
+        }
+        self.llm_selector
+            .lock()
+            .expect("Failed to lock llm_selector POISON")
+            .set_profile_names(profile_names.clone());
+        self.profile_names = profile_names;
+    }
 }
 fn load_icon_from_memory(icon_data: &[u8]) -> tray_icon::Icon {
     let (icon_rgba, icon_width, icon_height) = {
@@ -296,8 +380,13 @@ fn run_once_tray_icon_init(
         let login_menu_item = MenuItem::new("Login", true, None);
         let welcome_menu_item = MenuItem::new("Welcome", false, None);
         let updater_menu_item = MenuItem::new("Updater", false, None);
-        let (tray_menu, icon, map_temp) =
-            create_menu(&login_menu_item, &welcome_menu_item, &updater_menu_item);
+        let profile_menu = Submenu::new("Profile", true);
+        let (tray_menu, icon, map_temp) = create_menu(
+            &login_menu_item,
+            &welcome_menu_item,
+            &updater_menu_item,
+            &profile_menu,
+        );
         *hashmap_clone.lock().unwrap() = Some(map_temp);
         //  gtk::init().unwrap();
         let tray_icon = Some(
@@ -322,6 +411,9 @@ fn run_once_tray_icon_init(
                         updater_menu_item.set_text(text);
                         updater_menu_item.set_enabled(enabled);
                     }
+                    MenuUpdate::UpdateProfiles(names) => {
+                        set_profile_items(&profile_menu, &names, &hashmap_clone);
+                    }
                 }
             }
             glib::ControlFlow::Continue
@@ -337,6 +429,7 @@ fn create_menu(
     login_menu_item: &MenuItem,
     welcome_menu_item: &MenuItem,
     updater_menu_item: &MenuItem,
+    profile_menu: &Submenu,
 ) -> (Menu, Icon, HashMap<String, String>) {
     let tray_menu = Menu::new();
     let icon_data = include_bytes!("../../assets/32x32.png");
@@ -352,6 +445,10 @@ fn create_menu(
     #[cfg(feature = "computeruse_editor")]
     let usecase_editor_i = MenuItem::new("Usecase Editor", true, None);
     let llm_selector_i = MenuItem::new("LLM Selector", true, None);
+    let statistics_i = MenuItem::new("Statistics", true, None);
+    // The profiles are added above it by `set_profile_items`.
+    let profiles_i = MenuItem::new("Manage profiles", true, None);
+    _ = profile_menu.append_items(&[&PredefinedMenuItem::separator(), &profiles_i]);
     let quit_i = MenuItem::new("Quit", true, None);
     let about_icon = tray_icon::menu::Icon::from_rgba(icon_data_menu, 32, 32).unwrap();
     #[cfg(feature = "cs")]
@@ -363,6 +460,8 @@ fn create_menu(
         &llm_selector_i,
 
         &template_i,
+        &statistics_i,
+        profile_menu,
         #[cfg(feature = "computeruse_editor")]
         &usecase_editor_i,
         &PredefinedMenuItem::separator(),
@@ -400,6 +499,8 @@ fn create_menu(
         &llm_selector_i,
 
         &template_i,
+        &statistics_i,
+        profile_menu,
         #[cfg(feature = "computeruse_editor")]
         &usecase_editor_i,
         &PredefinedMenuItem::separator(),
@@ -437,6 +538,8 @@ fn create_menu(
         llm_selector_i.id().0.to_string(),
     );
     map.insert("Template Editor".to_string(), template_i.id().0.to_string());
+    map.insert("Statistics".to_string(), statistics_i.id().0.to_string());
+    map.insert("Profiles".to_string(), profiles_i.id().0.to_string());
     map.insert("Login".to_string(), login_menu_item.id().0.to_string());
     map.insert("Quit".to_string(), quit_i.id().0.to_string());
     map.insert("Updater".to_string(), updater_menu_item.id().0.to_string());
@@ -447,17 +550,42 @@ fn create_menu(
     );
     (tray_menu, icon, map)
 }
+/// Replaces the profiles at the top of the tray's profile menu.
+fn set_profile_items(
+    profile_menu: &Submenu,
+    names: &[String],
+    menu_map: &Mutex<Option<HashMap<String, String>>>,
+) {
+    let mut menu_map = menu_map.lock().unwrap();
+    let map = menu_map.get_or_insert_with(HashMap::new);
+    let old_items = map.keys().filter(|key| key.starts_with("Profile:")).count();
+    for _ in 0..old_items {
+        _ = profile_menu.remove_at(0);
+    }
+    map.retain(|key, _| !key.starts_with("Profile:"));
+    for (position, name) in names.iter().enumerate() {
+        let item = MenuItem::new(name, true, None);
+        _ = profile_menu.insert(&item, position);
+        map.insert(format!("Profile:{}", name), item.id().0.to_string());
+    }
+}
+
 #[cfg(not(target_os = "linux"))]
 fn run_once_tray_icon_init(
     tray_icon: &mut Option<TrayIcon>,
     login_menu_item: &MenuItem,
     welcome_menu_item: &MenuItem,
     updater_menu_item: &MenuItem,
+    profile_menu: &Submenu,
     menu_map: Arc<Mutex<Option<HashMap<String, String>>>>,
 ) {
     let hashmap_clone = menu_map.clone();
-    let (tray_menu, icon, map_temp) =
-        create_menu(login_menu_item, welcome_menu_item, updater_menu_item);
+    let (tray_menu, icon, map_temp) = create_menu(
+        login_menu_item,
+        welcome_menu_item,
+        updater_menu_item,
+        profile_menu,
+    );
     *hashmap_clone.lock().unwrap() = Some(map_temp);
 
     *tray_icon = Some(
@@ -494,6 +622,7 @@ impl EguiOverlay for PlugOvr {
                     &self.login_menu_item,
                     &self.welcome_menu_item,
                     &self.updater_menu_item,
+                    &self.profile_menu,
                     self.main_window.menu_map.clone(),
                 );
                 #[cfg(target_os = "linux")]
@@ -520,6 +649,12 @@ impl EguiOverlay for PlugOvr {
         // Set the window size to cover all screens
         glfw_backend.set_window_size([self.screen_width as f32, self.screen_height as f32]);
 
+        let settings_revision = settings::revision();
+        if self.settings_revision != Some(settings_revision) {
+            self.settings_revision = Some(settings_revision);
+            self.apply_settings();
+        }
+
         self.main_window.show(egui_context);
 
         egui_context.set_visuals(egui::Visuals::light());
@@ -630,6 +765,7 @@ impl EguiOverlay for PlugOvr {
             && !self.assistance_window.shortcut_clicked
         {
             self.assistance_window.text_entry_changed = true;
+            self.assistance_window.forget_focused_app();
         }
 
         #[cfg(feature = "computeruse_record")]
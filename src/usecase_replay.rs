@@ -1,4 +1,5 @@
-use crate::llm::LLMSelector;
+use crate::llm::{LLMSelector, json_schema_response_format};
+use crate::settings;
 use crate::usecase_recorder::EventType;
 use crate::usecase_recorder::UseCase;
 use egui_overlay::egui_render_three_d::{
@@ -26,8 +27,6 @@ use serde_json::Value;
 use std::fs::File;
 
 use std::io::Cursor;
-use std::io::Read;
-use std::io::Write;
 
 use std::error::Error;
 use std::sync::{Arc, Mutex};
@@ -103,6 +102,57 @@ struct Action {
     value: String,
 }
 
+/// Schema of a plan, either shape of `StepFormat`.
+fn usecase_actions_schema() -> Value {
+    let actions = serde_json::json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "type": { "type": "string", "enum": ["Click", "InsertText", "KeyPress"] },
+                "value": { "type": "string" }
+            },
+            "required": ["type", "value"],
+            "additionalProperties": false
+        }
+    });
+    serde_json::json!({
+        "anyOf": [
+            {
+                "type": "object",
+                "properties": {
+                    "instruction": { "type": "string" },
+                    "actions": actions
+                },
+                "required": ["instruction", "actions"],
+                "additionalProperties": false
+            },
+            {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "instruction": { "type": "string" },
+                        "actions": actions
+                    },
+                    "additionalProperties": false
+                }
+            }
+        ]
+    })
+}
+
+/// Servers that support structured output return the plan as valid JSON, the
+/// repair of the answer stays for those that don't.
+fn request_plan_format(builder: &mut ChatCompletionParametersBuilder) {
+    match json_schema_response_format(&usecase_actions_schema()) {
+        Ok(response_format) => {
+            builder.response_format(response_format);
+        }
+        Err(e) => eprintln!("Failed to build response format: {}", e),
+    }
+}
+
 impl From<Action> for ActionTypes {
     fn from(action: Action) -> Self {
         match action.action_type.as_str() {
@@ -116,10 +166,9 @@ impl From<Action> for ActionTypes {
 
 impl UseCaseReplay {
     pub fn new() -> Self {
-        let server_url_planning =
-            load_server_url_planning().unwrap_or("http://127.0.0.1:8000/v1".to_string());
-        let server_url_execution =
-            load_server_url_execution().unwrap_or("http://127.0.0.1:8000/v1".to_string());
+        let profile = settings::load().profile();
+        let server_url_planning = profile.server_url_planning;
+        let server_url_execution = profile.server_url_execution;
         Self {
             index_instruction: Arc::new(Mutex::new(0)),
             index_action: Arc::new(Mutex::new(0)),
@@ -426,7 +475,8 @@ impl UseCaseReplay {
             rt.block_on(async {
                 let mut client = Client::new("".to_string());
                 client.set_base_url(&server_url_planning);
-                if let Ok(parameters) = ChatCompletionParametersBuilder::default()
+                let mut builder = ChatCompletionParametersBuilder::default();
+                builder
                     .model("Qwen/Qwen2.5-VL-7B-Instruct".to_string())
                     .messages(vec![
                         ChatMessage::System {
@@ -451,9 +501,9 @@ impl UseCaseReplay {
                         },
                     ])
                     .max_completion_tokens(1024u32)
-                    .temperature(0.0)
-                    .build()
-                {
+                    .temperature(0.0);
+                request_plan_format(&mut builder);
+                if let Ok(parameters) = builder.build() {
                     if let Ok(result) = client.chat().create(parameters).await {
                         let msg = result.choices[0].message.clone();
                         match msg {
@@ -652,7 +702,8 @@ impl UseCaseReplay {
 
                 //let system_prompt = "You are an expert in analyzing screenshots. Given an instruction and a screenshot, output the coordinates [x1, y1, x2, y2] of where to click. The coordinates should be in pixels and represent a bounding box around the target element.";
                 let system_prompt = "You are a helpful assistant";
-                if let Ok(parameters) = ChatCompletionParametersBuilder::default()
+                let mut builder = ChatCompletionParametersBuilder::default();
+                builder
                     .model("Qwen/Qwen2.5-VL-7B-Instruct".to_string())
                     .messages(vec![
                         ChatMessage::System {
@@ -680,9 +731,8 @@ impl UseCaseReplay {
                         },
                     ])
                     .max_completion_tokens(1024u32)
-                    .temperature(0.0)
-                    .build()
-                {
+                    .temperature(0.0);
+                if let Ok(parameters) = builder.build() {
                     if let Ok(result) = client.chat().create(parameters).await {
                         let msg = result.choices[0].message.clone();
                         match msg {
@@ -1282,49 +1332,13 @@ fn key_up(key: &str) {
 //     Ok(image::open(path)?)
 // }
 pub fn save_server_url_planning(server_url: &str) -> std::io::Result<()> {
-    let mut path = dirs::home_dir().expect("Unable to get home directory");
-    path.push(".plugovr");
-    std::fs::create_dir_all(&path)?;
-    path.push("server_url_planning.json");
-
-    let serialized = serde_json::to_string(&server_url)?;
-    let mut file = File::create(path)?;
-    file.write_all(serialized.as_bytes())?;
-    Ok(())
+    settings::update(|settings| settings.profile_mut().server_url_planning = server_url.to_string())
 }
 
 pub fn save_server_url_execution(server_url: &str) -> std::io::Result<()> {
-    let mut path = dirs::home_dir().expect("Unable to get home directory");
-    path.push(".plugovr");
-    std::fs::create_dir_all(&path)?;
-    path.push("server_url_execution.json");
-
-    let serialized = serde_json::to_string(&server_url)?;
-    let mut file = File::create(path)?;
-    file.write_all(serialized.as_bytes())?;
-    Ok(())
-}
-
-fn load_server_url_planning() -> std::io::Result<String> {
-    let mut path = dirs::home_dir().expect("Unable to get home directory");
-    path.push(".plugovr");
-    path.push("server_url_planning.json");
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let server_url: String = serde_json::from_str(&contents)?;
-    Ok(server_url)
-}
-
-fn load_server_url_execution() -> std::io::Result<String> {
-    let mut path = dirs::home_dir().expect("Unable to get home directory");
-    path.push(".plugovr");
-    path.push("server_url_execution.json");
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let server_url: String = serde_json::from_str(&contents)?;
-    Ok(server_url)
+    settings::update(|settings| {
+        settings.profile_mut().server_url_execution = server_url.to_string()
+    })
 }
 
 pub fn auto_execution_thread(usecase_replay: Arc<Mutex<UseCaseReplay>>) {
@@ -8,14 +8,17 @@ use x11rb::connection::Connection;
 #[cfg(target_os = "linux")]
 use x11rb::protocol::xproto::ConnectionExt;
 #[cfg(target_os = "linux")]
+#[derive(Clone, Copy, PartialEq)]
 pub struct ActiveWindow(pub u32);
 #[cfg(target_os = "macos")]
+#[derive(Clone, Copy, PartialEq)]
 pub struct ActiveWindow(pub u64);
 
 #[cfg(target_os = "windows")]
 use winapi::shared::windef::HWND;
 
 #[cfg(target_os = "windows")]
+#[derive(Clone, Copy, PartialEq)]
 pub struct ActiveWindow(pub usize);
 
 #[cfg(target_os = "linux")]
@@ -371,6 +374,129 @@ pub fn get_active_window() -> Option<ActiveWindow> {
     }
 }
 
+/// Name of the application a window belongs to, its `WM_CLASS`, e.g. `firefox`.
+#[cfg(target_os = "linux")]
+pub fn active_app_name(window: &ActiveWindow) -> Option<String> {
+    let (conn, _) = x11rb::connect(None).ok()?;
+    let class = conn
+        .get_property(
+            false,
+            window.0,
+            x11rb::protocol::xproto::AtomEnum::WM_CLASS,
+            x11rb::protocol::xproto::AtomEnum::STRING,
+            0,
+            1024,
+        )
+        .ok()?
+        .reply()
+        .ok()?;
+    // Instance and class, each terminated by a zero byte.
+    class
+        .value
+        .split(|byte| *byte == 0)
+        .filter(|part| !part.is_empty())
+        .next_back()
+        .map(|part| String::from_utf8_lossy(part).to_lowercase())
+}
+
+/// Name of the application a window belongs to, e.g. `Mail`.
+#[cfg(target_os = "macos")]
+pub fn active_app_name(window: &ActiveWindow) -> Option<String> {
+    use cocoa::base::id;
+    use objc::{msg_send, sel, sel_impl};
+
+    unsafe {
+        let shared_app: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let running_apps: id = msg_send![shared_app, runningApplications];
+        let count: usize = msg_send![running_apps, count];
+
+        for i in 0..count {
+            let app: id = msg_send![running_apps, objectAtIndex: i];
+            let pid: u64 = msg_send![app, processIdentifier];
+            if pid == window.0 {
+                let app_name: id = msg_send![app, localizedName];
+                let name = cocoa::foundation::NSString::UTF8String(app_name);
+                return Some(std::ffi::CStr::from_ptr(name).to_string_lossy().to_string());
+            }
+        }
+        None
+    }
+}
+
+/// Name of the application a window belongs to, its executable without
+/// `.exe`, e.g. `outlook`.
+#[cfg(target_os = "windows")]
+pub fn active_app_name(window: &ActiveWindow) -> Option<String> {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::OpenProcess;
+    use winapi::um::winbase::QueryFullProcessImageNameW;
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    unsafe {
+        let mut process_id = 0;
+        GetWindowThreadProcessId(window.0 as HWND, &mut process_id);
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id);
+        if process.is_null() {
+            return None;
+        }
+        let mut buffer = [0u16; 1024];
+        let mut length = buffer.len() as DWORD;
+        let ok = QueryFullProcessImageNameW(process, 0, buffer.as_mut_ptr(), &mut length);
+        CloseHandle(process);
+        if ok == 0 {
+            return None;
+        }
+        let path = String::from_utf16_lossy(&buffer[..length as usize]);
+        std::path::Path::new(&path)
+            .file_stem()
+            .map(|name| name.to_string_lossy().to_lowercase())
+    }
+}
+
+/// Title of a window, its `_NET_WM_NAME`.
+#[cfg(target_os = "linux")]
+pub fn active_window_title(window: &ActiveWindow) -> Option<String> {
+    let (conn, _) = x11rb::connect(None).ok()?;
+    let wm_name = conn
+        .intern_atom(false, b"_NET_WM_NAME")
+        .ok()?
+        .reply()
+        .ok()?
+        .atom;
+    let utf8_string = conn
+        .intern_atom(false, b"UTF8_STRING")
+        .ok()?
+        .reply()
+        .ok()?
+        .atom;
+    let title = conn
+        .get_property(false, window.0, wm_name, utf8_string, 0, 1024)
+        .ok()?
+        .reply()
+        .ok()?;
+    String::from_utf8(title.value).ok()
+}
+
+/// Title of the frontmost window if it belongs to the application.
+#[cfg(target_os = "macos")]
+pub fn active_window_title(window: &ActiveWindow) -> Option<String> {
+    active_win_pos_rs::get_active_window()
+        .ok()
+        .filter(|active_window| active_window.process_id == window.0)
+        .map(|active_window| active_window.title)
+}
+
+#[cfg(target_os = "windows")]
+pub fn active_window_title(window: &ActiveWindow) -> Option<String> {
+    use winapi::um::winuser::GetWindowTextW;
+
+    unsafe {
+        let mut buffer = [0u16; 1024];
+        let length = GetWindowTextW(window.0 as HWND, buffer.as_mut_ptr(), buffer.len() as i32);
+        (length > 0).then(|| String::from_utf16_lossy(&buffer[..length as usize]))
+    }
+}
+
 #[cfg(target_os = "windows")]
 pub fn find_window_by_title(title: &str) -> Option<usize> {
     use std::ffi::OsStr;